@@ -0,0 +1,270 @@
+//! Alarms that start playback at a time of day, for waking up to music.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{load_json, save_json};
+
+/// Days of the week as bits, Monday is the lowest
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Days(u8);
+
+impl Days {
+    pub const EVERY_DAY: Days = Days(0b111_1111);
+    pub const WEEKDAYS: Days = Days(0b001_1111);
+    pub const WEEKEND: Days = Days(0b110_0000);
+
+    /// What the alarm menu steps through, followed by every single day
+    const PRESETS: [Days; 3] = [Days::EVERY_DAY, Days::WEEKDAYS, Days::WEEKEND];
+
+    pub fn contains(self, day: Weekday) -> bool {
+        self.0 & (1 << day.num_days_from_monday()) != 0
+    }
+
+    /// The next or, for a negative `direction`, the previous of every day,
+    /// weekdays, the weekend and each single day
+    pub fn step(self, direction: i32) -> Days {
+        let choices: Vec<Days> = Self::PRESETS
+            .into_iter()
+            .chain((0..7).map(|day| Days(1 << day)))
+            .collect();
+        let len = choices.len() as i32;
+        let index = match choices.iter().position(|&days| days == self) {
+            Some(index) => (index as i32 + direction).rem_euclid(len),
+            None => 0,
+        };
+        choices[index as usize]
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            Days::EVERY_DAY => "Every day".to_string(),
+            Days::WEEKDAYS => "Mon-Fri".to_string(),
+            Days::WEEKEND => "Sat-Sun".to_string(),
+            Days(0) => "Never".to_string(),
+            _ => {
+                let names: Vec<String> = (0..7)
+                    .map(|day| Weekday::try_from(day).unwrap())
+                    .filter(|&day| self.contains(day))
+                    .map(|day| day.to_string())
+                    .collect();
+                names.join(" ")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alarm {
+    pub hour: u32,
+    pub minute: u32,
+    pub days: Days,
+    /// File or playlist to play, the queue that played last if unset
+    pub sound: Option<PathBuf>,
+    /// Percent the volume ramps up to
+    pub volume: u8,
+    pub enabled: bool,
+}
+
+impl Default for Alarm {
+    fn default() -> Self {
+        Self {
+            hour: 7,
+            minute: 0,
+            days: Days::WEEKDAYS,
+            sound: None,
+            volume: 50,
+            enabled: true,
+        }
+    }
+}
+
+impl Alarm {
+    fn time(&self) -> NaiveTime {
+        NaiveTime::from_hms_opt(self.hour, self.minute, 0).unwrap_or_default()
+    }
+
+    /// Whether the alarm goes off after `after` and no later than `until`
+    pub fn due(&self, after: NaiveDateTime, until: NaiveDateTime) -> bool {
+        if !self.enabled || until <= after {
+            return false;
+        }
+        // Only the last day counts after a long gap like a suspend, nobody
+        // wants to be woken up for yesterday
+        let date = until.date();
+        let at = date.and_time(self.time());
+        self.days.contains(date.weekday()) && after < at && at <= until
+    }
+
+    /// Adds `hours` and `minutes` to the time of day, wrapping around
+    pub fn shift(&mut self, hours: i32, minutes: i32) {
+        let total = (self.hour * 60 + self.minute) as i32 + hours * 60 + minutes;
+        let total = total.rem_euclid(24 * 60) as u32;
+        self.hour = total / 60;
+        self.minute = total % 60;
+    }
+
+    pub fn label(&self) -> String {
+        format!(
+            "{:02}:{:02} {:<9} {}",
+            self.hour,
+            self.minute,
+            self.days.label(),
+            if self.enabled { "on" } else { "off" }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Alarms(Vec<Alarm>);
+
+impl Alarms {
+    /// Reads the alarms from `path`, a missing file means there are none
+    pub fn load(path: &Path) -> Result<Self> {
+        load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_json(path, self)
+    }
+
+    pub fn list(&self) -> &[Alarm] {
+        &self.0
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Alarm> {
+        self.0.get_mut(index)
+    }
+
+    /// Adds an alarm and returns its index
+    pub fn add(&mut self, alarm: Alarm) -> usize {
+        self.0.push(alarm);
+        self.0.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.0.len() {
+            self.0.remove(index);
+        }
+    }
+
+    /// The first alarm going off after `after` and no later than `until`
+    pub fn due(&self, after: NaiveDateTime, until: NaiveDateTime) -> Option<&Alarm> {
+        self.0.iter().find(|alarm| alarm.due(after, until))
+    }
+}
+
+/// Raises the volume from almost nothing to an alarm's volume
+#[derive(Debug, Clone)]
+pub struct Ramp {
+    started: Instant,
+    to: u8,
+    duration: Duration,
+}
+
+impl Ramp {
+    pub fn new(started: Instant, to: u8, duration: Duration) -> Self {
+        Self {
+            started,
+            to,
+            duration,
+        }
+    }
+
+    /// The volume in percent at `now`, at least 1 so something is heard
+    pub fn volume(&self, now: Instant) -> u8 {
+        if self.done(now) {
+            return self.to;
+        }
+        let ramped = now.duration_since(self.started).as_secs_f64() / self.duration.as_secs_f64();
+        ((self.to as f64 * ramped) as u8).max(1)
+    }
+
+    pub fn done(&self, now: Instant) -> bool {
+        now.duration_since(self.started) >= self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // October 2026 starts on a Thursday
+        NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_alarm_due() {
+        let alarm = Alarm::default();
+        // Friday
+        assert!(alarm.due(at(16, 6, 59), at(16, 7, 0)));
+        assert!(!alarm.due(at(16, 7, 0), at(16, 7, 1)));
+        // Saturday is not a weekday
+        assert!(!alarm.due(at(17, 6, 59), at(17, 7, 0)));
+        // Missed while the clock jumped overnight, only today's counts
+        assert!(!alarm.due(at(15, 6, 0), at(16, 6, 0)));
+        assert!(alarm.due(at(15, 8, 0), at(16, 8, 0)));
+
+        let off = Alarm {
+            enabled: false,
+            ..Alarm::default()
+        };
+        assert!(!off.due(at(16, 6, 59), at(16, 7, 0)));
+    }
+
+    #[test]
+    fn test_days_and_time() {
+        assert_eq!(Days::WEEKDAYS.label(), "Mon-Fri");
+        assert_eq!(Days::EVERY_DAY.step(-1).label(), "Sun");
+        assert_eq!(Days::WEEKEND.step(1).label(), "Mon");
+        assert_eq!(Days(0b101).label(), "Mon Wed");
+        assert!(Days::WEEKEND.contains(Weekday::Sun));
+
+        let mut alarm = Alarm::default();
+        alarm.shift(-8, 0);
+        alarm.shift(0, -5);
+        assert_eq!(alarm.label(), "22:55 Mon-Fri   on");
+    }
+
+    #[test]
+    fn test_alarms_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("alarms.json");
+        let mut alarms = Alarms::load(&path).unwrap();
+        assert!(alarms.list().is_empty());
+
+        let index = alarms.add(Alarm {
+            sound: Some(PathBuf::from("/music/wake.mp3")),
+            ..Alarm::default()
+        });
+        alarms.get_mut(index).unwrap().days = Days::EVERY_DAY;
+        alarms.save(&path).unwrap();
+        assert_eq!(Alarms::load(&path).unwrap(), alarms);
+        assert!(alarms.due(at(17, 6, 59), at(17, 7, 0)).is_some());
+        alarms.remove(index);
+        assert_eq!(alarms, Alarms::default());
+    }
+
+    #[test]
+    fn test_ramp() {
+        let start = Instant::now();
+        let ramp = Ramp::new(start, 60, Duration::from_secs(60));
+        assert_eq!(ramp.volume(start), 1);
+        assert_eq!(ramp.volume(start + Duration::from_secs(30)), 30);
+        assert!(!ramp.done(start + Duration::from_secs(59)));
+        assert_eq!(ramp.volume(start + Duration::from_secs(90)), 60);
+    }
+}
@@ -0,0 +1,500 @@
+//! Sends audio to a Bluetooth device once it connects and back to the
+//! built-in output when it disconnects, through `pactl` like the volume.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use macaddr::MacAddr6;
+use tokio::{
+    process::Command,
+    sync::mpsc::{Sender, UnboundedReceiver},
+    time::Instant,
+};
+use tracing::{debug, error, info};
+
+use crate::bluetooth::BluetoothEvent;
+
+/// How long a connected device has to show up as a sink
+const SINK_TIMEOUT: Duration = Duration::from_secs(10);
+const SINK_POLL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioEvent {
+    /// Audio is played through this output now, named for the screen
+    Output(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sink {
+    pub id: u32,
+    pub name: String,
+}
+
+/// How the router talks to the sound server
+#[async_trait]
+pub trait Sinks: Send + std::fmt::Debug {
+    async fn default_sink(&mut self) -> Result<String>;
+    async fn list(&mut self) -> Result<Vec<Sink>>;
+    /// Makes `sink` the default and moves what is playing, like mpv, to it
+    async fn route(&mut self, sink: &str) -> Result<()>;
+}
+
+/// PulseAudio or PipeWire through `pactl`, like the volume
+#[derive(Debug)]
+pub struct Pactl;
+
+#[async_trait]
+impl Sinks for Pactl {
+    async fn default_sink(&mut self) -> Result<String> {
+        Ok(pactl(&["get-default-sink"]).await?.trim().to_string())
+    }
+
+    async fn list(&mut self) -> Result<Vec<Sink>> {
+        Ok(parse_sinks(&pactl(&["list", "short", "sinks"]).await?))
+    }
+
+    async fn route(&mut self, sink: &str) -> Result<()> {
+        pactl(&["set-default-sink", sink]).await?;
+        let inputs = pactl(&["list", "short", "sink-inputs"]).await?;
+        for input in parse_sink_inputs(&inputs) {
+            debug!("Moving sink input {} to {}", input, sink);
+            pactl(&["move-sink-input", &input.to_string(), sink]).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct AudioRouter {
+    sinks: Box<dyn Sinks>,
+    events: UnboundedReceiver<BluetoothEvent>,
+    channel: Sender<AudioEvent>,
+    /// Sink used without a Bluetooth device, the first other one if unset
+    fallback: Option<String>,
+    /// Connected devices with a sink and their names, the last one to get
+    /// its sink last
+    connected: Vec<(MacAddr6, String)>,
+    /// Connected devices whose sink hasn't shown up yet and when to give up
+    /// on them, devices that don't play audio never get one
+    waiting: Vec<(MacAddr6, String, Instant)>,
+    /// When to look for the sinks of `waiting` again
+    poll_at: Option<Instant>,
+}
+
+impl AudioRouter {
+    pub fn new(
+        sinks: Box<dyn Sinks>,
+        events: UnboundedReceiver<BluetoothEvent>,
+        channel: Sender<AudioEvent>,
+        fallback: Option<String>,
+    ) -> Self {
+        Self {
+            sinks,
+            events,
+            channel,
+            fallback,
+            connected: Vec::new(),
+            waiting: Vec::new(),
+            poll_at: None,
+        }
+    }
+
+    /// Follows the Bluetooth devices until the event channel closes
+    pub async fn run(&mut self) -> Result<()> {
+        match self.sinks.default_sink().await {
+            Ok(sink) => {
+                let event = AudioEvent::Output(output_label(&sink));
+                self.channel.send(event).await?;
+            }
+            Err(e) => error!("Failed to get the default sink: {}", e),
+        }
+        loop {
+            let result = tokio::select! {
+                event = self.events.recv() => match event {
+                    Some(event) => self.handle_bluetooth_event(event).await,
+                    None => break,
+                },
+                _ = wait_until(self.poll_at) => self.find_sinks().await,
+            };
+            if let Err(e) = result {
+                error!("Failed to route audio: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_bluetooth_event(&mut self, event: BluetoothEvent) -> Result<()> {
+        let (addr, name, connected) = match event {
+            BluetoothEvent::DeviceFound(device) => (device.addr, device.name, device.connected),
+            BluetoothEvent::DeviceUpdated {
+                addr,
+                name,
+                connected,
+                ..
+            } => (addr, name, connected),
+            BluetoothEvent::DeviceLost(addr) => (addr, String::new(), false),
+            _ => return Ok(()),
+        };
+        let known = self.connected.iter().position(|(known, _)| *known == addr);
+        let waiting = self.waiting.iter().position(|(known, ..)| *known == addr);
+        match (connected, known, waiting) {
+            (true, None, None) => {
+                self.waiting
+                    .push((addr, name, Instant::now() + SINK_TIMEOUT));
+                self.find_sinks().await
+            }
+            (false, None, Some(i)) => {
+                self.waiting.remove(i);
+                Ok(())
+            }
+            (false, Some(i), _) => {
+                self.connected.remove(i);
+                self.route_back().await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends audio to the waiting device whose sink showed up last, gives up
+    /// on the ones that took too long
+    async fn find_sinks(&mut self) -> Result<()> {
+        // Tries again later if listing the sinks fails
+        self.poll_at = (!self.waiting.is_empty()).then(|| Instant::now() + SINK_POLL);
+        if self.waiting.is_empty() {
+            return Ok(());
+        }
+        let sinks = self.sinks.list().await?;
+        let now = Instant::now();
+        let mut found = None;
+        for (addr, name, deadline) in std::mem::take(&mut self.waiting) {
+            if let Some(sink) = bluez_sink(&sinks, addr) {
+                self.connected.push((addr, name.clone()));
+                found = Some((sink.name.clone(), name));
+            } else if now >= deadline {
+                info!("{} has no audio sink", addr);
+            } else {
+                self.waiting.push((addr, name, deadline));
+            }
+        }
+        self.poll_at = (!self.waiting.is_empty()).then(|| now + SINK_POLL);
+        match found {
+            Some((sink, name)) => self.route(&sink, &name).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Goes back to the device that connected before, or the fallback sink
+    async fn route_back(&mut self) -> Result<()> {
+        let sinks = self.sinks.list().await?;
+        let device = self.connected.iter().rev().find_map(|(addr, name)| {
+            bluez_sink(&sinks, *addr).map(|sink| (sink.name.clone(), name.clone()))
+        });
+        if let Some((sink, name)) = device {
+            return self.route(&sink, &name).await;
+        }
+        let sink = fallback_sink(&sinks, self.fallback.as_deref())
+            .ok_or_else(|| anyhow!("There is no sink to fall back to"))?;
+        self.route(&sink.name, &output_label(&sink.name)).await
+    }
+
+    async fn route(&mut self, sink: &str, label: &str) -> Result<()> {
+        info!("Playing audio through {}", sink);
+        self.sinks.route(sink).await?;
+        let name = if label.is_empty() { "Bluetooth" } else { label };
+        self.channel
+            .send(AudioEvent::Output(name.to_string()))
+            .await?;
+        Ok(())
+    }
+}
+
+async fn wait_until(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Runs `pactl` and returns what it printed, fails if it does
+async fn pactl(args: &[&str]) -> Result<String> {
+    let output = Command::new("pactl").args(args).output().await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "pactl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Reads `pactl list short sinks`, one "id name driver format state" per line
+pub fn parse_sinks(output: &str) -> Vec<Sink> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let id = fields.next()?.trim().parse().ok()?;
+            let name = fields.next()?.to_string();
+            Some(Sink { id, name })
+        })
+        .collect()
+}
+
+/// Reads the ids of `pactl list short sink-inputs`
+pub fn parse_sink_inputs(output: &str) -> Vec<u32> {
+    output
+        .lines()
+        .filter_map(|line| line.split('\t').next()?.trim().parse().ok())
+        .collect()
+}
+
+/// The device's sink, `bluez_output.AA_BB_CC_DD_EE_FF.1` with PipeWire and
+/// `bluez_sink.AA_BB_CC_DD_EE_FF.a2dp_sink` with PulseAudio
+pub fn bluez_sink(sinks: &[Sink], addr: MacAddr6) -> Option<&Sink> {
+    let addr = addr.to_string().replace(':', "_");
+    sinks
+        .iter()
+        .find(|sink| sink.name.starts_with("bluez_") && sink.name.contains(&addr))
+}
+
+/// The configured sink if it's there, otherwise the first one that isn't a
+/// Bluetooth device
+pub fn fallback_sink<'a>(sinks: &'a [Sink], configured: Option<&str>) -> Option<&'a Sink> {
+    configured
+        .and_then(|name| sinks.iter().find(|sink| sink.name == name))
+        .or_else(|| sinks.iter().find(|sink| !sink.name.starts_with("bluez_")))
+}
+
+/// A short name for a sink that isn't a known device
+pub fn output_label(sink: &str) -> String {
+    let lower = sink.to_lowercase();
+    if lower.starts_with("bluez_") {
+        "Bluetooth".to_string()
+    } else if lower.contains("hdmi") {
+        "HDMI".to_string()
+    } else if ["analog", "headphones", "bcm2835"]
+        .iter()
+        .any(|name| lower.contains(name))
+    {
+        "Analog".to_string()
+    } else {
+        sink.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+
+    const SPEAKER: &str = "AA:BB:CC:DD:EE:FF";
+    const HEADPHONES: &str = "00:11:22:33:44:55";
+    const ANALOG: &str = "alsa_output.platform-bcm2835_audio.stereo-fallback";
+    const HDMI: &str = "alsa_output.platform-fef00700.hdmi.hdmi-stereo";
+
+    /// Sinks that are there and the ones audio was sent to
+    #[derive(Debug, Clone, Default)]
+    struct FakeSinks {
+        sinks: Arc<Mutex<Vec<Sink>>>,
+        routed: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FakeSinks {
+        fn add(&self, name: &str) {
+            let mut sinks = self.sinks.lock().unwrap();
+            let id = sinks.len() as u32;
+            sinks.push(Sink {
+                id,
+                name: name.to_string(),
+            });
+        }
+
+        fn routed(&self) -> Vec<String> {
+            std::mem::take(&mut self.routed.lock().unwrap())
+        }
+    }
+
+    #[async_trait]
+    impl Sinks for FakeSinks {
+        async fn default_sink(&mut self) -> Result<String> {
+            Ok(ANALOG.to_string())
+        }
+
+        async fn list(&mut self) -> Result<Vec<Sink>> {
+            Ok(self.sinks.lock().unwrap().clone())
+        }
+
+        async fn route(&mut self, sink: &str) -> Result<()> {
+            self.routed.lock().unwrap().push(sink.to_string());
+            Ok(())
+        }
+    }
+
+    fn router(
+        sinks: &FakeSinks,
+        fallback: Option<&str>,
+    ) -> (
+        AudioRouter,
+        mpsc::UnboundedSender<BluetoothEvent>,
+        mpsc::Receiver<AudioEvent>,
+    ) {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let (tx, rx) = mpsc::channel(10);
+        let router = AudioRouter::new(
+            Box::new(sinks.clone()),
+            event_rx,
+            tx,
+            fallback.map(str::to_string),
+        );
+        (router, event_tx, rx)
+    }
+
+    fn device(addr: &str, name: &str, connected: bool) -> BluetoothEvent {
+        BluetoothEvent::DeviceUpdated {
+            addr: addr.parse().unwrap(),
+            name: name.to_string(),
+            connected,
+            paired: true,
+            trusted: true,
+            rssi: None,
+        }
+    }
+
+    fn output(name: &str) -> Option<AudioEvent> {
+        Some(AudioEvent::Output(name.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_routing() {
+        let sinks = FakeSinks::default();
+        for name in [
+            ANALOG,
+            HDMI,
+            "bluez_output.AA_BB_CC_DD_EE_FF.1",
+            "bluez_output.00_11_22_33_44_55.1",
+        ] {
+            sinks.add(name);
+        }
+        let (mut router, _events, mut outputs) = router(&sinks, Some(HDMI));
+
+        for event in [
+            device(SPEAKER, "Speaker", true),
+            // Only changes in the connection matter
+            device(SPEAKER, "Speaker", true),
+            device(HEADPHONES, "Headphones", true),
+        ] {
+            router.handle_bluetooth_event(event).await.unwrap();
+        }
+        assert_eq!(
+            sinks.routed(),
+            [
+                "bluez_output.AA_BB_CC_DD_EE_FF.1",
+                "bluez_output.00_11_22_33_44_55.1"
+            ]
+        );
+        assert_eq!(outputs.recv().await, output("Speaker"));
+        assert_eq!(outputs.recv().await, output("Headphones"));
+
+        // Back to the device that connected before, then the configured sink
+        router
+            .handle_bluetooth_event(device(HEADPHONES, "Headphones", false))
+            .await
+            .unwrap();
+        assert_eq!(sinks.routed(), ["bluez_output.AA_BB_CC_DD_EE_FF.1"]);
+        assert_eq!(outputs.recv().await, output("Speaker"));
+        let lost = BluetoothEvent::DeviceLost(SPEAKER.parse().unwrap());
+        router.handle_bluetooth_event(lost).await.unwrap();
+        assert_eq!(sinks.routed(), [HDMI]);
+        assert_eq!(outputs.recv().await, output("HDMI"));
+
+        router
+            .handle_bluetooth_event(device(SPEAKER, "Speaker", false))
+            .await
+            .unwrap();
+        assert!(sinks.routed().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waits_for_sinks_in_the_background() {
+        let sinks = FakeSinks::default();
+        sinks.add(ANALOG);
+        let (mut router, events, mut outputs) = router(&sinks, None);
+        let task = tokio::spawn(async move { router.run().await });
+        assert_eq!(outputs.recv().await, output("Analog"));
+
+        // The keyboard never gets a sink
+        events.send(device(SPEAKER, "Speaker", true)).unwrap();
+        events.send(device(HEADPHONES, "Keyboard", true)).unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(sinks.routed().is_empty());
+        sinks.add("bluez_output.AA_BB_CC_DD_EE_FF.1");
+        assert_eq!(outputs.recv().await, output("Speaker"));
+
+        // Handled while still waiting for the keyboard
+        events.send(device(SPEAKER, "Speaker", false)).unwrap();
+        assert_eq!(outputs.recv().await, output("Analog"));
+        tokio::time::sleep(SINK_TIMEOUT).await;
+        sinks.add("bluez_output.00_11_22_33_44_55.1");
+        tokio::time::sleep(SINK_TIMEOUT).await;
+        assert_eq!(sinks.routed(), ["bluez_output.AA_BB_CC_DD_EE_FF.1", ANALOG]);
+
+        drop(events);
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_fallback_sink() {
+        let sinks = FakeSinks::default();
+        for name in ["bluez_output.AA_BB_CC_DD_EE_FF.1", HDMI, ANALOG] {
+            sinks.add(name);
+        }
+        let (mut router, _events, mut outputs) = router(&sinks, Some("gone"));
+
+        router
+            .handle_bluetooth_event(device(SPEAKER, "", true))
+            .await
+            .unwrap();
+        assert_eq!(outputs.recv().await, output("Bluetooth"));
+        router
+            .handle_bluetooth_event(device(SPEAKER, "", false))
+            .await
+            .unwrap();
+        assert_eq!(sinks.routed(), ["bluez_output.AA_BB_CC_DD_EE_FF.1", HDMI]);
+        assert_eq!(outputs.recv().await, output("HDMI"));
+    }
+
+    const SINKS: &str = "\
+        56\talsa_output.platform-bcm2835_audio.stereo-fallback\tPipeWire\ts16le 2ch 48000Hz\tSUSPENDED\n\
+        57\talsa_output.platform-fef00700.hdmi.hdmi-stereo\tPipeWire\ts32le 2ch 48000Hz\tSUSPENDED\n\
+        81\tbluez_output.AA_BB_CC_DD_EE_FF.1\tPipeWire\ts16le 2ch 48000Hz\tRUNNING\n";
+
+    #[test]
+    fn test_sinks() {
+        let sinks = parse_sinks(SINKS);
+        assert_eq!(sinks.len(), 3);
+        assert_eq!(sinks[2].id, 81);
+
+        let speaker = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        assert_eq!(bluez_sink(&sinks, speaker).unwrap().id, 81);
+        assert!(bluez_sink(&sinks, "00:11:22:33:44:55".parse().unwrap()).is_none());
+
+        assert_eq!(fallback_sink(&sinks, None).unwrap().id, 56);
+        let hdmi = "alsa_output.platform-fef00700.hdmi.hdmi-stereo";
+        assert_eq!(fallback_sink(&sinks, Some(hdmi)).unwrap().id, 57);
+        assert_eq!(fallback_sink(&sinks, Some("missing")).unwrap().id, 56);
+        assert_eq!(output_label(&sinks[0].name), "Analog");
+        assert_eq!(output_label(hdmi), "HDMI");
+        assert_eq!(output_label(&sinks[2].name), "Bluetooth");
+    }
+
+    #[test]
+    fn test_sink_inputs() {
+        let output = "65\t81\t64\tPipeWire\tfloat32le 2ch 48000Hz\n\
+            70\t56\t69\tPipeWire\ts16le 2ch 44100Hz\n";
+        assert_eq!(parse_sink_inputs(output), [65, 70]);
+        assert!(parse_sink_inputs("").is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+//! Runs the OLED UI on a regular Linux machine. The 128x64 framebuffer is
+//! drawn in the terminal with half-block characters and the joystick and
+//! buttons are mapped to the keyboard.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use clap::Parser;
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use embedded_graphics::pixelcolor::BinaryColor;
+use oled::{
+    bluetooth::BluetoothRequest,
+    buttons::{Button, ButtonInput, Buttons},
+    config::Config,
+    display::{Display, Framebuffer, MemoryPanel},
+    joystick::{self, Joystick, JoystickInput},
+    library::{self, LibraryEvent},
+    mpv::{MpvEvent, MpvManager, MpvRequest},
+    state::State,
+};
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+/// How many frames a key press keeps its input held down. Terminals only
+/// report presses, so releases have to be faked.
+const KEY_HOLD_FRAMES: u8 = 3;
+
+#[derive(Parser)]
+#[command(about = "Run the OLED UI in a terminal")]
+struct Cli {
+    /// Directory shown in the Files tab, defaults to the configured roots
+    #[arg(long)]
+    audio_dir: Option<PathBuf>,
+    /// Config file, the display, button and joystick pins are ignored
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Where `p` saves PNG snapshots
+    #[arg(long, default_value = ".")]
+    snapshot_dir: PathBuf,
+    /// Size of one OLED pixel in the PNG snapshots
+    #[arg(long, default_value_t = 4)]
+    scale: u32,
+    /// Render a single frame to this PNG and exit without touching the terminal
+    #[arg(long)]
+    png: Option<PathBuf>,
+    #[arg(long, default_value = "oled-sim.log")]
+    log_file: PathBuf,
+}
+
+#[derive(Debug, Default)]
+struct Keys {
+    joystick: Option<(joystick::State, u8)>,
+    buttons: [u8; 3],
+}
+
+/// Keyboard backed joystick and buttons. Clones share the same key state.
+#[derive(Debug, Clone, Default)]
+struct Keyboard {
+    keys: Arc<Mutex<Keys>>,
+}
+
+impl Keyboard {
+    fn press_joystick(&self, state: joystick::State) {
+        self.keys.lock().unwrap().joystick = Some((state, KEY_HOLD_FRAMES));
+    }
+
+    fn press_button(&self, button: Button) {
+        self.keys.lock().unwrap().buttons[button_index(button)] = KEY_HOLD_FRAMES;
+    }
+
+    /// Counts down held inputs, call once per frame
+    fn tick(&self) {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some((_, frames)) = keys.joystick.as_mut() {
+            *frames -= 1;
+            if *frames == 0 {
+                keys.joystick = None;
+            }
+        }
+        for frames in keys.buttons.iter_mut() {
+            *frames = frames.saturating_sub(1);
+        }
+    }
+}
+
+fn button_index(button: Button) -> usize {
+    match button {
+        Button::B1 => 0,
+        Button::B2 => 1,
+        Button::B3 => 2,
+    }
+}
+
+impl JoystickInput for Keyboard {
+    fn read(&mut self) -> Result<joystick::State> {
+        Ok(match self.keys.lock().unwrap().joystick {
+            Some((state, _)) => state,
+            None => joystick::State::Neutral,
+        })
+    }
+}
+
+impl ButtonInput for Keyboard {
+    fn is_down(&mut self, button: Button) -> Result<bool> {
+        Ok(self.keys.lock().unwrap().buttons[button_index(button)] > 0)
+    }
+}
+
+/// Restores the terminal even if the simulator bails out with an error
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn draw_terminal(out: &mut impl Write, frame: &Framebuffer) -> io::Result<()> {
+    let border = "─".repeat(frame.width as usize);
+    queue!(out, MoveTo(0, 0), Print(format!("┌{}┐\r\n", border)))?;
+    for y in (0..frame.height).step_by(2) {
+        let line: String = (0..frame.width)
+            .map(|x| match (frame.pixel(x, y), frame.pixel(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            })
+            .collect();
+        queue!(out, Print(format!("│{}│\r\n", line)))?;
+    }
+    queue!(
+        out,
+        Print(format!("└{}┘\r\n", border)),
+        Print("arrows: joystick  enter: click  1/2/3: B1/B2/B3  shift: hold B2  p: snapshot  q: quit\r\n"),
+    )?;
+    out.flush()
+}
+
+fn write_png(path: &Path, frame: &Framebuffer, scale: u32) -> Result<()> {
+    let width = frame.width as u32 * scale;
+    let height = frame.height as u32 * scale;
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut data = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let on = frame.pixel((x / scale) as i32, (y / scale) as i32);
+            data.push(if on { 0xFF } else { 0x00 });
+        }
+    }
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(())
+}
+
+/// Applies a key press, returns false when the simulator should quit
+fn handle_key(keyboard: &Keyboard, code: KeyCode, modifiers: KeyModifiers) -> bool {
+    // Shift stands in for holding B2, terminals can't report two held keys
+    let is_joystick = matches!(
+        code,
+        KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::Enter
+    );
+    if is_joystick && modifiers.contains(KeyModifiers::SHIFT) {
+        keyboard.press_button(Button::B2);
+    }
+    match code {
+        KeyCode::Up => keyboard.press_joystick(joystick::State::Up),
+        KeyCode::Down => keyboard.press_joystick(joystick::State::Down),
+        KeyCode::Left => keyboard.press_joystick(joystick::State::Left),
+        KeyCode::Right => keyboard.press_joystick(joystick::State::Right),
+        KeyCode::Enter => keyboard.press_joystick(joystick::State::Click),
+        KeyCode::Char('1') => keyboard.press_button(Button::B1),
+        KeyCode::Char('2') => keyboard.press_button(Button::B2),
+        KeyCode::Char('3') => keyboard.press_button(Button::B3),
+        KeyCode::Char('q') | KeyCode::Esc => return false,
+        KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return false,
+        _ => {}
+    }
+    true
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+    let mut config = Config::find(cli.config.as_deref())?;
+    if let Some(audio_dir) = &cli.audio_dir {
+        config.audio.roots = vec![audio_dir.clone()];
+    }
+    config.validate()?;
+    let timings = config.timings.clone();
+
+    let log_file = File::create(&cli.log_file)?;
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new("oled=debug"))
+        .with_writer(Mutex::new(log_file))
+        .with_ansi(false)
+        .init();
+
+    let (bt_tx, mut bt_rx) = tokio::sync::mpsc::channel::<BluetoothRequest>(10);
+    let (mpv_tx, mpv_rx) = tokio::sync::mpsc::channel::<MpvRequest>(10);
+    let (mpv_event_tx, mut mpv_event_rx) = tokio::sync::mpsc::channel::<MpvEvent>(10);
+    let (library_tx, mut library_rx) = tokio::sync::mpsc::channel::<LibraryEvent>(1);
+
+    let panel = MemoryPanel::new(config.display.width, config.display.height);
+    let keyboard = Keyboard::default();
+    let display = Display::new(
+        config.display.width,
+        config.display.height,
+        Box::new(panel.clone()),
+    )?;
+    let joystick = Joystick::new(Box::new(keyboard.clone()));
+    let buttons = Buttons::new(Box::new(keyboard.clone()));
+    let mut state = State::new(display, joystick, buttons, &config, bt_tx, mpv_tx)?;
+
+    if let Some(path) = cli.png {
+        state.display.fill(BinaryColor::Off);
+        state.draw();
+        state.display.render()?;
+        return write_png(&path, &panel.snapshot(), cli.scale);
+    }
+
+    // There is no Bluetooth stack to talk to, the requests are only logged
+    let bluetooth_task = tokio::spawn(async move {
+        while let Some(request) = bt_rx.recv().await {
+            info!("Ignoring {:?} in the simulator", request);
+        }
+    });
+
+    let library_task = library::spawn_indexer(
+        config.audio.roots.clone(),
+        config.storage.library_cache_path(),
+        Duration::from_millis(config.timings.library_debounce_ms),
+        library_tx,
+    );
+
+    let mpv_task = tokio::spawn(async move {
+        let mut mpv_manager = MpvManager::new(
+            mpv_event_tx,
+            mpv_rx,
+            config.mpv.socket_path,
+            &config.storage,
+        )
+        .await?;
+        mpv_manager.run().await
+    });
+
+    let guard = TerminalGuard::new()?;
+    let mut stdout = io::stdout();
+    let mut last_frame: Option<Framebuffer> = None;
+    let mut snapshot_counter = 0u32;
+    'outer: while state.running {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Release {
+                    continue;
+                }
+                if key.code == KeyCode::Char('p') {
+                    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                    let path = cli
+                        .snapshot_dir
+                        .join(format!("oled-{}-{}.png", timestamp, snapshot_counter));
+                    snapshot_counter += 1;
+                    write_png(&path, &panel.snapshot(), cli.scale)?;
+                    info!("Saved snapshot to {:?}", path);
+                } else if !handle_key(&keyboard, key.code, key.modifiers) {
+                    break 'outer;
+                }
+            }
+        }
+
+        while let Ok(event) = mpv_event_rx.try_recv() {
+            state.handle_mpv_event(event);
+        }
+
+        while let Ok(event) = library_rx.try_recv() {
+            state.handle_library_event(event);
+        }
+
+        state.display.fill(BinaryColor::Off);
+        state.update().await?;
+        state.draw();
+        state.display.render()?;
+
+        let frame = panel.snapshot();
+        if last_frame.as_ref() != Some(&frame) {
+            draw_terminal(&mut stdout, &frame)?;
+            last_frame = Some(frame);
+        }
+
+        keyboard.tick();
+        tokio::time::sleep(Duration::from_millis(timings.frame_ms)).await;
+    }
+
+    drop(guard);
+    bluetooth_task.abort();
+    mpv_task.abort();
+    library_task.abort();
+    Ok(())
+}
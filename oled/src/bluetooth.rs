@@ -1,9 +1,27 @@
-use std::{hash::Hash, process::Stdio};
+use std::{
+    hash::Hash,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use macaddr::MacAddr6;
-use tokio::process::{Child, Command};
-use tracing::{debug, info};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc::Sender, time::Instant};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    config::{BluetoothConfig, StorageConfig},
+    storage::{load_json, save_json},
+};
+
+pub mod agent;
+pub mod bluetoothctl;
+pub mod bluez;
+
+use bluetoothctl::Bluetoothctl;
+use bluez::BlueZ;
 
 #[allow(dead_code)]
 #[derive(Debug, Eq, Clone)]
@@ -13,6 +31,39 @@ pub struct Device {
     pub paired: bool,
     pub trusted: bool,
     pub connected: bool,
+    /// Signal strength in dBm, only known while scanning
+    pub rssi: Option<i16>,
+}
+
+impl Device {
+    /// The event telling that the device changed to how it is now
+    pub fn updated(&self) -> BluetoothEvent {
+        BluetoothEvent::DeviceUpdated {
+            addr: self.addr,
+            name: self.name.clone(),
+            connected: self.connected,
+            paired: self.paired,
+            trusted: self.trusted,
+            rssi: self.rssi,
+        }
+    }
+
+    /// Whether anything but the address differs, unlike `==`
+    fn differs(&self, other: &Device) -> bool {
+        (
+            &self.name,
+            self.paired,
+            self.trusted,
+            self.connected,
+            self.rssi,
+        ) != (
+            &other.name,
+            other.paired,
+            other.trusted,
+            other.connected,
+            other.rssi,
+        )
+    }
 }
 
 impl Hash for Device {
@@ -36,6 +87,7 @@ impl From<ScanResult> for Device {
             paired: false,
             trusted: false,
             connected: false,
+            rssi: None,
         }
     }
 }
@@ -51,7 +103,7 @@ impl TryFrom<&str> for ScanResult {
 
     fn try_from(s: &str) -> std::result::Result<Self, Self::Error> {
         // Device 00:11:22:33:44:55 My Device
-        let mut split = s.trim().split_whitespace();
+        let mut split = s.split_whitespace();
         let _ = split.next().unwrap();
         let addr_str = split.next().unwrap();
         let mut name = split.collect::<Vec<&str>>().join(" ");
@@ -64,249 +116,523 @@ impl TryFrom<&str> for ScanResult {
     }
 }
 
-#[derive(Debug, Clone)]
+/// The devices that were connected before, the last one first, so they can
+/// be connected again without picking them on the Bluetooth tab
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KnownDevices {
+    pub devices: Vec<String>,
+    /// Older versions only remembered the last device
+    #[serde(skip_serializing)]
+    addr: Option<String>,
+}
+
+impl KnownDevices {
+    /// Reads the devices from `path`, a missing file means there are none
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut known: Self = load_json(path)?;
+        if let Some(addr) = known.addr.take() {
+            if !known.devices.contains(&addr) {
+                known.devices.insert(0, addr);
+            }
+        }
+        Ok(known)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_json(path, self)
+    }
+
+    /// Moves the device to the front
+    pub fn connected(&mut self, addr: MacAddr6) {
+        self.forget(addr);
+        self.devices.insert(0, addr.to_string());
+    }
+
+    pub fn forget(&mut self, addr: MacAddr6) {
+        let addr = addr.to_string();
+        self.devices.retain(|known| *known != addr);
+    }
+}
+
+/// The devices to connect by themselves, the configured ones in order and
+/// then those connected before, the last one first
+pub fn reconnect_order(config: &BluetoothConfig, known: &KnownDevices) -> Vec<MacAddr6> {
+    let configured = config.devices.iter().map(|device| &device.addr);
+    let mut order: Vec<MacAddr6> = Vec::new();
+    for addr in configured.chain(&known.devices) {
+        let Ok(addr) = addr.parse::<MacAddr6>() else {
+            continue;
+        };
+        let excluded = config
+            .devices
+            .iter()
+            .any(|device| !device.reconnect && device.addr.parse() == Ok(addr));
+        if !excluded && !order.contains(&addr) {
+            order.push(addr);
+        }
+    }
+    order
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum BluetoothEvent {
-    Scan(Vec<Device>),
+    DeviceFound(Device),
+    DeviceLost(MacAddr6),
+    DeviceUpdated {
+        addr: MacAddr6,
+        name: String,
+        connected: bool,
+        paired: bool,
+        trusted: bool,
+        rssi: Option<i16>,
+    },
+    ConnectFailed {
+        addr: MacAddr6,
+        reason: String,
+    },
+    /// A device being paired asks something, answered through `reply` when
+    /// there is one
+    PairingPrompt {
+        addr: MacAddr6,
+        pairing: Pairing,
+        reply: Option<PairingReply>,
+    },
+    /// The prompt for the device is no longer needed
+    PairingCancelled(MacAddr6),
+    /// Trying to connect a known device again, `None` once that stopped
+    Reconnecting {
+        attempt: Option<u32>,
+    },
+}
+
+/// What a device that is being paired asks for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pairing {
+    /// Whether the passkey matches the one the device shows
+    Confirm(String),
+    /// A passkey or PIN to type on the device
+    Display(String),
+    /// Whether to answer with this PIN
+    PinCode(String),
+    /// Whether the device may pair at all
+    Authorize,
+}
+
+/// Answers a pairing prompt, dropping it rejects
+#[derive(Debug, Clone)]
+pub struct PairingReply(Sender<bool>);
+
+impl PairingReply {
+    pub fn new(channel: Sender<bool>) -> Self {
+        Self(channel)
+    }
+
+    pub fn send(&self, accept: bool) {
+        let _ = self.0.try_send(accept);
+    }
+}
+
+impl PartialEq for PairingReply {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.same_channel(&other.0)
+    }
+}
+
+/// The events that turn the devices in `before` into those in `after`
+pub fn diff_devices(before: &[Device], after: &[Device]) -> Vec<BluetoothEvent> {
+    let mut events = Vec::new();
+    for device in after {
+        match before.iter().find(|known| known.addr == device.addr) {
+            None => events.push(BluetoothEvent::DeviceFound(device.clone())),
+            Some(known) if known.differs(device) => events.push(device.updated()),
+            Some(_) => {}
+        }
+    }
+    for known in before {
+        if !after.iter().any(|device| device.addr == known.addr) {
+            events.push(BluetoothEvent::DeviceLost(known.addr));
+        }
+    }
+    events
 }
 
+/// How long to wait before asking the backend for changes again after it
+/// failed
+const CHANGES_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// The wait before the first attempt to reconnect, doubling after that
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Clone)]
 pub enum BluetoothRequest {
     Connect(Device),
     Unpair(Device),
     StopScan,
+    /// Connects the first known device that can be connected, if there is one
+    ReconnectLast,
+}
+
+/// How the manager talks to BlueZ
+#[async_trait]
+pub trait Backend: Send + std::fmt::Debug {
+    /// Makes the adapter pairable, called once at startup. Questions about
+    /// pairing are sent to `events`.
+    async fn setup(&mut self, events: Sender<BluetoothEvent>) -> Result<()>;
+    async fn start_discovery(&mut self) -> Result<()>;
+    async fn stop_discovery(&mut self) -> Result<()>;
+    /// Waits until devices are found, lost or change. The first call reports
+    /// every device that is known already.
+    async fn changes(&mut self) -> Result<Vec<BluetoothEvent>>;
+    async fn pair(&mut self, addr: MacAddr6) -> Result<()>;
+    async fn trust(&mut self, addr: MacAddr6) -> Result<()>;
+    async fn connect(&mut self, addr: MacAddr6) -> Result<()>;
+    async fn disconnect(&mut self, addr: MacAddr6) -> Result<()>;
+    /// Unpairs the device and forgets about it
+    async fn remove(&mut self, addr: MacAddr6) -> Result<()>;
+}
+
+/// BlueZ over D-Bus, or bluetoothctl polled every `poll` if the system bus
+/// or BlueZ can't be reached
+pub async fn backend(poll: Duration) -> Box<dyn Backend> {
+    match BlueZ::system().await {
+        Ok(bluez) => {
+            info!("Using BlueZ over D-Bus");
+            Box::new(bluez)
+        }
+        Err(e) => {
+            warn!(
+                "BlueZ is not reachable over D-Bus, using bluetoothctl: {}",
+                e
+            );
+            Box::new(Bluetoothctl::new(poll))
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct BluetoothManager {
+    backend: Box<dyn Backend>,
     channel: tokio::sync::mpsc::Sender<BluetoothEvent>,
     #[allow(dead_code)]
     log_channel: tokio::sync::mpsc::Sender<String>,
     request_channel: tokio::sync::mpsc::Receiver<BluetoothRequest>,
-    scan_process: Option<Child>,
+    scanning: bool,
+    config: BluetoothConfig,
+    known_path: PathBuf,
+    known: KnownDevices,
+    /// Whether the backend has listed the devices yet, before that it isn't
+    /// known whether one is connected already
+    listed: bool,
+    /// The known device that is connected
+    connected: Option<MacAddr6>,
+    /// When to try connecting a known device again
+    reconnect_at: Option<Instant>,
+    /// Attempts to reconnect since a known device was last connected
+    reconnect_attempts: u32,
 }
 
 impl BluetoothManager {
     pub async fn new(
+        mut backend: Box<dyn Backend>,
         channel: tokio::sync::mpsc::Sender<BluetoothEvent>,
         log_channel: tokio::sync::mpsc::Sender<String>,
         request_channel: tokio::sync::mpsc::Receiver<BluetoothRequest>,
+        config: &BluetoothConfig,
+        storage: &StorageConfig,
     ) -> Result<Self> {
-        Command::new("bluetoothctl")
-            .arg("agent")
-            .arg("on")
-            .output()
-            .await?;
-        Command::new("bluetoothctl")
-            .arg("pairable")
-            .arg("on")
-            .output()
-            .await?;
+        backend.setup(channel.clone()).await?;
+
+        let known_path = storage.bluetooth_path();
+        let known = KnownDevices::load(&known_path).unwrap_or_else(|e| {
+            warn!("Could not read {:?}: {}", known_path, e);
+            KnownDevices::default()
+        });
 
         Ok(Self {
+            backend,
             channel,
             log_channel,
             request_channel,
-            scan_process: None,
+            scanning: false,
+            config: config.clone(),
+            known_path,
+            known,
+            listed: false,
+            connected: None,
+            // Connect at startup once it's clear that nothing is connected
+            reconnect_at: config.reconnect.then(Instant::now),
+            reconnect_attempts: 0,
         })
     }
 
     pub async fn start_scan(&mut self) -> Result<()> {
-        debug!("Before scan");
-        if self.scan_process.is_none() {
-            Command::new("bluetoothctl")
-                .arg("discoverable")
-                .arg("on")
-                .kill_on_drop(true)
-                .spawn()?;
-            let scan_process = Command::new("bluetoothctl")
-                .arg("scan")
-                .arg("on")
-                .stdin(Stdio::null())
-                .stdout(Stdio::null())
-                .spawn()?;
-            self.scan_process = Some(scan_process);
-        }
-        debug!("After scan");
+        if !self.scanning {
+            self.backend.start_discovery().await?;
+            self.scanning = true;
+        }
         Ok(())
     }
 
     pub async fn stop_scan(&mut self) -> Result<()> {
-        if let Some(scan_process) = self.scan_process.as_mut() {
-            scan_process.kill().await?;
-            self.scan_process = None;
+        if self.scanning {
+            self.backend.stop_discovery().await?;
+            self.scanning = false;
         }
         Ok(())
     }
 
-    pub async fn get_devices(&mut self) -> Result<()> {
-        let all = self.devices().await?;
-        let mut devices: Vec<Device> = all.into_iter().map(|x| x.into()).collect();
-        for d in devices.iter_mut() {
-            let output = Command::new("bluetoothctl")
-                .arg("info")
-                .arg(format!("{}", d.addr))
-                .output()
-                .await?;
-            for line in String::from_utf8_lossy(&output.stdout).lines() {
-                if line.trim_start().starts_with("Paired:") {
-                    let status = line.split(": ").skip(1).next();
-                    if status == Some("yes") {
-                        d.paired = true;
-                    }
-                    if status == Some("no") {
-                        d.paired = false;
+    /// Handles requests and passes on what changed about the devices until
+    /// the request channel closes
+    pub async fn run(&mut self) -> Result<()> {
+        loop {
+            tokio::select! {
+                request = self.request_channel.recv() => {
+                    let Some(request) = request else {
+                        return Ok(());
+                    };
+                    if let Err(e) = self.process_request(request).await {
+                        error!("Error processing Bluetooth request: {}", e);
                     }
                 }
-                if line.trim_start().starts_with("Trusted:") {
-                    let status = line.split(": ").skip(1).next();
-                    if status == Some("yes") {
-                        d.trusted = true;
+                changes = self.backend.changes() => match changes {
+                    Ok(events) => {
+                        self.listed = true;
+                        for event in events {
+                            self.track(&event).await?;
+                            self.channel.send(event).await?;
+                        }
                     }
-                    if status == Some("no") {
-                        d.trusted = false;
-                    }
-                }
-                if line.trim_start().starts_with("Connected:") {
-                    let status = line.split(": ").skip(1).next();
-                    if status == Some("yes") {
-                        d.connected = true;
-                    }
-                    if status == Some("no") {
-                        d.connected = false;
+                    Err(e) => {
+                        error!("Failed to get Bluetooth devices: {}", e);
+                        tokio::time::sleep(CHANGES_RETRY_DELAY).await;
                     }
+                },
+                _ = wait_until(self.reconnect_at), if self.listed => {
+                    self.reconnect().await?;
                 }
             }
         }
+    }
+
+    /// Follows whether a known device is connected, and starts reconnecting
+    /// when it drops out
+    async fn track(&mut self, event: &BluetoothEvent) -> Result<()> {
+        let (addr, connected) = match event {
+            BluetoothEvent::DeviceFound(device) => (device.addr, device.connected),
+            BluetoothEvent::DeviceUpdated {
+                addr, connected, ..
+            } => (*addr, *connected),
+            BluetoothEvent::DeviceLost(addr) => (*addr, false),
+            _ => return Ok(()),
+        };
+        if connected && reconnect_order(&self.config, &self.known).contains(&addr) {
+            self.connected = Some(addr);
+            self.stop_reconnecting().await?;
+        } else if !connected && self.connected == Some(addr) {
+            info!("Lost the connection to {}", addr);
+            self.connected = None;
+            if self.config.reconnect {
+                self.schedule_reconnect().await?;
+            }
+        }
+        Ok(())
+    }
 
-        self.channel.send(BluetoothEvent::Scan(devices)).await?;
+    /// Tries again after a delay that doubles with every attempt
+    async fn schedule_reconnect(&mut self) -> Result<()> {
+        let max = Duration::from_secs(self.config.reconnect_max_secs);
+        let delay = (RECONNECT_DELAY * (1 << self.reconnect_attempts.min(10))).min(max);
+        debug!("Reconnecting in {:?}", delay);
+        self.reconnect_at = Some(Instant::now() + delay);
+        Ok(())
+    }
 
+    async fn stop_reconnecting(&mut self) -> Result<()> {
+        self.reconnect_at = None;
+        if self.reconnect_attempts > 0 {
+            self.reconnect_attempts = 0;
+            let event = BluetoothEvent::Reconnecting { attempt: None };
+            self.channel.send(event).await?;
+        }
         Ok(())
     }
 
-    /// Assumes that scanning is running in the background
-    async fn devices(&mut self) -> Result<Vec<ScanResult>> {
-        let output = Command::new("bluetoothctl").arg("devices").output().await?;
-        let mut results = vec![];
-        for line in String::from_utf8_lossy(&output.stdout).lines() {
-            let line = line.trim();
-            if line.is_empty() {
-                continue;
+    /// Connects the first known device that is in range, waits longer if
+    /// none is
+    async fn reconnect(&mut self) -> Result<()> {
+        self.reconnect_at = None;
+        if self.connected.is_some() {
+            return self.stop_reconnecting().await;
+        }
+        let order = reconnect_order(&self.config, &self.known);
+        if order.is_empty() {
+            return self.stop_reconnecting().await;
+        }
+        self.reconnect_attempts += 1;
+        let event = BluetoothEvent::Reconnecting {
+            attempt: Some(self.reconnect_attempts),
+        };
+        self.channel.send(event).await?;
+        match self.connect_known(&order).await {
+            Ok(()) => self.stop_reconnecting().await,
+            Err(e) => {
+                debug!("Could not reconnect: {}", e);
+                self.schedule_reconnect().await
             }
-            if let Ok(s) = ScanResult::try_from(line) {
-                results.push(s);
+        }
+    }
+
+    /// Connects the first device in `order` that can be connected
+    async fn connect_known(&mut self, order: &[MacAddr6]) -> Result<()> {
+        let mut error = anyhow!("There is no device to reconnect to");
+        for &addr in order {
+            info!("Reconnecting to {}", addr);
+            match self.backend.connect(addr).await {
+                Ok(()) => {
+                    self.connected_to(addr);
+                    // Scanning needs to be turned off when we're playing audio
+                    return self.stop_scan().await;
+                }
+                Err(e) => error = e,
             }
         }
-        Ok(results)
+        Err(error)
+    }
+
+    /// Remembers the device as the last one connected
+    fn connected_to(&mut self, addr: MacAddr6) {
+        self.connected = Some(addr);
+        self.known.connected(addr);
+        if let Err(e) = self.known.save(&self.known_path) {
+            warn!("Could not save the known devices: {}", e);
+        }
     }
 
+    /// Disconnects a connected device, otherwise pairs, trusts and connects
+    /// it. Failing to connect is logged, the device may just be out of range.
     pub async fn connect(&mut self, device: &Device) -> Result<()> {
-        info!("Inside connecting");
         if device.connected {
-            let output = Command::new("bluetoothctl")
-                .arg("disconnect")
-                .arg(device.addr.to_string())
-                .output()
-                .await?;
-            debug!("{}", String::from_utf8_lossy(&output.stdout).to_string());
             info!("Disconnecting from {:?}", device);
-            self.start_scan().await?;
-        } else {
-            if !device.paired {
-                self.pair(device.addr).await?;
+            // Dropping out on purpose, so it isn't connected again
+            if self.connected == Some(device.addr) {
+                self.connected = None;
             }
-            if !device.trusted {
-                self.trust(device.addr).await?;
+            if let Err(e) = self.backend.disconnect(device.addr).await {
+                warn!("Could not disconnect from {}: {}", device.addr, e);
             }
-            let output = Command::new("bluetoothctl")
-                .arg("connect")
-                .arg(device.addr.to_string())
-                .output()
-                .await?;
-            debug!("{}", String::from_utf8_lossy(&output.stdout).to_string());
-            info!("Connecting to {:?}", device);
-            self.stop_scan().await?;
+            self.start_scan().await?;
+            return Ok(());
         }
 
+        info!("Connecting to {:?}", device);
+        let connected = self.pair_and_connect(device).await;
+        self.stop_scan().await?;
+        match connected {
+            Ok(()) => {
+                self.connected_to(device.addr);
+                self.stop_reconnecting().await?;
+            }
+            Err(e) => {
+                warn!("Could not connect to {}: {}", device.addr, e);
+                self.connect_failed(device.addr, e).await?;
+            }
+        }
         Ok(())
     }
 
-    async fn trust(&mut self, addr: MacAddr6) -> Result<()> {
-        let output = Command::new("bluetoothctl")
-            .arg("trust")
-            .arg(addr.to_string())
-            .output()
-            .await?;
-        debug!("{}", String::from_utf8_lossy(&output.stdout).to_string());
+    async fn pair_and_connect(&mut self, device: &Device) -> Result<()> {
+        if !device.paired {
+            self.backend.pair(device.addr).await?;
+        }
+        if !device.trusted {
+            self.backend.trust(device.addr).await?;
+        }
+        self.backend.connect(device.addr).await
+    }
+
+    pub async fn reconnect_last(&mut self) -> Result<()> {
+        if self.connected.is_some() {
+            return Ok(());
+        }
+        let order = reconnect_order(&self.config, &self.known);
+        let Some(&first) = order.first() else {
+            debug!("No device to reconnect to");
+            return Ok(());
+        };
+        if let Err(e) = self.connect_known(&order).await {
+            warn!("Could not reconnect: {}", e);
+            self.connect_failed(first, e).await?;
+        }
         Ok(())
     }
 
-    async fn pair(&mut self, addr: MacAddr6) -> Result<()> {
-        let output = Command::new("bluetoothctl")
-            .arg("pair")
-            .arg(addr.to_string())
-            .output()
-            .await?;
-        debug!("{}", String::from_utf8_lossy(&output.stdout).to_string());
+    async fn connect_failed(&mut self, addr: MacAddr6, e: anyhow::Error) -> Result<()> {
+        let event = BluetoothEvent::ConnectFailed {
+            addr,
+            reason: e.to_string(),
+        };
+        self.channel.send(event).await?;
         Ok(())
     }
 
     pub async fn unpair(&mut self, device: &Device) -> Result<()> {
         if device.connected {
-            let output = Command::new("bluetoothctl")
-                .arg("disconnect")
-                .arg(device.addr.to_string())
-                .output()
-                .await?;
-            debug!("{}", String::from_utf8_lossy(&output.stdout).to_string());
             info!("Disconnecting from {:?} before unpair", device);
+            if let Err(e) = self.backend.disconnect(device.addr).await {
+                warn!("Could not disconnect from {}: {}", device.addr, e);
+            }
+        }
+
+        if self.connected == Some(device.addr) {
+            self.connected = None;
+        }
+        self.known.forget(device.addr);
+        if let Err(e) = self.known.save(&self.known_path) {
+            warn!("Could not save the known devices: {}", e);
+        }
+
+        match self.backend.remove(device.addr).await {
+            Ok(()) => info!("Unpaired device {:?}", device),
+            Err(e) => warn!("Could not unpair {}: {}", device.addr, e),
         }
 
-        let output = Command::new("bluetoothctl")
-            .arg("remove")
-            .arg(device.addr.to_string())
-            .output()
-            .await?;
-        debug!("{}", String::from_utf8_lossy(&output.stdout).to_string());
-        info!("Unpaired device {:?}", device);
-        
         self.start_scan().await?;
         Ok(())
     }
 
-    pub async fn process_requests(&mut self) -> Result<()> {
-        // This is working!
-        while !self.request_channel.is_empty() {
-            if let Ok(request) = self.request_channel.try_recv() {
-                info!("Processing {:?}", request);
-                match request {
-                    BluetoothRequest::Connect(device) => {
-                        self.connect(&device).await?;
-                    }
-                    BluetoothRequest::Unpair(device) => {
-                        self.unpair(&device).await?;
-                    }
-                    BluetoothRequest::StopScan => {
-                        self.stop_scan().await?;
-                    }
-                }
+    async fn process_request(&mut self, request: BluetoothRequest) -> Result<()> {
+        info!("Processing {:?}", request);
+        match request {
+            BluetoothRequest::Connect(device) => {
+                self.connect(&device).await?;
+            }
+            BluetoothRequest::Unpair(device) => {
+                self.unpair(&device).await?;
+            }
+            BluetoothRequest::StopScan => {
+                self.stop_scan().await?;
+            }
+            BluetoothRequest::ReconnectLast => {
+                self.reconnect_last().await?;
             }
         }
-
         Ok(())
     }
 }
 
-impl Drop for BluetoothManager {
-    fn drop(&mut self) {
-        if let Some(scan_process) = self.scan_process.as_mut() {
-            scan_process.start_kill().unwrap();
-        }
+/// Waits until `at`, forever if there is nothing to wait for
+async fn wait_until(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::BluetoothDeviceConfig;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn test_scan_result() {
@@ -315,4 +641,218 @@ mod tests {
         assert_eq!(result.addr.to_string(), "00:11:22:33:44:55");
         assert_eq!(result.name, "My Device");
     }
+
+    fn device(addr: &str, name: &str) -> Device {
+        Device {
+            addr: addr.parse().unwrap(),
+            name: name.to_string(),
+            paired: false,
+            trusted: false,
+            connected: false,
+            rssi: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_devices() {
+        let speaker = device("00:11:22:33:44:55", "Speaker");
+        let phone = device("AA:BB:CC:DD:EE:FF", "Phone");
+        assert_eq!(
+            diff_devices(&[], std::slice::from_ref(&speaker)),
+            [BluetoothEvent::DeviceFound(speaker.clone())]
+        );
+        assert!(diff_devices(
+            std::slice::from_ref(&speaker),
+            std::slice::from_ref(&speaker)
+        )
+        .is_empty());
+
+        let connected = Device {
+            connected: true,
+            rssi: Some(-60),
+            ..speaker.clone()
+        };
+        assert_eq!(
+            diff_devices(
+                &[speaker.clone(), phone.clone()],
+                std::slice::from_ref(&connected)
+            ),
+            [connected.updated(), BluetoothEvent::DeviceLost(phone.addr)]
+        );
+    }
+
+    #[test]
+    fn test_known_devices_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bluetooth.json");
+        assert_eq!(KnownDevices::load(&path).unwrap(), KnownDevices::default());
+
+        // Written before more than the last device was remembered
+        std::fs::write(&path, r#"{"addr": "00:11:22:33:44:55"}"#).unwrap();
+        let mut known = KnownDevices::load(&path).unwrap();
+        assert_eq!(known.devices, ["00:11:22:33:44:55"]);
+
+        known.connected("AA:BB:CC:DD:EE:FF".parse().unwrap());
+        known.connected("00:11:22:33:44:55".parse().unwrap());
+        known.save(&path).unwrap();
+        assert_eq!(
+            KnownDevices::load(&path).unwrap().devices,
+            ["00:11:22:33:44:55", "AA:BB:CC:DD:EE:FF"]
+        );
+    }
+
+    const SPEAKER: &str = "AA:BB:CC:DD:EE:FF";
+    const HEADPHONES: &str = "00:11:22:33:44:55";
+    const PHONE: &str = "11:22:33:44:55:66";
+
+    fn addr(addr: &str) -> MacAddr6 {
+        addr.parse().unwrap()
+    }
+
+    fn bluetooth_config() -> BluetoothConfig {
+        BluetoothConfig {
+            devices: vec![
+                BluetoothDeviceConfig {
+                    addr: PHONE.to_string(),
+                    reconnect: false,
+                },
+                BluetoothDeviceConfig {
+                    addr: HEADPHONES.to_string(),
+                    reconnect: true,
+                },
+            ],
+            ..BluetoothConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_reconnect_order() {
+        let known = KnownDevices {
+            devices: vec![
+                PHONE.to_string(),
+                SPEAKER.to_string(),
+                HEADPHONES.to_string(),
+            ],
+            ..KnownDevices::default()
+        };
+        assert_eq!(
+            reconnect_order(&bluetooth_config(), &known),
+            [addr(HEADPHONES), addr(SPEAKER)]
+        );
+    }
+
+    /// Connects the devices that are in range, never reports changes
+    #[derive(Debug, Default)]
+    struct FakeBackend {
+        in_range: Arc<Mutex<Vec<MacAddr6>>>,
+    }
+
+    #[async_trait]
+    impl Backend for FakeBackend {
+        async fn setup(&mut self, _events: Sender<BluetoothEvent>) -> Result<()> {
+            Ok(())
+        }
+
+        async fn start_discovery(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn stop_discovery(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn changes(&mut self) -> Result<Vec<BluetoothEvent>> {
+            std::future::pending().await
+        }
+
+        async fn pair(&mut self, _addr: MacAddr6) -> Result<()> {
+            Ok(())
+        }
+
+        async fn trust(&mut self, _addr: MacAddr6) -> Result<()> {
+            Ok(())
+        }
+
+        async fn connect(&mut self, addr: MacAddr6) -> Result<()> {
+            if !self.in_range.lock().unwrap().contains(&addr) {
+                return Err(anyhow!("{} is out of range", addr));
+            }
+            Ok(())
+        }
+
+        async fn disconnect(&mut self, _addr: MacAddr6) -> Result<()> {
+            Ok(())
+        }
+
+        async fn remove(&mut self, _addr: MacAddr6) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reconnect() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageConfig {
+            state_dir: dir.path().to_path_buf(),
+        };
+        let known = KnownDevices {
+            devices: vec![SPEAKER.to_string()],
+            ..KnownDevices::default()
+        };
+        known.save(&storage.bluetooth_path()).unwrap();
+
+        let backend = FakeBackend::default();
+        let in_range = backend.in_range.clone();
+        in_range.lock().unwrap().push(addr(SPEAKER));
+        let (tx, mut events) = tokio::sync::mpsc::channel(10);
+        let (log_tx, _log_rx) = tokio::sync::mpsc::channel(10);
+        let (_request_tx, request_rx) = tokio::sync::mpsc::channel(10);
+        let mut manager = BluetoothManager::new(
+            Box::new(backend),
+            tx,
+            log_tx,
+            request_rx,
+            &bluetooth_config(),
+            &storage,
+        )
+        .await
+        .unwrap();
+        assert!(manager.reconnect_at.is_some());
+
+        // The configured headphones are out of range, the speaker is next
+        manager.reconnect().await.unwrap();
+        assert_eq!(manager.connected, Some(addr(SPEAKER)));
+        assert_eq!(
+            events.recv().await,
+            Some(BluetoothEvent::Reconnecting { attempt: Some(1) })
+        );
+        assert_eq!(
+            events.recv().await,
+            Some(BluetoothEvent::Reconnecting { attempt: None })
+        );
+        assert!(manager.reconnect_at.is_none());
+
+        in_range.lock().unwrap().clear();
+        let lost = BluetoothEvent::DeviceLost(addr(SPEAKER));
+        manager.track(&lost).await.unwrap();
+        assert_eq!(manager.connected, None);
+        assert_eq!(manager.reconnect_at, Some(Instant::now() + RECONNECT_DELAY));
+        manager.reconnect().await.unwrap();
+        manager.reconnect().await.unwrap();
+        assert_eq!(manager.reconnect_attempts, 2);
+        assert_eq!(
+            manager.reconnect_at,
+            Some(Instant::now() + RECONNECT_DELAY * 4)
+        );
+
+        // Coming back by itself stops the attempts
+        let found = BluetoothEvent::DeviceFound(Device {
+            connected: true,
+            ..device(SPEAKER, "Speaker")
+        });
+        manager.track(&found).await.unwrap();
+        assert_eq!(manager.connected, Some(addr(SPEAKER)));
+        assert!(manager.reconnect_at.is_none());
+        assert_eq!(manager.reconnect_attempts, 0);
+    }
 }
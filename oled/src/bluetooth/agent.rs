@@ -0,0 +1,262 @@
+//! Pairing agent BlueZ asks when a device wants a passkey confirmed or a PIN,
+//! the question is shown on the screen and answered with B1 or B2.
+
+use std::{sync::Mutex, time::Duration};
+
+use macaddr::MacAddr6;
+use tokio::sync::mpsc::{self, Sender};
+use tracing::{info, warn};
+use zbus::{interface, zvariant::ObjectPath, DBusError};
+
+use super::{bluez::device_addr, BluetoothEvent, Pairing, PairingReply};
+
+/// Where the agent is served on the system bus connection
+pub const AGENT_PATH: &str = "/org/oled/agent";
+/// The screen can show a passkey and B1/B2 answer yes or no, but there is
+/// no way to type digits
+pub const CAPABILITY: &str = "DisplayYesNo";
+/// How long a question stays on the screen before pairing is given up
+pub const PAIRING_TIMEOUT: Duration = Duration::from_secs(30);
+/// Answered to devices that ask for a PIN, what most headsets use
+const DEFAULT_PIN: &str = "0000";
+
+#[derive(Debug, DBusError)]
+#[zbus(prefix = "org.bluez.Error")]
+pub enum AgentError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    Rejected(String),
+    Canceled(String),
+}
+
+#[derive(Debug)]
+pub struct Agent {
+    events: Sender<BluetoothEvent>,
+    timeout: Duration,
+    /// The device whose question is on the screen
+    pending: Mutex<Option<MacAddr6>>,
+}
+
+impl Agent {
+    pub fn new(events: Sender<BluetoothEvent>, timeout: Duration) -> Self {
+        Self {
+            events,
+            timeout,
+            pending: Mutex::new(None),
+        }
+    }
+
+    /// Shows `pairing` on the screen without waiting for an answer
+    async fn show(
+        &self,
+        device: &ObjectPath<'_>,
+        pairing: Pairing,
+        reply: Option<PairingReply>,
+    ) -> Result<MacAddr6, AgentError> {
+        let addr = device_addr(device)
+            .ok_or_else(|| AgentError::Rejected(format!("Unknown device {}", device)))?;
+        info!("Pairing {} asks for {:?}", addr, pairing);
+        *self.pending.lock().unwrap() = Some(addr);
+        let event = BluetoothEvent::PairingPrompt {
+            addr,
+            pairing,
+            reply,
+        };
+        self.events
+            .send(event)
+            .await
+            .map_err(|_| AgentError::Rejected("Nothing to show it on".to_string()))?;
+        Ok(addr)
+    }
+
+    /// Shows `pairing` on the screen and waits until it is accepted, rejected
+    /// or times out
+    async fn ask(&self, device: &ObjectPath<'_>, pairing: Pairing) -> Result<(), AgentError> {
+        let (tx, mut rx) = mpsc::channel(1);
+        let addr = self
+            .show(device, pairing, Some(PairingReply::new(tx)))
+            .await?;
+        let answer = tokio::time::timeout(self.timeout, rx.recv()).await;
+        self.pending.lock().unwrap().take();
+        match answer {
+            Ok(Some(true)) => Ok(()),
+            Ok(_) => Err(AgentError::Rejected("Rejected".to_string())),
+            Err(_) => {
+                warn!("Nobody answered pairing {}", addr);
+                let _ = self
+                    .events
+                    .send(BluetoothEvent::PairingCancelled(addr))
+                    .await;
+                Err(AgentError::Canceled("Timed out".to_string()))
+            }
+        }
+    }
+}
+
+#[interface(name = "org.bluez.Agent1")]
+impl Agent {
+    fn release(&self) {
+        info!("Pairing agent released");
+    }
+
+    async fn request_pin_code(&self, device: ObjectPath<'_>) -> Result<String, AgentError> {
+        self.ask(&device, Pairing::PinCode(DEFAULT_PIN.to_string()))
+            .await?;
+        Ok(DEFAULT_PIN.to_string())
+    }
+
+    async fn display_pin_code(
+        &self,
+        device: ObjectPath<'_>,
+        pincode: String,
+    ) -> Result<(), AgentError> {
+        self.show(&device, Pairing::Display(pincode), None).await?;
+        Ok(())
+    }
+
+    /// Typing a passkey would need digits the buttons don't have
+    fn request_passkey(&self, device: ObjectPath<'_>) -> Result<u32, AgentError> {
+        Err(AgentError::Rejected(format!(
+            "Can't enter a passkey for {}",
+            device
+        )))
+    }
+
+    /// Called again for every digit typed on the device
+    async fn display_passkey(
+        &self,
+        device: ObjectPath<'_>,
+        passkey: u32,
+        _entered: u16,
+    ) -> Result<(), AgentError> {
+        let passkey = format!("{:06}", passkey);
+        self.show(&device, Pairing::Display(passkey), None).await?;
+        Ok(())
+    }
+
+    async fn request_confirmation(
+        &self,
+        device: ObjectPath<'_>,
+        passkey: u32,
+    ) -> Result<(), AgentError> {
+        self.ask(&device, Pairing::Confirm(format!("{:06}", passkey)))
+            .await
+    }
+
+    async fn request_authorization(&self, device: ObjectPath<'_>) -> Result<(), AgentError> {
+        self.ask(&device, Pairing::Authorize).await
+    }
+
+    /// Services of devices that are paired already are fine
+    fn authorize_service(&self, _device: ObjectPath<'_>, _uuid: String) {}
+
+    async fn cancel(&self) {
+        let addr = self.pending.lock().unwrap().take();
+        if let Some(addr) = addr {
+            info!("Pairing {} was cancelled", addr);
+            let _ = self
+                .events
+                .send(BluetoothEvent::PairingCancelled(addr))
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixStream;
+    use zbus::{connection, proxy, Connection};
+
+    const DEVICE: &str = "/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF";
+
+    #[proxy(
+        interface = "org.bluez.Agent1",
+        default_service = "org.oled",
+        default_path = "/org/oled/agent"
+    )]
+    trait Agent1 {
+        fn request_confirmation(&self, device: &ObjectPath<'_>, passkey: u32) -> zbus::Result<()>;
+
+        fn request_pin_code(&self, device: &ObjectPath<'_>) -> zbus::Result<String>;
+    }
+
+    /// A connection to call the agent on like BlueZ would and the events it
+    /// sends
+    async fn mock_agent(
+        timeout: Duration,
+    ) -> (Connection, Connection, mpsc::Receiver<BluetoothEvent>) {
+        let (tx, rx) = mpsc::channel(10);
+        let (server, client) = UnixStream::pair().unwrap();
+        let server = connection::Builder::unix_stream(server)
+            .server(zbus::Guid::generate())
+            .unwrap()
+            .p2p()
+            .serve_at(AGENT_PATH, Agent::new(tx, timeout))
+            .unwrap()
+            .build();
+        let client = connection::Builder::unix_stream(client).p2p().build();
+        let (server, client) = tokio::join!(server, client);
+        (server.unwrap(), client.unwrap(), rx)
+    }
+
+    async fn answer(events: &mut mpsc::Receiver<BluetoothEvent>, accept: bool) -> Pairing {
+        match events.recv().await.unwrap() {
+            BluetoothEvent::PairingPrompt {
+                addr,
+                pairing,
+                reply: Some(reply),
+            } => {
+                assert_eq!(addr.to_string(), "AA:BB:CC:DD:EE:FF");
+                reply.send(accept);
+                pairing
+            }
+            event => panic!("Unexpected {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_confirmation() {
+        let (_server, client, mut events) = mock_agent(PAIRING_TIMEOUT).await;
+        let agent = Agent1Proxy::builder(&client).build().await.unwrap();
+        let device = ObjectPath::try_from(DEVICE).unwrap();
+
+        let (result, pairing) = tokio::join!(
+            agent.request_confirmation(&device, 1234),
+            answer(&mut events, true)
+        );
+        assert_eq!(pairing, Pairing::Confirm("001234".to_string()));
+        result.unwrap();
+
+        let (result, _) = tokio::join!(
+            agent.request_confirmation(&device, 1234),
+            answer(&mut events, false)
+        );
+        assert!(result.unwrap_err().to_string().contains("Rejected"));
+
+        let (result, pairing) =
+            tokio::join!(agent.request_pin_code(&device), answer(&mut events, true));
+        assert_eq!(pairing, Pairing::PinCode(DEFAULT_PIN.to_string()));
+        assert_eq!(result.unwrap(), DEFAULT_PIN);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let (_server, client, mut events) = mock_agent(Duration::from_millis(10)).await;
+        let agent = Agent1Proxy::builder(&client).build().await.unwrap();
+        let device = ObjectPath::try_from(DEVICE).unwrap();
+
+        let result = agent.request_confirmation(&device, 1234).await;
+        assert!(result.unwrap_err().to_string().contains("Canceled"));
+        assert!(matches!(
+            events.recv().await,
+            Some(BluetoothEvent::PairingPrompt { .. })
+        ));
+        assert_eq!(
+            events.recv().await,
+            Some(BluetoothEvent::PairingCancelled(
+                "AA:BB:CC:DD:EE:FF".parse().unwrap()
+            ))
+        );
+    }
+}
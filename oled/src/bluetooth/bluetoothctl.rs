@@ -0,0 +1,222 @@
+//! Fallback backend that runs `bluetoothctl` and reads its output, for when
+//! BlueZ can't be reached over D-Bus. Changes are found by listing the
+//! devices again every so often.
+
+use std::{process::Stdio, time::Duration};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use macaddr::MacAddr6;
+use tokio::{
+    process::{Child, Command},
+    sync::mpsc::Sender,
+};
+use tracing::debug;
+
+use super::{diff_devices, Backend, BluetoothEvent, Device, ScanResult};
+
+#[derive(Debug)]
+pub struct Bluetoothctl {
+    scan_process: Option<Child>,
+    /// How often the devices are listed
+    poll: Duration,
+    /// The devices as of the last time they were listed, `None` before that
+    known: Option<Vec<Device>>,
+}
+
+impl Bluetoothctl {
+    pub fn new(poll: Duration) -> Self {
+        Self {
+            scan_process: None,
+            poll,
+            known: None,
+        }
+    }
+
+    /// Every device `bluetoothctl devices` lists with its `info`
+    pub async fn devices(&mut self) -> Result<Vec<Device>> {
+        let mut devices: Vec<Device> = self
+            .scan_results()
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect();
+        for d in devices.iter_mut() {
+            let output = Command::new("bluetoothctl")
+                .arg("info")
+                .arg(format!("{}", d.addr))
+                .output()
+                .await?;
+            parse_info(d, &String::from_utf8_lossy(&output.stdout));
+        }
+        Ok(devices)
+    }
+
+    /// Runs `bluetoothctl <command> <addr>` and fails if it does
+    async fn run(&self, command: &str, addr: MacAddr6) -> Result<()> {
+        let output = Command::new("bluetoothctl")
+            .arg(command)
+            .arg(addr.to_string())
+            .output()
+            .await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        debug!("{}", stdout);
+        if !output.status.success() {
+            return Err(anyhow!(
+                "bluetoothctl {} {} failed: {}",
+                command,
+                addr,
+                stdout.lines().last().unwrap_or_default()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Every device `bluetoothctl devices` lists
+    async fn scan_results(&self) -> Result<Vec<ScanResult>> {
+        let output = Command::new("bluetoothctl").arg("devices").output().await?;
+        let mut results = vec![];
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Ok(s) = ScanResult::try_from(line) {
+                results.push(s);
+            }
+        }
+        Ok(results)
+    }
+}
+
+#[async_trait]
+impl Backend for Bluetoothctl {
+    /// bluetoothctl's own agent answers pairing requests
+    async fn setup(&mut self, _events: Sender<BluetoothEvent>) -> Result<()> {
+        Command::new("bluetoothctl")
+            .arg("agent")
+            .arg("on")
+            .output()
+            .await?;
+        Command::new("bluetoothctl")
+            .arg("pairable")
+            .arg("on")
+            .output()
+            .await?;
+        Ok(())
+    }
+
+    async fn start_discovery(&mut self) -> Result<()> {
+        if self.scan_process.is_none() {
+            Command::new("bluetoothctl")
+                .arg("discoverable")
+                .arg("on")
+                .kill_on_drop(true)
+                .spawn()?;
+            let scan_process = Command::new("bluetoothctl")
+                .arg("scan")
+                .arg("on")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .spawn()?;
+            self.scan_process = Some(scan_process);
+        }
+        Ok(())
+    }
+
+    async fn stop_discovery(&mut self) -> Result<()> {
+        if let Some(scan_process) = self.scan_process.as_mut() {
+            scan_process.kill().await?;
+            self.scan_process = None;
+        }
+        Ok(())
+    }
+
+    async fn changes(&mut self) -> Result<Vec<BluetoothEvent>> {
+        if self.known.is_some() {
+            tokio::time::sleep(self.poll).await;
+        }
+        let devices = self.devices().await?;
+        let known = self.known.get_or_insert_with(Vec::new);
+        let events = diff_devices(known, &devices);
+        *known = devices;
+        Ok(events)
+    }
+
+    async fn pair(&mut self, addr: MacAddr6) -> Result<()> {
+        self.run("pair", addr).await
+    }
+
+    async fn trust(&mut self, addr: MacAddr6) -> Result<()> {
+        self.run("trust", addr).await
+    }
+
+    async fn connect(&mut self, addr: MacAddr6) -> Result<()> {
+        self.run("connect", addr).await
+    }
+
+    async fn disconnect(&mut self, addr: MacAddr6) -> Result<()> {
+        self.run("disconnect", addr).await
+    }
+
+    async fn remove(&mut self, addr: MacAddr6) -> Result<()> {
+        self.run("remove", addr).await
+    }
+}
+
+impl Drop for Bluetoothctl {
+    fn drop(&mut self) {
+        if let Some(scan_process) = self.scan_process.as_mut() {
+            let _ = scan_process.start_kill();
+        }
+    }
+}
+
+/// Reads the "Paired:", "Trusted:", "Connected:" and "RSSI:" lines of
+/// `bluetoothctl info`
+fn parse_info(device: &mut Device, info: &str) {
+    device.rssi = None;
+    for line in info.lines() {
+        let Some((key, value)) = line.trim_start().split_once(": ") else {
+            continue;
+        };
+        // "RSSI: -60", or "RSSI: 0xffffffc4 (-60)" in newer versions
+        if key == "RSSI" {
+            let rssi = value.rsplit(' ').next().unwrap_or_default();
+            device.rssi = rssi.trim_matches(['(', ')']).parse().ok();
+            continue;
+        }
+        let flag = match key {
+            "Paired" => &mut device.paired,
+            "Trusted" => &mut device.trusted,
+            "Connected" => &mut device.connected,
+            _ => continue,
+        };
+        match value.trim() {
+            "yes" => *flag = true,
+            "no" => *flag = false,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_info() {
+        let mut device: Device = ScanResult::try_from("Device 00:11:22:33:44:55 Speaker")
+            .unwrap()
+            .into();
+        let info = "Device 00:11:22:33:44:55 (public)\n\
+            \tName: Speaker\n\
+            \tPaired: yes\n\
+            \tTrusted: no\n\
+            \tConnected: yes\n\
+            \tRSSI: 0xffffffc4 (-60)\n";
+        parse_info(&mut device, info);
+        assert!(device.paired && !device.trusted && device.connected);
+        assert_eq!(device.rssi, Some(-60));
+    }
+}
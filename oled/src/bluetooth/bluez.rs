@@ -0,0 +1,746 @@
+//! Backend talking to BlueZ over D-Bus, through the `org.bluez` service on
+//! the system bus. Changes to the devices come from BlueZ's
+//! `InterfacesAdded`, `InterfacesRemoved` and `PropertiesChanged` signals.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures_util::{
+    stream::{self, Select},
+    StreamExt,
+};
+use macaddr::MacAddr6;
+use tokio::sync::mpsc::Sender;
+use zbus::{
+    fdo::{
+        InterfacesAdded, InterfacesRemoved, ManagedObjects, ObjectManagerProxy, PropertiesChanged,
+    },
+    message::{self, Message},
+    proxy,
+    proxy::CacheProperties,
+    zvariant::{ObjectPath, OwnedObjectPath, Value},
+    Connection, MatchRule, MessageStream,
+};
+
+use super::{
+    agent::{self, Agent},
+    Backend, BluetoothEvent, Device,
+};
+
+const SERVICE: &str = "org.bluez";
+const ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+const DEVICE_INTERFACE: &str = "org.bluez.Device1";
+
+#[proxy(interface = "org.bluez.Adapter1", default_service = "org.bluez")]
+trait Adapter1 {
+    fn start_discovery(&self) -> zbus::Result<()>;
+
+    fn stop_discovery(&self) -> zbus::Result<()>;
+
+    fn remove_device(&self, device: &ObjectPath<'_>) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn set_pairable(&self, value: bool) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn set_discoverable(&self, value: bool) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.bluez.AgentManager1",
+    default_service = "org.bluez",
+    default_path = "/org/bluez"
+)]
+trait AgentManager1 {
+    fn register_agent(&self, agent: &ObjectPath<'_>, capability: &str) -> zbus::Result<()>;
+
+    fn request_default_agent(&self, agent: &ObjectPath<'_>) -> zbus::Result<()>;
+}
+
+#[proxy(interface = "org.bluez.Device1", default_service = "org.bluez")]
+trait Device1 {
+    fn connect(&self) -> zbus::Result<()>;
+
+    fn disconnect(&self) -> zbus::Result<()>;
+
+    fn pair(&self) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn set_trusted(&self, value: bool) -> zbus::Result<()>;
+}
+
+#[derive(Debug)]
+pub struct BlueZ {
+    connection: Connection,
+    /// Object path of the adapter, like `/org/bluez/hci0`
+    adapter: OwnedObjectPath,
+    /// The devices by object path, `None` until `changes` is first called
+    known: Option<HashMap<OwnedObjectPath, Device>>,
+    /// The object manager and device property signals
+    signals: Option<Select<MessageStream, MessageStream>>,
+}
+
+impl BlueZ {
+    /// Connects to the system bus, fails if BlueZ isn't running or there is
+    /// no adapter
+    pub async fn system() -> Result<Self> {
+        Self::new(Connection::system().await?).await
+    }
+
+    /// Uses the first adapter BlueZ has on `connection`
+    pub async fn new(connection: Connection) -> Result<Self> {
+        let objects = object_manager(&connection)
+            .await?
+            .get_managed_objects()
+            .await?;
+        let mut adapters: Vec<&OwnedObjectPath> = objects
+            .iter()
+            .filter(|(_, interfaces)| {
+                interfaces
+                    .keys()
+                    .any(|name| name.as_str() == ADAPTER_INTERFACE)
+            })
+            .map(|(path, _)| path)
+            .collect();
+        adapters.sort_by_key(|path| path.as_str());
+        let adapter = adapters
+            .first()
+            .map(|&path| path.clone())
+            .ok_or_else(|| anyhow!("BlueZ has no Bluetooth adapter"))?;
+        Ok(Self {
+            connection,
+            adapter,
+            known: None,
+            signals: None,
+        })
+    }
+
+    /// Every device of the adapter, named ones by name and then the rest
+    pub async fn devices(&self) -> Result<Vec<Device>> {
+        let objects = object_manager(&self.connection)
+            .await?
+            .get_managed_objects()
+            .await?;
+        Ok(sorted(parse_devices(&objects, &self.adapter).into_values()))
+    }
+
+    /// Listens for devices being added and removed, and for their properties
+    /// changing
+    async fn subscribe(&self) -> Result<Select<MessageStream, MessageStream>> {
+        let objects = MatchRule::builder()
+            .msg_type(message::Type::Signal)
+            .sender(SERVICE)?
+            .interface("org.freedesktop.DBus.ObjectManager")?
+            .path("/")?
+            .build();
+        let properties = MatchRule::builder()
+            .msg_type(message::Type::Signal)
+            .sender(SERVICE)?
+            .interface("org.freedesktop.DBus.Properties")?
+            .member("PropertiesChanged")?
+            .path_namespace(self.adapter.as_ref())?
+            .arg(0, DEVICE_INTERFACE)?
+            .build();
+        let objects = MessageStream::for_match_rule(objects, &self.connection, None).await?;
+        let properties = MessageStream::for_match_rule(properties, &self.connection, None).await?;
+        Ok(stream::select(objects, properties))
+    }
+
+    async fn adapter(&self) -> Result<Adapter1Proxy<'_>> {
+        let proxy = Adapter1Proxy::builder(&self.connection)
+            .path(&self.adapter)?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        Ok(proxy)
+    }
+
+    async fn device(&self, addr: MacAddr6) -> Result<Device1Proxy<'_>> {
+        let proxy = Device1Proxy::builder(&self.connection)
+            .path(device_path(&self.adapter, addr))?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        Ok(proxy)
+    }
+}
+
+#[async_trait]
+impl Backend for BlueZ {
+    async fn setup(&mut self, events: Sender<BluetoothEvent>) -> Result<()> {
+        self.adapter().await?.set_pairable(true).await?;
+        let agent = Agent::new(events, agent::PAIRING_TIMEOUT);
+        self.connection
+            .object_server()
+            .at(agent::AGENT_PATH, agent)
+            .await?;
+        let path = ObjectPath::from_static_str(agent::AGENT_PATH)?;
+        let manager = AgentManager1Proxy::new(&self.connection).await?;
+        manager.register_agent(&path, agent::CAPABILITY).await?;
+        manager.request_default_agent(&path).await?;
+        Ok(())
+    }
+
+    async fn start_discovery(&mut self) -> Result<()> {
+        let adapter = self.adapter().await?;
+        adapter.set_discoverable(true).await?;
+        adapter.start_discovery().await?;
+        Ok(())
+    }
+
+    async fn stop_discovery(&mut self) -> Result<()> {
+        self.adapter().await?.stop_discovery().await?;
+        Ok(())
+    }
+
+    async fn changes(&mut self) -> Result<Vec<BluetoothEvent>> {
+        // Subscribe before listing the devices so no change is missed
+        if self.signals.is_none() {
+            self.signals = Some(self.subscribe().await?);
+        }
+        let (Some(signals), Some(known)) = (self.signals.as_mut(), self.known.as_mut()) else {
+            let objects = object_manager(&self.connection)
+                .await?
+                .get_managed_objects()
+                .await?;
+            let known = parse_devices(&objects, &self.adapter);
+            let devices = sorted(known.values().cloned());
+            self.known = Some(known);
+            return Ok(devices
+                .into_iter()
+                .map(BluetoothEvent::DeviceFound)
+                .collect());
+        };
+        loop {
+            let message = signals
+                .next()
+                .await
+                .ok_or_else(|| anyhow!("D-Bus connection closed"))??;
+            if let Some(event) = device_event(known, &self.adapter, message)? {
+                return Ok(vec![event]);
+            }
+        }
+    }
+
+    async fn pair(&mut self, addr: MacAddr6) -> Result<()> {
+        self.device(addr).await?.pair().await?;
+        Ok(())
+    }
+
+    async fn trust(&mut self, addr: MacAddr6) -> Result<()> {
+        self.device(addr).await?.set_trusted(true).await?;
+        Ok(())
+    }
+
+    async fn connect(&mut self, addr: MacAddr6) -> Result<()> {
+        self.device(addr).await?.connect().await?;
+        Ok(())
+    }
+
+    async fn disconnect(&mut self, addr: MacAddr6) -> Result<()> {
+        self.device(addr).await?.disconnect().await?;
+        Ok(())
+    }
+
+    async fn remove(&mut self, addr: MacAddr6) -> Result<()> {
+        let path = device_path(&self.adapter, addr);
+        self.adapter().await?.remove_device(&path).await?;
+        Ok(())
+    }
+}
+
+async fn object_manager(connection: &Connection) -> Result<ObjectManagerProxy<'_>> {
+    let proxy = ObjectManagerProxy::builder(connection)
+        .destination(SERVICE)?
+        .path("/")?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?;
+    Ok(proxy)
+}
+
+/// BlueZ names devices after their address, `/org/bluez/hci0/dev_00_11_22_33_44_55`
+fn device_path(adapter: &ObjectPath<'_>, addr: MacAddr6) -> ObjectPath<'static> {
+    let path = format!("{}/dev_{}", adapter, addr.to_string().replace(':', "_"));
+    ObjectPath::try_from(path).expect("adapter path with a device suffix is valid")
+}
+
+/// The address in a device's object path
+pub fn device_addr(path: &ObjectPath<'_>) -> Option<MacAddr6> {
+    let name = path.as_str().rsplit('/').next()?.strip_prefix("dev_")?;
+    name.replace('_', ":").parse().ok()
+}
+
+fn is_device_of(path: &ObjectPath<'_>, adapter: &ObjectPath<'_>) -> bool {
+    path.as_str()
+        .strip_prefix(adapter.as_str())
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The devices below `adapter` by object path
+fn parse_devices(
+    objects: &ManagedObjects,
+    adapter: &ObjectPath<'_>,
+) -> HashMap<OwnedObjectPath, Device> {
+    objects
+        .iter()
+        .filter(|(path, _)| is_device_of(path, adapter))
+        .filter_map(|(path, interfaces)| {
+            let (_, properties) = interfaces
+                .iter()
+                .find(|(name, _)| name.as_str() == DEVICE_INTERFACE)?;
+            let device = parse_device(|name| properties.get(name).map(|value| &**value))?;
+            Some((path.clone(), device))
+        })
+        .collect()
+}
+
+/// Named devices by name and then the rest
+fn sorted(devices: impl Iterator<Item = Device>) -> Vec<Device> {
+    let mut devices: Vec<Device> = devices.collect();
+    devices.sort_by_key(|device| {
+        (
+            device.name.is_empty(),
+            device.name.to_lowercase(),
+            device.addr.into_array(),
+        )
+    });
+    devices
+}
+
+/// A device from the properties of its `org.bluez.Device1` interface
+fn parse_device<'a>(property: impl Fn(&str) -> Option<&'a Value<'a>>) -> Option<Device> {
+    let address: &str = property("Address")?.try_into().ok()?;
+    let mut device = Device {
+        addr: address.parse().ok()?,
+        name: String::new(),
+        paired: false,
+        trusted: false,
+        connected: false,
+        rssi: None,
+    };
+    update_device(&mut device, property);
+    Some(device)
+}
+
+/// Sets the properties of `device` that `property` has a value for
+fn update_device<'a>(device: &mut Device, property: impl Fn(&str) -> Option<&'a Value<'a>>) {
+    if let Some(name) = property("Name").and_then(|value| <&str>::try_from(value).ok()) {
+        device.name = name.to_string();
+    }
+    for (name, flag) in [
+        ("Paired", &mut device.paired),
+        ("Trusted", &mut device.trusted),
+        ("Connected", &mut device.connected),
+    ] {
+        if let Some(value) = property(name).and_then(|value| bool::try_from(value).ok()) {
+            *flag = value;
+        }
+    }
+    if let Some(rssi) = property("RSSI").and_then(|value| i16::try_from(value).ok()) {
+        device.rssi = Some(rssi);
+    }
+}
+
+/// Applies a signal to the `known` devices, returns what changed if anything
+fn device_event(
+    known: &mut HashMap<OwnedObjectPath, Device>,
+    adapter: &ObjectPath<'_>,
+    message: Message,
+) -> Result<Option<BluetoothEvent>> {
+    if let Some(signal) = InterfacesAdded::from_message(message.clone()) {
+        let args = signal.args()?;
+        if !is_device_of(args.object_path(), adapter) {
+            return Ok(None);
+        }
+        let Some((_, properties)) = args
+            .interfaces_and_properties()
+            .iter()
+            .find(|(name, _)| name.as_str() == DEVICE_INTERFACE)
+        else {
+            return Ok(None);
+        };
+        let Some(device) = parse_device(|name| properties.get(name)) else {
+            return Ok(None);
+        };
+        known.insert(args.object_path().clone().into(), device.clone());
+        return Ok(Some(BluetoothEvent::DeviceFound(device)));
+    }
+    if let Some(signal) = InterfacesRemoved::from_message(message.clone()) {
+        let args = signal.args()?;
+        if !args
+            .interfaces()
+            .iter()
+            .any(|name| name.as_str() == DEVICE_INTERFACE)
+        {
+            return Ok(None);
+        }
+        let path = OwnedObjectPath::from(args.object_path().clone());
+        return Ok(known
+            .remove(&path)
+            .map(|device| BluetoothEvent::DeviceLost(device.addr)));
+    }
+    if let Some(signal) = PropertiesChanged::from_message(message.clone()) {
+        let args = signal.args()?;
+        let Some(path) = message.header().path().cloned() else {
+            return Ok(None);
+        };
+        let Some(device) = known.get_mut(&OwnedObjectPath::from(path)) else {
+            return Ok(None);
+        };
+        let before = device.clone();
+        update_device(device, |name| args.changed_properties().get(name));
+        // BlueZ invalidates the RSSI once discovery stops
+        if args.invalidated_properties().contains(&"RSSI") {
+            device.rssi = None;
+        }
+        if device.differs(&before) {
+            return Ok(Some(device.updated()));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixStream;
+    use zbus::{
+        connection, fdo, fdo::ObjectManager, interface, object_server::SignalEmitter, ObjectServer,
+    };
+
+    const ADAPTER: &str = "/org/bluez/hci0";
+
+    #[derive(Debug, Default)]
+    struct MockAdapter {
+        pairable: bool,
+        discoverable: bool,
+        discovering: bool,
+    }
+
+    #[interface(name = "org.bluez.Adapter1")]
+    impl MockAdapter {
+        fn start_discovery(&mut self) -> fdo::Result<()> {
+            if self.discovering {
+                return Err(fdo::Error::Failed("In progress".to_string()));
+            }
+            self.discovering = true;
+            Ok(())
+        }
+
+        fn stop_discovery(&mut self) {
+            self.discovering = false;
+        }
+
+        async fn remove_device(
+            &self,
+            device: ObjectPath<'_>,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> fdo::Result<()> {
+            server.remove::<MockDevice, _>(&device).await?;
+            Ok(())
+        }
+
+        #[zbus(property)]
+        fn pairable(&self) -> bool {
+            self.pairable
+        }
+
+        #[zbus(property)]
+        fn set_pairable(&mut self, value: bool) {
+            self.pairable = value;
+        }
+
+        #[zbus(property)]
+        fn discoverable(&self) -> bool {
+            self.discoverable
+        }
+
+        #[zbus(property)]
+        fn set_discoverable(&mut self, value: bool) {
+            self.discoverable = value;
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct MockAgentManager {
+        agent: Option<(String, String)>,
+        default_agent: Option<String>,
+    }
+
+    #[interface(name = "org.bluez.AgentManager1")]
+    impl MockAgentManager {
+        fn register_agent(&mut self, agent: ObjectPath<'_>, capability: String) {
+            self.agent = Some((agent.to_string(), capability));
+        }
+
+        fn request_default_agent(&mut self, agent: ObjectPath<'_>) -> fdo::Result<()> {
+            if self.agent.as_ref().map(|(path, _)| path.as_str()) != Some(agent.as_str()) {
+                return Err(fdo::Error::Failed("Not registered".to_string()));
+            }
+            self.default_agent = Some(agent.to_string());
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct MockDevice {
+        address: String,
+        name: Option<String>,
+        paired: bool,
+        trusted: bool,
+        connected: bool,
+    }
+
+    impl MockDevice {
+        fn new(address: &str, name: Option<&str>) -> Self {
+            Self {
+                address: address.to_string(),
+                name: name.map(str::to_string),
+                paired: false,
+                trusted: false,
+                connected: false,
+            }
+        }
+    }
+
+    #[interface(name = "org.bluez.Device1")]
+    impl MockDevice {
+        async fn connect(
+            &mut self,
+            #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        ) -> fdo::Result<()> {
+            if !self.paired {
+                return Err(fdo::Error::Failed("Not paired".to_string()));
+            }
+            self.connected = true;
+            self.connected_changed(&emitter).await?;
+            Ok(())
+        }
+
+        async fn disconnect(
+            &mut self,
+            #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        ) -> fdo::Result<()> {
+            self.connected = false;
+            self.connected_changed(&emitter).await?;
+            Ok(())
+        }
+
+        async fn pair(
+            &mut self,
+            #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        ) -> fdo::Result<()> {
+            self.paired = true;
+            self.paired_changed(&emitter).await?;
+            Ok(())
+        }
+
+        #[zbus(property)]
+        fn address(&self) -> &str {
+            &self.address
+        }
+
+        /// Like BlueZ, only there when the device told us its name
+        #[zbus(property)]
+        fn name(&self) -> fdo::Result<String> {
+            self.name
+                .clone()
+                .ok_or_else(|| fdo::Error::InvalidArgs("No name".to_string()))
+        }
+
+        #[zbus(property)]
+        fn paired(&self) -> bool {
+            self.paired
+        }
+
+        #[zbus(property)]
+        fn trusted(&self) -> bool {
+            self.trusted
+        }
+
+        #[zbus(property)]
+        fn set_trusted(&mut self, value: bool) {
+            self.trusted = value;
+        }
+
+        #[zbus(property)]
+        fn connected(&self) -> bool {
+            self.connected
+        }
+    }
+
+    fn addr(addr: &str) -> MacAddr6 {
+        addr.parse().unwrap()
+    }
+
+    /// A peer to peer connection to a fake BlueZ with an adapter and two
+    /// devices, the first of which has no name
+    async fn mock_bluez() -> (Connection, BlueZ) {
+        let (server, client) = UnixStream::pair().unwrap();
+        let server = connection::Builder::unix_stream(server)
+            .server(zbus::Guid::generate())
+            .unwrap()
+            .p2p()
+            .serve_at("/", ObjectManager)
+            .unwrap()
+            .serve_at("/org/bluez", MockAgentManager::default())
+            .unwrap()
+            .serve_at(ADAPTER, MockAdapter::default())
+            .unwrap()
+            .serve_at(
+                format!("{}/dev_00_11_22_33_44_55", ADAPTER),
+                MockDevice::new("00:11:22:33:44:55", None),
+            )
+            .unwrap()
+            .serve_at(
+                format!("{}/dev_AA_BB_CC_DD_EE_FF", ADAPTER),
+                MockDevice::new("AA:BB:CC:DD:EE:FF", Some("Speaker")),
+            )
+            .unwrap()
+            .build();
+        let client = connection::Builder::unix_stream(client).p2p().build();
+        let (server, client) = tokio::join!(server, client);
+        let bluez = BlueZ::new(client.unwrap()).await.unwrap();
+        (server.unwrap(), bluez)
+    }
+
+    async fn mock_adapter(server: &Connection) -> MockAdapter {
+        let adapter = server
+            .object_server()
+            .interface::<_, MockAdapter>(ADAPTER)
+            .await
+            .unwrap();
+        let adapter = adapter.get().await;
+        MockAdapter {
+            pairable: adapter.pairable,
+            discoverable: adapter.discoverable,
+            discovering: adapter.discovering,
+        }
+    }
+
+    #[test]
+    fn test_device_addr() {
+        let path = device_path(
+            &ObjectPath::try_from(ADAPTER).unwrap(),
+            addr("AA:BB:CC:DD:EE:FF"),
+        );
+        assert_eq!(device_addr(&path), Some(addr("AA:BB:CC:DD:EE:FF")));
+        assert_eq!(device_addr(&ObjectPath::try_from(ADAPTER).unwrap()), None);
+    }
+
+    #[tokio::test]
+    async fn test_devices() {
+        let (_server, bluez) = mock_bluez().await;
+        assert_eq!(bluez.adapter.as_str(), ADAPTER);
+        let devices = bluez.devices().await.unwrap();
+        let names: Vec<(String, String)> = devices
+            .iter()
+            .map(|device| (device.addr.to_string(), device.name.clone()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("AA:BB:CC:DD:EE:FF".to_string(), "Speaker".to_string()),
+                ("00:11:22:33:44:55".to_string(), String::new()),
+            ]
+        );
+        assert!(devices.iter().all(|device| !device.paired));
+    }
+
+    #[tokio::test]
+    async fn test_discovery() {
+        let (server, mut bluez) = mock_bluez().await;
+        let (events, _) = tokio::sync::mpsc::channel(1);
+        bluez.setup(events).await.unwrap();
+        let manager = server
+            .object_server()
+            .interface::<_, MockAgentManager>("/org/bluez")
+            .await
+            .unwrap();
+        assert_eq!(
+            manager.get().await.agent,
+            Some((agent::AGENT_PATH.to_string(), "DisplayYesNo".to_string()))
+        );
+        assert_eq!(
+            manager.get().await.default_agent.as_deref(),
+            Some(agent::AGENT_PATH)
+        );
+
+        bluez.start_discovery().await.unwrap();
+        let adapter = mock_adapter(&server).await;
+        assert!(adapter.pairable && adapter.discoverable && adapter.discovering);
+        // BlueZ refuses to start twice
+        assert!(bluez.start_discovery().await.is_err());
+        bluez.stop_discovery().await.unwrap();
+        assert!(!mock_adapter(&server).await.discovering);
+    }
+
+    #[tokio::test]
+    async fn test_pair_connect_and_remove() {
+        let (_server, mut bluez) = mock_bluez().await;
+        let speaker = addr("AA:BB:CC:DD:EE:FF");
+        assert!(bluez.connect(speaker).await.is_err());
+
+        bluez.pair(speaker).await.unwrap();
+        bluez.trust(speaker).await.unwrap();
+        bluez.connect(speaker).await.unwrap();
+        let device = bluez.devices().await.unwrap().remove(0);
+        assert_eq!(device.addr, speaker);
+        assert!(device.paired && device.trusted && device.connected);
+
+        bluez.disconnect(speaker).await.unwrap();
+        assert!(!bluez.devices().await.unwrap()[0].connected);
+
+        bluez.remove(speaker).await.unwrap();
+        let devices = bluez.devices().await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].addr, addr("00:11:22:33:44:55"));
+        assert!(bluez.pair(speaker).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_changes() {
+        let (server, mut bluez) = mock_bluez().await;
+        let speaker = addr("AA:BB:CC:DD:EE:FF");
+        let found: Vec<MacAddr6> = bluez
+            .changes()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| match event {
+                BluetoothEvent::DeviceFound(device) => device.addr,
+                event => panic!("Unexpected {:?}", event),
+            })
+            .collect();
+        assert_eq!(found, [speaker, addr("00:11:22:33:44:55")]);
+
+        bluez.pair(speaker).await.unwrap();
+        let mut paired = bluez.devices().await.unwrap().remove(0);
+        assert!(paired.paired);
+        assert_eq!(bluez.changes().await.unwrap(), [paired.updated()]);
+        bluez.trust(speaker).await.unwrap();
+        paired.trusted = true;
+        assert_eq!(bluez.changes().await.unwrap(), [paired.updated()]);
+
+        server
+            .object_server()
+            .at(
+                format!("{}/dev_11_22_33_44_55_66", ADAPTER),
+                MockDevice::new("11:22:33:44:55:66", Some("Phone")),
+            )
+            .await
+            .unwrap();
+        match &bluez.changes().await.unwrap()[..] {
+            [BluetoothEvent::DeviceFound(device)] => assert_eq!(device.name, "Phone"),
+            events => panic!("Unexpected {:?}", events),
+        }
+
+        bluez.remove(speaker).await.unwrap();
+        assert_eq!(
+            bluez.changes().await.unwrap(),
+            [BluetoothEvent::DeviceLost(speaker)]
+        );
+    }
+}
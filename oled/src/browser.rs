@@ -0,0 +1,417 @@
+//! Directory browser behind the Files tab. With a single audio root the top
+//! level is that directory, with several the roots themselves are listed.
+
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+};
+
+use tracing::warn;
+
+use crate::playlist;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+    /// The ".." entry leading back up
+    Parent,
+    Dir,
+    /// An M3U or PLS file, entered like a directory to list its tracks
+    Playlist,
+    File,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+impl Entry {
+    /// The name as shown in the list, directories end in a slash
+    pub fn label(&self) -> String {
+        match self.kind {
+            EntryKind::Parent => "..".to_string(),
+            EntryKind::Dir => format!("{}/", self.name),
+            EntryKind::Playlist => format!("[{}]", self.name),
+            EntryKind::File => self.name.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Browser {
+    roots: Vec<PathBuf>,
+    /// Directories entered from the top level, innermost last
+    path: Vec<PathBuf>,
+    entries: Vec<Entry>,
+    cursor: usize,
+    scroll: usize,
+    /// How many entries fit on the screen
+    rows: usize,
+    /// Why the current directory could not be listed
+    error: Option<String>,
+}
+
+impl Browser {
+    pub fn new(roots: Vec<PathBuf>, rows: usize) -> Self {
+        let mut browser = Self {
+            roots,
+            path: Vec::new(),
+            entries: Vec::new(),
+            cursor: 0,
+            scroll: 0,
+            rows: rows.max(1),
+            error: None,
+        };
+        browser.list();
+        browser
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.cursor)
+    }
+
+    /// The directory or playlist being shown, `None` for the list of roots
+    pub fn current_dir(&self) -> Option<&Path> {
+        match (self.path.last(), self.roots.as_slice()) {
+            (Some(dir), _) => Some(dir),
+            (None, [root]) => Some(root),
+            (None, _) => None,
+        }
+    }
+
+    /// The current directory relative to the top level, `None` at the top
+    pub fn breadcrumb(&self) -> Option<String> {
+        let dir = self.path.last()?;
+        let base = match self.roots.as_slice() {
+            [root] => root.as_path(),
+            // The root's own name is part of the breadcrumb
+            _ => self.path[0].parent().unwrap_or(Path::new("")),
+        };
+        let relative = dir.strip_prefix(base).unwrap_or(dir);
+        Some(relative.to_string_lossy().into_owned())
+    }
+
+    /// Moves the cursor and scrolls to keep it on screen
+    pub fn move_cursor(&mut self, delta: i32) {
+        let last = self.entries.len().saturating_sub(1) as i32;
+        self.cursor = (self.cursor as i32 + delta).clamp(0, last) as usize;
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + self.rows {
+            self.scroll = self.cursor + 1 - self.rows;
+        }
+    }
+
+    /// Enters the selected directory or playlist or goes up for "..".
+    /// Returns false if the selection is a file.
+    pub fn enter(&mut self) -> bool {
+        let Some(entry) = self.selected() else {
+            return false;
+        };
+        match entry.kind {
+            EntryKind::Parent => self.leave(),
+            EntryKind::Dir | EntryKind::Playlist => {
+                self.path.push(entry.path.clone());
+                self.cursor = 0;
+                self.scroll = 0;
+                self.list();
+                true
+            }
+            EntryKind::File => false,
+        }
+    }
+
+    /// Goes up one directory and selects the one we came from. Returns
+    /// false at the top level.
+    pub fn leave(&mut self) -> bool {
+        let Some(left) = self.path.pop() else {
+            return false;
+        };
+        self.list();
+        self.select(&left);
+        true
+    }
+
+    /// Lists the current directory again after files changed. The selected
+    /// entry stays selected if it's still there, a directory or playlist that
+    /// is gone is left for the closest one that still exists.
+    pub fn reload(&mut self) {
+        let mut selected = self.selected().map(|entry| entry.path.clone());
+        while self.path.last().is_some_and(|dir| !dir.exists()) {
+            selected = self.path.pop();
+        }
+        self.list();
+        if let Some(path) = selected {
+            self.select(&path);
+        }
+    }
+
+    fn select(&mut self, path: &Path) {
+        if let Some(index) = self.entries.iter().position(|e| e.path == path) {
+            self.cursor = index;
+            self.move_cursor(0);
+        }
+    }
+
+    /// Lists the current directory, keeping the cursor in range
+    fn list(&mut self) {
+        self.error = None;
+        self.entries = match self.current_dir() {
+            Some(path) if playlist::is_playlist(path) && path.is_file() => {
+                match list_playlist(path) {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        warn!("Could not read playlist {:?}: {}", path, e);
+                        self.error = Some(e.to_string());
+                        Vec::new()
+                    }
+                }
+            }
+            Some(dir) => match list_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("Could not list {:?}: {}", dir, e);
+                    self.error = Some(e.to_string());
+                    Vec::new()
+                }
+            },
+            None => self
+                .roots
+                .iter()
+                .map(|root| Entry {
+                    name: root.to_string_lossy().into_owned(),
+                    path: root.clone(),
+                    kind: EntryKind::Dir,
+                })
+                .collect(),
+        };
+        if !self.path.is_empty() {
+            self.entries.insert(
+                0,
+                Entry {
+                    name: "..".to_string(),
+                    path: PathBuf::new(),
+                    kind: EntryKind::Parent,
+                },
+            );
+        }
+        self.cursor = self.cursor.min(self.entries.len().saturating_sub(1));
+        self.scroll = self.scroll.min(self.cursor);
+    }
+}
+
+/// The tracks of a playlist in its order, named by their titles if it has
+/// them
+fn list_playlist(path: &Path) -> anyhow::Result<Vec<Entry>> {
+    let entries = playlist::load_tracks(path)?
+        .into_iter()
+        .map(|track| {
+            let path = PathBuf::from(&track.location);
+            let name = track.title.unwrap_or_else(|| {
+                path.file_name().map_or(track.location.clone(), |name| {
+                    name.to_string_lossy().into_owned()
+                })
+            });
+            Entry {
+                name,
+                path,
+                kind: EntryKind::File,
+            }
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Directories first, then playlists and files, each by name. Hidden entries
+/// are skipped.
+fn list_dir(dir: &Path) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        // Follows symlinks, unlike DirEntry::file_type
+        let kind = if path.is_dir() {
+            EntryKind::Dir
+        } else if path.is_file() && playlist::is_playlist(&path) {
+            EntryKind::Playlist
+        } else if path.is_file() {
+            EntryKind::File
+        } else {
+            continue;
+        };
+        entries.push(Entry { name, path, kind });
+    }
+    let rank = |kind| match kind {
+        EntryKind::Dir => 0,
+        EntryKind::Playlist => 1,
+        _ => 2,
+    };
+    entries.sort_by(|a, b| match rank(a.kind).cmp(&rank(b.kind)) {
+        Ordering::Equal => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+        order => order,
+    });
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for path in [
+            "b.mp3",
+            "A.mp3",
+            ".hidden.mp3",
+            "Rock/Album/01.flac",
+            "Rock/02.mp3",
+            "jazz/03.ogg",
+        ] {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+        dir
+    }
+
+    fn labels(browser: &Browser) -> Vec<String> {
+        browser.entries().iter().map(Entry::label).collect()
+    }
+
+    #[test]
+    fn test_enter_and_leave() {
+        let dir = library();
+        let mut browser = Browser::new(vec![dir.path().to_path_buf()], 4);
+        assert_eq!(labels(&browser), ["jazz/", "Rock/", "A.mp3", "b.mp3"]);
+        assert_eq!(browser.breadcrumb(), None);
+        assert!(!browser.leave());
+
+        browser.move_cursor(1);
+        assert!(browser.enter());
+        assert_eq!(labels(&browser), ["..", "Album/", "02.mp3"]);
+        browser.move_cursor(1);
+        assert!(browser.enter());
+        assert_eq!(browser.breadcrumb().as_deref(), Some("Rock/Album"));
+        browser.move_cursor(1);
+        assert!(!browser.enter());
+
+        // ".." behaves like leave and selects where we came from
+        browser.move_cursor(-5);
+        assert!(browser.enter());
+        assert_eq!(browser.selected().unwrap().name, "Album");
+        assert!(browser.leave());
+        assert_eq!(browser.selected().unwrap().name, "Rock");
+    }
+
+    #[test]
+    fn test_several_roots() {
+        let a = library();
+        let b = tempfile::tempdir().unwrap();
+        let mut browser = Browser::new(vec![a.path().to_path_buf(), b.path().to_path_buf()], 4);
+        assert_eq!(browser.entries().len(), 2);
+        assert!(browser.enter());
+        let root_name = a.path().file_name().unwrap().to_string_lossy();
+        assert_eq!(browser.breadcrumb(), Some(root_name.into_owned()));
+        assert_eq!(labels(&browser)[0], "..");
+    }
+
+    #[test]
+    fn test_unreadable_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut browser = Browser::new(vec![dir.path().join("missing")], 4);
+        assert!(browser.entries().is_empty());
+        assert!(browser.error().is_some());
+        assert!(browser.selected().is_none());
+        browser.move_cursor(1);
+        assert!(!browser.enter());
+    }
+
+    #[test]
+    fn test_reload_keeps_selection() {
+        let dir = library();
+        let mut browser = Browser::new(vec![dir.path().to_path_buf()], 4);
+        browser.move_cursor(3);
+        assert_eq!(browser.selected().unwrap().name, "b.mp3");
+        std::fs::write(dir.path().join("0 new.mp3"), b"").unwrap();
+        browser.reload();
+        assert_eq!(browser.selected().unwrap().name, "b.mp3");
+        assert_eq!(browser.cursor(), 4);
+
+        // Removing the directory we're in goes back to its parent
+        browser.move_cursor(-3);
+        assert!(browser.enter());
+        browser.move_cursor(1);
+        assert!(browser.enter());
+        assert_eq!(browser.breadcrumb().as_deref(), Some("Rock/Album"));
+        std::fs::remove_dir_all(dir.path().join("Rock")).unwrap();
+        browser.reload();
+        assert_eq!(browser.breadcrumb(), None);
+        assert_eq!(labels(&browser), ["jazz/", "0 new.mp3", "A.mp3", "b.mp3"]);
+        assert_eq!(browser.cursor(), 0);
+    }
+
+    #[test]
+    fn test_playlists() {
+        let dir = library();
+        std::fs::write(
+            dir.path().join("mix.m3u"),
+            "#EXTM3U\n#EXTINF:-1,Second\nb.mp3\nRock/02.mp3\nmissing.mp3\n",
+        )
+        .unwrap();
+        let mut browser = Browser::new(vec![dir.path().to_path_buf()], 4);
+        assert_eq!(
+            labels(&browser),
+            ["jazz/", "Rock/", "[mix.m3u]", "A.mp3", "b.mp3"]
+        );
+
+        browser.move_cursor(2);
+        assert!(browser.enter());
+        assert_eq!(browser.breadcrumb().as_deref(), Some("mix.m3u"));
+        assert_eq!(labels(&browser), ["..", "Second", "02.mp3"]);
+        assert_eq!(browser.entries()[2].path, dir.path().join("Rock/02.mp3"));
+
+        // Deleting it goes back to where it was
+        std::fs::remove_file(dir.path().join("mix.m3u")).unwrap();
+        browser.reload();
+        assert_eq!(browser.breadcrumb(), None);
+    }
+
+    #[test]
+    fn test_scrolling() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..10 {
+            std::fs::write(dir.path().join(format!("{:02}.mp3", i)), b"").unwrap();
+        }
+        let mut browser = Browser::new(vec![dir.path().to_path_buf()], 4);
+        browser.move_cursor(5);
+        assert_eq!((browser.cursor(), browser.scroll()), (5, 2));
+        browser.move_cursor(20);
+        assert_eq!((browser.cursor(), browser.scroll()), (9, 6));
+        browser.move_cursor(-8);
+        assert_eq!((browser.cursor(), browser.scroll()), (1, 1));
+    }
+}
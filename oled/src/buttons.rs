@@ -1,18 +1,52 @@
 use anyhow::Result;
 use rppal::gpio::{Gpio, InputPin};
 
-#[derive(Debug)]
+use crate::config::ButtonsConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Button {
     B1,
     B2,
     B3,
 }
 
+/// Something that can tell whether a button is currently held down
+pub trait ButtonInput: std::fmt::Debug {
+    fn is_down(&mut self, button: Button) -> Result<bool>;
+}
+
 #[derive(Debug)]
-pub struct Buttons {
+pub struct GpioButtons {
     b1: InputPin,
     b2: InputPin,
     b3: InputPin,
+}
+
+impl GpioButtons {
+    pub fn new(config: &ButtonsConfig) -> Result<Self> {
+        let gpio = Gpio::new()?;
+
+        let b1 = gpio.get(config.b1_pin)?.into_input_pullup();
+        let b2 = gpio.get(config.b2_pin)?.into_input_pullup();
+        let b3 = gpio.get(config.b3_pin)?.into_input_pullup();
+
+        Ok(Self { b1, b2, b3 })
+    }
+}
+
+impl ButtonInput for GpioButtons {
+    fn is_down(&mut self, button: Button) -> Result<bool> {
+        Ok(match button {
+            Button::B1 => self.b1.is_low(),
+            Button::B2 => self.b2.is_low(),
+            Button::B3 => self.b3.is_low(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Buttons {
+    input: Box<dyn ButtonInput>,
 
     b1_pressed: bool,
     b2_pressed: bool,
@@ -24,57 +58,41 @@ pub struct Buttons {
 }
 
 impl Buttons {
-    pub fn pi_zero_2_w() -> Result<Self> {
-        let gpio = Gpio::new()?;
-
-        let b1 = gpio.get(21).unwrap().into_input_pullup();
-        let b2 = gpio.get(20).unwrap().into_input_pullup();
-        let b3 = gpio.get(16).unwrap().into_input_pullup();
-
-        Ok(Self {
-            b1,
-            b2,
-            b3,
+    pub fn new(input: Box<dyn ButtonInput>) -> Self {
+        Self {
+            input,
             b1_just_pressed: false,
             b2_just_pressed: false,
             b3_just_pressed: false,
             b1_pressed: false,
             b2_pressed: false,
             b3_pressed: false,
-        })
+        }
+    }
+
+    pub fn gpio(config: &ButtonsConfig) -> Result<Self> {
+        Ok(Self::new(Box::new(GpioButtons::new(config)?)))
     }
 
     pub fn update(&mut self) -> Result<()> {
-        if self.b1.is_low() {
-            if !self.b1_pressed {
-                self.b1_just_pressed = true;
-            } else {
-                self.b1_just_pressed = false;
-            }
+        if self.input.is_down(Button::B1)? {
+            self.b1_just_pressed = !self.b1_pressed;
             self.b1_pressed = true;
         } else {
             self.b1_pressed = false;
             self.b1_just_pressed = false;
         }
 
-        if self.b2.is_low() {
-            if !self.b2_pressed {
-                self.b2_just_pressed = true;
-            } else {
-                self.b2_just_pressed = false;
-            }
+        if self.input.is_down(Button::B2)? {
+            self.b2_just_pressed = !self.b2_pressed;
             self.b2_pressed = true;
         } else {
             self.b2_pressed = false;
             self.b2_just_pressed = false;
         }
 
-        if self.b3.is_low() {
-            if !self.b3_pressed {
-                self.b3_just_pressed = true;
-            } else {
-                self.b3_just_pressed = false;
-            }
+        if self.input.is_down(Button::B3)? {
+            self.b3_just_pressed = !self.b3_pressed;
             self.b3_pressed = true;
         } else {
             self.b3_pressed = false;
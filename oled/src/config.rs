@@ -0,0 +1,580 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use macaddr::MacAddr6;
+use serde::Deserialize;
+use tracing::warn;
+
+/// Used when neither `OLED_CONFIG` is set nor a path is given explicitly
+pub const DEFAULT_CONFIG_PATH: &str = "oled.toml";
+
+/// Highest BCM GPIO number broken out on the 40 pin header
+const MAX_GPIO: u8 = 27;
+/// 16 columns of the 5x9 font, the header's arrows and sleep timer need 12
+const MIN_WIDTH: i32 = 80;
+/// The Network, Player and Clock tabs are laid out for the SH1106's full
+/// 64 rows
+const MIN_HEIGHT: i32 = 64;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub display: DisplayConfig,
+    pub buttons: ButtonsConfig,
+    pub joystick: JoystickConfig,
+    pub audio: AudioConfig,
+    pub mpv: MpvConfig,
+    pub storage: StorageConfig,
+    pub timings: TimingsConfig,
+    pub sleep: SleepConfig,
+    pub alarm: AlarmConfig,
+    pub radio: RadioConfig,
+    pub bluetooth: BluetoothConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    pub width: i32,
+    pub height: i32,
+    /// First visible column in the controller's RAM
+    pub column_offset: u8,
+    pub spi_bus: u8,
+    pub spi_slave_select: u8,
+    pub spi_speed_hz: u32,
+    pub reset_pin: u8,
+    pub dc_pin: u8,
+    pub cs_pin: u8,
+    pub backlight_pin: u8,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            width: 128,
+            height: 64,
+            column_offset: 2,
+            spi_bus: 0,
+            spi_slave_select: 0,
+            spi_speed_hz: 8_000_000,
+            reset_pin: 25,
+            dc_pin: 24,
+            cs_pin: 8,
+            backlight_pin: 18,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ButtonsConfig {
+    pub b1_pin: u8,
+    pub b2_pin: u8,
+    pub b3_pin: u8,
+}
+
+impl Default for ButtonsConfig {
+    fn default() -> Self {
+        Self {
+            b1_pin: 21,
+            b2_pin: 20,
+            b3_pin: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JoystickConfig {
+    pub up_pin: u8,
+    pub down_pin: u8,
+    pub left_pin: u8,
+    pub right_pin: u8,
+    pub click_pin: u8,
+}
+
+impl Default for JoystickConfig {
+    fn default() -> Self {
+        Self {
+            up_pin: 6,
+            down_pin: 19,
+            left_pin: 5,
+            right_pin: 26,
+            click_pin: 13,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// Directories shown in the Files tab. `AUDIO_DIR` replaces these if set.
+    pub roots: Vec<PathBuf>,
+    /// Where the Queue tab saves the queue as a playlist, the first root if
+    /// unset
+    pub playlists: Option<PathBuf>,
+    /// `pactl` sink played through when no Bluetooth device is connected, the
+    /// first other one if unset
+    pub fallback_sink: Option<String>,
+}
+
+impl AudioConfig {
+    pub fn playlists_dir(&self) -> PathBuf {
+        match (&self.playlists, self.roots.first()) {
+            (Some(dir), _) => dir.clone(),
+            (None, Some(root)) => root.clone(),
+            (None, None) => PathBuf::from("."),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MpvConfig {
+    pub socket_path: PathBuf,
+}
+
+impl Default for MpvConfig {
+    fn default() -> Self {
+        Self {
+            socket_path: PathBuf::from("/tmp/mpvsocket"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Where settings changed on the device are kept, relative paths start
+    /// at the working directory
+    pub state_dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            state_dir: PathBuf::from("state"),
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn player_modes_path(&self) -> PathBuf {
+        self.state_dir.join("player.json")
+    }
+
+    pub fn library_cache_path(&self) -> PathBuf {
+        self.state_dir.join("library.json")
+    }
+
+    pub fn resume_path(&self) -> PathBuf {
+        self.state_dir.join("resume.json")
+    }
+
+    pub fn bookmarks_path(&self) -> PathBuf {
+        self.state_dir.join("bookmarks.json")
+    }
+
+    pub fn speeds_path(&self) -> PathBuf {
+        self.state_dir.join("speeds.json")
+    }
+
+    pub fn alarms_path(&self) -> PathBuf {
+        self.state_dir.join("alarms.json")
+    }
+
+    /// The Bluetooth devices that were connected, the last one first
+    pub fn bluetooth_path(&self) -> PathBuf {
+        self.state_dir.join("bluetooth.json")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimingsConfig {
+    pub frame_ms: u64,
+    /// How often `bluetoothctl` lists the devices, BlueZ over D-Bus reports
+    /// changes as they happen instead
+    pub bluetooth_poll_ms: u64,
+    /// How long the audio roots have to stay unchanged before the library is
+    /// updated, so copying an album is picked up once it's done
+    pub library_debounce_ms: u64,
+    /// Ignored since mpv reports its state by itself, still read so older
+    /// config files keep working
+    pub mpv_poll_ms: Option<u64>,
+    /// Ignored like `mpv_poll_ms`
+    pub status_interval_frames: Option<u32>,
+}
+
+impl TimingsConfig {
+    /// The settings in the file that don't do anything anymore
+    pub fn deprecated(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.mpv_poll_ms.is_some() {
+            names.push("timings.mpv_poll_ms");
+        }
+        if self.status_interval_frames.is_some() {
+            names.push("timings.status_interval_frames");
+        }
+        names
+    }
+}
+
+impl Default for TimingsConfig {
+    fn default() -> Self {
+        Self {
+            frame_ms: 50,
+            bluetooth_poll_ms: 1000,
+            library_debounce_ms: 2000,
+            mpv_poll_ms: None,
+            status_interval_frames: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SleepConfig {
+    /// How long the volume takes to fade out before the sleep timer pauses
+    /// playback, 0 stops without fading
+    pub fade_secs: u64,
+    /// Turn the display off when the sleep timer runs out, any button turns
+    /// it back on
+    pub display_off: bool,
+}
+
+impl Default for SleepConfig {
+    fn default() -> Self {
+        Self {
+            fade_secs: 60,
+            display_off: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlarmConfig {
+    /// How long the volume takes to go up to the alarm's volume
+    pub ramp_secs: u64,
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        Self { ramp_secs: 60 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RadioConfig {
+    /// M3U or PLS playlist with the stations listed in the Radio view
+    pub stations: PathBuf,
+}
+
+impl Default for RadioConfig {
+    fn default() -> Self {
+        Self {
+            stations: PathBuf::from("stations.m3u"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BluetoothConfig {
+    /// Connect a known device again at startup and when it drops out
+    pub reconnect: bool,
+    /// The wait between two attempts doubles from 1 second up to this
+    pub reconnect_max_secs: u64,
+    /// Tried in this order before the devices that were connected before
+    pub devices: Vec<BluetoothDeviceConfig>,
+}
+
+impl Default for BluetoothConfig {
+    fn default() -> Self {
+        Self {
+            reconnect: true,
+            reconnect_max_secs: 60,
+            devices: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BluetoothDeviceConfig {
+    /// Like `AA:BB:CC:DD:EE:FF`
+    pub addr: String,
+    /// `false` never connects the device by itself
+    #[serde(default = "default_true")]
+    pub reconnect: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Config {
+    /// Loads and validates the config, see [`Config::find`]
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let config = Self::find(path)?;
+        config.validate()?;
+        for name in config.timings.deprecated() {
+            warn!("{} is no longer used and can be removed", name);
+        }
+        Ok(config)
+    }
+
+    /// Reads the config from `path`, `OLED_CONFIG` or [`DEFAULT_CONFIG_PATH`]
+    /// in that order without validating it. A missing default file is not an
+    /// error, every setting has a default.
+    pub fn find(path: Option<&Path>) -> Result<Self> {
+        let env_path = std::env::var_os("OLED_CONFIG").map(PathBuf::from);
+        let mut config = match path.map(Path::to_path_buf).or(env_path) {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        if let Ok(audio_dir) = std::env::var("AUDIO_DIR") {
+            config.audio.roots = vec![PathBuf::from(audio_dir)];
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read config file {:?}", path))?;
+        Self::parse(&contents).with_context(|| format!("Invalid config file {:?}", path))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let display = &self.display;
+        if display.width < MIN_WIDTH || display.width + display.column_offset as i32 > 132 {
+            return Err(anyhow!(
+                "display.width must be between {} and {} with a column_offset of {}, got {}",
+                MIN_WIDTH,
+                132 - display.column_offset as i32,
+                display.column_offset,
+                display.width
+            ));
+        }
+        if display.height != MIN_HEIGHT {
+            return Err(anyhow!(
+                "display.height must be {}, got {}",
+                MIN_HEIGHT,
+                display.height
+            ));
+        }
+        if display.spi_bus > 6 {
+            return Err(anyhow!(
+                "display.spi_bus must be between 0 and 6, got {}",
+                display.spi_bus
+            ));
+        }
+        if display.spi_slave_select > 2 {
+            return Err(anyhow!(
+                "display.spi_slave_select must be between 0 and 2, got {}",
+                display.spi_slave_select
+            ));
+        }
+        if display.spi_speed_hz == 0 {
+            return Err(anyhow!("display.spi_speed_hz must be greater than 0"));
+        }
+
+        let pins = [
+            ("display.reset_pin", display.reset_pin),
+            ("display.dc_pin", display.dc_pin),
+            ("display.cs_pin", display.cs_pin),
+            ("display.backlight_pin", display.backlight_pin),
+            ("buttons.b1_pin", self.buttons.b1_pin),
+            ("buttons.b2_pin", self.buttons.b2_pin),
+            ("buttons.b3_pin", self.buttons.b3_pin),
+            ("joystick.up_pin", self.joystick.up_pin),
+            ("joystick.down_pin", self.joystick.down_pin),
+            ("joystick.left_pin", self.joystick.left_pin),
+            ("joystick.right_pin", self.joystick.right_pin),
+            ("joystick.click_pin", self.joystick.click_pin),
+        ];
+        let mut used: HashMap<u8, &str> = HashMap::new();
+        for (name, pin) in pins {
+            if pin > MAX_GPIO {
+                return Err(anyhow!(
+                    "{} must be a BCM GPIO number between 0 and {}, got {}",
+                    name,
+                    MAX_GPIO,
+                    pin
+                ));
+            }
+            if let Some(other) = used.insert(pin, name) {
+                return Err(anyhow!("{} and {} both use GPIO {}", other, name, pin));
+            }
+        }
+
+        if self.audio.roots.is_empty() {
+            return Err(anyhow!(
+                "No audio library configured, set audio.roots in the config file or the AUDIO_DIR environment variable"
+            ));
+        }
+
+        let timings = [
+            ("timings.frame_ms", self.timings.frame_ms),
+            ("timings.bluetooth_poll_ms", self.timings.bluetooth_poll_ms),
+            (
+                "timings.library_debounce_ms",
+                self.timings.library_debounce_ms,
+            ),
+        ];
+        for (name, value) in timings {
+            if value == 0 {
+                return Err(anyhow!("{} must be greater than 0", name));
+            }
+        }
+
+        if self.bluetooth.reconnect_max_secs == 0 {
+            return Err(anyhow!(
+                "bluetooth.reconnect_max_secs must be greater than 0"
+            ));
+        }
+        for device in &self.bluetooth.devices {
+            if device.addr.parse::<MacAddr6>().is_err() {
+                return Err(anyhow!(
+                    "bluetooth.devices has an invalid address {:?}",
+                    device.addr
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_roots(contents: &str) -> Result<Config> {
+        let mut config = Config::parse(contents)?;
+        config.audio.roots.push(PathBuf::from("/music"));
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let config = with_roots("").unwrap();
+        assert_eq!(config.display, DisplayConfig::default());
+        assert_eq!(config.display.dc_pin, 24);
+        assert_eq!(config.mpv.socket_path, PathBuf::from("/tmp/mpvsocket"));
+        assert_eq!(config.timings.frame_ms, 50);
+    }
+
+    #[test]
+    fn test_example_config_matches_defaults() {
+        let mut config = Config::parse(include_str!("../../oled.example.toml")).unwrap();
+        config.validate().unwrap();
+        config.audio.roots.clear();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_partial_config() {
+        let config = Config::parse(
+            r#"
+            [audio]
+            roots = ["/home/pi/Music", "/mnt/usb"]
+
+            [joystick]
+            click_pin = 12
+
+            [timings]
+            frame_ms = 100
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.audio.roots.len(), 2);
+        assert_eq!(config.joystick.click_pin, 12);
+        assert_eq!(config.joystick.up_pin, 6);
+        assert_eq!(config.timings.frame_ms, 100);
+        assert_eq!(config.timings.bluetooth_poll_ms, 1000);
+    }
+
+    #[test]
+    fn test_bluetooth_devices() {
+        let config = with_roots(
+            r#"
+            [[bluetooth.devices]]
+            addr = "AA:BB:CC:DD:EE:FF"
+
+            [[bluetooth.devices]]
+            addr = "00:11:22:33:44:55"
+            reconnect = false
+            "#,
+        )
+        .unwrap();
+        let devices = &config.bluetooth.devices;
+        assert_eq!(devices.len(), 2);
+        assert!(devices[0].reconnect && !devices[1].reconnect);
+        assert!(config.bluetooth.reconnect);
+    }
+
+    #[test]
+    fn test_unknown_field_is_rejected() {
+        let err = Config::parse("[display]\nwidht = 128\n").unwrap_err();
+        assert!(err.to_string().contains("widht"), "{}", err);
+    }
+
+    #[test]
+    fn test_removed_timings_are_accepted() {
+        let config =
+            with_roots("[timings]\nmpv_poll_ms = 500\nstatus_interval_frames = 20\n").unwrap();
+        assert_eq!(
+            config.timings.deprecated(),
+            ["timings.mpv_poll_ms", "timings.status_interval_frames"]
+        );
+        assert!(Config::default().timings.deprecated().is_empty());
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let err = with_roots("[buttons]\nb1_pin = 6\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "buttons.b1_pin and joystick.up_pin both use GPIO 6"
+        );
+
+        let err = with_roots("[joystick]\nclick_pin = 40\n").unwrap_err();
+        assert!(err.to_string().starts_with("joystick.click_pin"));
+
+        assert!(with_roots("[display]\nheight = 60\n").is_err());
+        assert!(with_roots("[display]\nwidth = 131\n").is_err());
+        assert!(with_roots("[timings]\nframe_ms = 0\n").is_err());
+        let err = with_roots("[[bluetooth.devices]]\naddr = \"speaker\"\n").unwrap_err();
+        assert!(err.to_string().contains("\"speaker\""), "{}", err);
+        assert!(Config::parse("").unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn test_minimum_display_size() {
+        assert!(with_roots("[display]\nwidth = 80\nheight = 64\n").is_ok());
+
+        let err = with_roots("[display]\nwidth = 24\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "display.width must be between 80 and 130 with a column_offset of 2, got 24"
+        );
+        let err = with_roots("[display]\nheight = 32\n").unwrap_err();
+        assert_eq!(err.to_string(), "display.height must be 64, got 32");
+    }
+}
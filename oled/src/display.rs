@@ -1,76 +1,94 @@
-use std::{thread::sleep, time::Duration};
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use embedded_graphics::{
-    pixelcolor::{BinaryColor, Gray2},
-    prelude::{DrawTarget, GrayColor, OriginDimensions, Size},
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, OriginDimensions, Pixel, Size},
 };
 use rppal::{
     gpio::{Gpio, OutputPin},
-    spi::Spi,
+    spi::{Bus, SlaveSelect, Spi},
 };
 
-const BUS_CLK_SPEED: u32 = 8_000_000;
+use crate::config::DisplayConfig;
 
-// TODO: Turn screen off on drop
+/// Unchanged runs shorter than this are sent anyway rather than splitting the
+/// write, since every span costs three command bytes to address.
+const MIN_SPAN_GAP: usize = 4;
+
+/// A backend capable of pushing a page-organised framebuffer somewhere. The
+/// [`Display`] does all of the drawing, a panel only has to know how to talk
+/// to whatever is on the other end.
+pub trait Panel: std::fmt::Debug {
+    fn init(&mut self) -> Result<()>;
+    /// Writes `data` to `page` starting at `column`. Each byte represents 8
+    /// pixels stacked vertically.
+    fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<()>;
+    fn set_contrast(&mut self, contrast: u8) -> Result<()>;
+    fn set_power(&mut self, on: bool) -> Result<()>;
+}
+
+/// The SH1106 controller on the Waveshare 1.3" HAT, driven over SPI
 #[derive(Debug)]
-pub struct Display {
-    width: i32,
-    height: i32,
+pub struct Sh1106 {
     bus: Spi,
+    column_offset: u8,
     rst_pin: OutputPin,
     dc_pin: OutputPin,
-    cs_pin: OutputPin,
-    bl_pin: OutputPin,
-    buffer: Vec<u8>,
+    // Never touched, but the pins have to stay configured as outputs
+    _cs_pin: OutputPin,
+    _bl_pin: OutputPin,
 }
 
-impl Display {
-    pub fn pi_zero_2_w(width: i32, height: i32) -> Result<Self> {
+impl Sh1106 {
+    pub fn new(config: &DisplayConfig) -> Result<Self> {
         let gpio = Gpio::new()?;
-        let rst_pin = gpio.get(25)?.into_output();
-        let dc_pin = gpio.get(24)?.into_output();
-        let cs_pin = gpio.get(8)?.into_output();
-        let bl_pin = gpio.get(18)?.into_output();
+        let rst_pin = gpio.get(config.reset_pin)?.into_output();
+        let dc_pin = gpio.get(config.dc_pin)?.into_output();
+        let cs_pin = gpio.get(config.cs_pin)?.into_output();
+        let bl_pin = gpio.get(config.backlight_pin)?.into_output();
 
+        let bus = match config.spi_bus {
+            0 => Bus::Spi0,
+            1 => Bus::Spi1,
+            2 => Bus::Spi2,
+            3 => Bus::Spi3,
+            4 => Bus::Spi4,
+            5 => Bus::Spi5,
+            6 => Bus::Spi6,
+            other => return Err(anyhow!("There is no SPI bus {}", other)),
+        };
+        let slave_select = match config.spi_slave_select {
+            0 => SlaveSelect::Ss0,
+            1 => SlaveSelect::Ss1,
+            2 => SlaveSelect::Ss2,
+            other => return Err(anyhow!("There is no slave select {}", other)),
+        };
         let bus = Spi::new(
-            rppal::spi::Bus::Spi0,
-            rppal::spi::SlaveSelect::Ss0,
-            BUS_CLK_SPEED,
+            bus,
+            slave_select,
+            config.spi_speed_hz,
             rppal::spi::Mode::Mode0,
         )?;
 
-        let mut out = Self {
-            width,
-            height,
+        Ok(Self {
             bus,
+            column_offset: config.column_offset,
             rst_pin,
             dc_pin,
-            cs_pin,
-            bl_pin,
-            buffer: vec![0x00; width as usize * ((height / 8) as usize)],
-        };
-
-        out.reset();
-        out.write_command(&[
-            0xAE, 0x02, 0x10, 0x40, 0x81, 0xA0, 0xC0, 0xA6, 0xA8, 0x3F, 0xD3, 0x00, 0xd5, 0x80,
-            0xD9, 0xF1, 0xDA, 0x12, 0xDB, 0x40, 0x20, 0x02, 0xA4, 0xA6,
-        ])?;
-        sleep(Duration::from_millis(100));
-        out.write_command(&[0xAF])?;
-
-        Ok(out)
-    }
-
-    fn write_data(&mut self, data: &[u8]) -> Result<()> {
-        self.dc_pin.set_high();
-        self.bus.write(&data)?;
-        Ok(())
+            _cs_pin: cs_pin,
+            _bl_pin: bl_pin,
+        })
     }
 
     fn write_command(&mut self, data: &[u8]) -> Result<()> {
         self.dc_pin.set_low();
-        self.bus.write(&data)?;
+        self.bus.write(data)?;
         Ok(())
     }
 
@@ -82,25 +100,253 @@ impl Display {
         self.rst_pin.set_high();
         sleep(Duration::from_millis(100));
     }
+}
+
+impl Panel for Sh1106 {
+    fn init(&mut self) -> Result<()> {
+        self.reset();
+        self.write_command(&[
+            0xAE, 0x02, 0x10, 0x40, 0x81, 0xA0, 0xC0, 0xA6, 0xA8, 0x3F, 0xD3, 0x00, 0xd5, 0x80,
+            0xD9, 0xF1, 0xDA, 0x12, 0xDB, 0x40, 0x20, 0x02, 0xA4, 0xA6,
+        ])?;
+        sleep(Duration::from_millis(100));
+        self.write_command(&[0xAF])?;
+        Ok(())
+    }
+
+    fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<()> {
+        // The SH1106 has 132 columns of RAM, the visible ones don't start at 0
+        let column = column + self.column_offset;
+        self.write_command(&[0xB0 + page])?;
+        self.write_command(&[column & 0x0F])?;
+        self.write_command(&[0x10 | (column >> 4)])?;
+        self.dc_pin.set_high();
+        self.bus.write(data)?;
+        Ok(())
+    }
+
+    fn set_contrast(&mut self, contrast: u8) -> Result<()> {
+        self.write_command(&[0x81, contrast])
+    }
+
+    fn set_power(&mut self, on: bool) -> Result<()> {
+        self.write_command(&[if on { 0xAF } else { 0xAE }])
+    }
+}
+
+/// What an in-memory panel has received so far
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    pub width: i32,
+    pub height: i32,
+    pub pages: Vec<u8>,
+    pub contrast: u8,
+    pub powered: bool,
+}
+
+impl Framebuffer {
+    pub fn pixel(&self, x: i32, y: i32) -> bool {
+        let index = x as usize + (y / 8) as usize * self.width as usize;
+        self.pages[index] & (1 << (y % 8)) != 0
+    }
+
+    /// Plain (P1) PBM with one line per pixel row, lit pixels are `1`
+    pub fn to_pbm(&self) -> String {
+        let mut out = format!("P1\n{} {}\n", self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                out.push(if self.pixel(x, y) { '1' } else { '0' });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Parses a plain PBM as written by [`Framebuffer::to_pbm`]
+    pub fn from_pbm(pbm: &str) -> Result<Self> {
+        let mut tokens = pbm
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(|line| line.split_whitespace());
+        if tokens.next() != Some("P1") {
+            return Err(anyhow!("Not a plain PBM file"));
+        }
+        let width: i32 = tokens.next().ok_or(anyhow!("Missing width"))?.parse()?;
+        let height: i32 = tokens.next().ok_or(anyhow!("Missing height"))?.parse()?;
+        if width <= 0 || height <= 0 || height % 8 != 0 {
+            return Err(anyhow!("Unsupported size {}x{}", width, height));
+        }
+
+        let mut frame = Self {
+            width,
+            height,
+            pages: vec![0x00; width as usize * (height / 8) as usize],
+            contrast: 0x80,
+            powered: true,
+        };
+        let mut bits = tokens.flat_map(|token| token.chars());
+        for y in 0..height {
+            for x in 0..width {
+                match bits.next() {
+                    Some('1') => {
+                        frame.pages[x as usize + (y / 8) as usize * width as usize] |= 1 << (y % 8)
+                    }
+                    Some('0') => {}
+                    Some(c) => return Err(anyhow!("Unexpected {:?} in pixel data", c)),
+                    None => return Err(anyhow!("Pixel data ends at ({}, {})", x, y)),
+                }
+            }
+        }
+        Ok(frame)
+    }
+}
+
+/// A panel that only exists in memory. Clones share the same framebuffer, so
+/// keep one around before handing the other to a [`Display`].
+#[derive(Debug, Clone)]
+pub struct MemoryPanel {
+    frame: Arc<Mutex<Framebuffer>>,
+}
+
+impl MemoryPanel {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self {
+            frame: Arc::new(Mutex::new(Framebuffer {
+                width,
+                height,
+                pages: vec![0x00; width as usize * ((height / 8) as usize)],
+                contrast: 0x80,
+                powered: false,
+            })),
+        }
+    }
+
+    pub fn snapshot(&self) -> Framebuffer {
+        self.frame.lock().unwrap().clone()
+    }
+}
+
+impl Panel for MemoryPanel {
+    fn init(&mut self) -> Result<()> {
+        let mut frame = self.frame.lock().unwrap();
+        frame.pages.fill(0x00);
+        frame.powered = true;
+        Ok(())
+    }
+
+    fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<()> {
+        let mut frame = self.frame.lock().unwrap();
+        let start = page as usize * frame.width as usize + column as usize;
+        let end = start + data.len();
+        if column as i32 + data.len() as i32 > frame.width || end > frame.pages.len() {
+            return Err(anyhow!(
+                "Write of {} bytes at page {} column {} is out of bounds",
+                data.len(),
+                page,
+                column
+            ));
+        }
+        frame.pages[start..end].copy_from_slice(data);
+        Ok(())
+    }
 
-    /// Each byte represents 8 pixels (stacked vertically) on the screen
+    fn set_contrast(&mut self, contrast: u8) -> Result<()> {
+        self.frame.lock().unwrap().contrast = contrast;
+        Ok(())
+    }
+
+    fn set_power(&mut self, on: bool) -> Result<()> {
+        self.frame.lock().unwrap().powered = on;
+        Ok(())
+    }
+}
+
+// TODO: Turn screen off on drop
+#[derive(Debug)]
+pub struct Display {
+    width: i32,
+    height: i32,
+    panel: Box<dyn Panel>,
+    buffer: Vec<u8>,
+    /// What the panel is currently showing, `None` if it is unknown
+    shadow: Option<Vec<u8>>,
+}
+
+impl Display {
+    pub fn new(width: i32, height: i32, mut panel: Box<dyn Panel>) -> Result<Self> {
+        panel.init()?;
+        Ok(Self {
+            width,
+            height,
+            panel,
+            buffer: vec![0x00; width as usize * ((height / 8) as usize)],
+            shadow: None,
+        })
+    }
+
+    pub fn sh1106(config: &DisplayConfig) -> Result<Self> {
+        Self::new(config.width, config.height, Box::new(Sh1106::new(config)?))
+    }
+
+    /// Each byte represents 8 pixels (stacked vertically) on the screen. Only
+    /// the spans that differ from the previous render are sent to the panel.
     pub fn render(&mut self) -> Result<()> {
-        for page in 0..8 {
-            self.write_command(&[0xB0 + page])?;
-            self.write_command(&[0x02])?;
-            self.write_command(&[0x10])?;
-            sleep(Duration::from_millis(10));
-            self.dc_pin.set_high();
-            for index in 0..(self.width as usize) {
-                let byte = self.buffer[index + self.width as usize * page as usize];
-                self.bus.write(&[byte])?;
+        let width = self.width as usize;
+        for page in 0..(self.height / 8) as usize {
+            let row = page * width..(page + 1) * width;
+            for span in self.dirty_spans(page) {
+                let start = row.start + span.start;
+                let end = row.start + span.end;
+                self.panel
+                    .write_page(page as u8, span.start as u8, &self.buffer[start..end])?;
+            }
+        }
+        self.shadow = Some(self.buffer.clone());
+
+        Ok(())
+    }
+
+    /// Forgets what the panel is showing so the next render sends everything
+    pub fn invalidate(&mut self) {
+        self.shadow = None;
+    }
+
+    /// Column ranges of `page` that have changed since the last render
+    fn dirty_spans(&self, page: usize) -> Vec<Range<usize>> {
+        let width = self.width as usize;
+        let row = &self.buffer[page * width..(page + 1) * width];
+        let Some(shadow) = &self.shadow else {
+            return std::iter::once(0..width).collect();
+        };
+        let shadow = &shadow[page * width..(page + 1) * width];
+
+        let mut spans: Vec<Range<usize>> = Vec::new();
+        for column in (0..width).filter(|&c| row[c] != shadow[c]) {
+            match spans.last_mut() {
+                Some(span) if column - span.end < MIN_SPAN_GAP => span.end = column + 1,
+                _ => spans.push(column..column + 1),
             }
         }
+        spans
+    }
+
+    pub fn set_contrast(&mut self, contrast: u8) -> Result<()> {
+        self.panel.set_contrast(contrast)
+    }
 
+    pub fn set_power(&mut self, on: bool) -> Result<()> {
+        self.panel.set_power(on)?;
+        if on {
+            self.invalidate();
+        }
         Ok(())
     }
 
+    /// Pixels outside the display are left out
     pub fn draw_pixel(&mut self, x: u8, y: u8, color: bool) {
+        if x as i32 >= self.width || y as i32 >= self.height {
+            return;
+        }
         let index = x as usize + (y / 8) as usize * self.width as usize;
         if color {
             self.buffer[index] |= 1 << (y % 8);
@@ -150,11 +396,163 @@ impl DrawTarget for Display {
 
     fn draw_iter<I>(&mut self, pixels: I) -> std::result::Result<(), Self::Error>
     where
-        I: IntoIterator<Item = embedded_graphics::Pixel<Self::Color>>,
+        I: IntoIterator<Item = Pixel<Self::Color>>,
     {
-        for pixel in pixels {
-            self.draw_pixel(pixel.0.x as u8, pixel.0.y as u8, pixel.1 == BinaryColor::On);
+        for Pixel(point, color) in pixels {
+            if (0..self.width).contains(&point.x) && (0..self.height).contains(&point.y) {
+                self.draw_pixel(point.x as u8, point.y as u8, color == BinaryColor::On);
+            }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::prelude::Point;
+
+    #[test]
+    fn test_render_to_memory_panel() {
+        let panel = MemoryPanel::new(128, 64);
+        let mut display = Display::new(128, 64, Box::new(panel.clone())).unwrap();
+        assert!(panel.snapshot().powered);
+
+        display.draw_pixel(0, 0, true);
+        display.draw_pixel(127, 63, true);
+        display.draw_rect(10, 10, 2, 2, BinaryColor::On);
+        assert!(!panel.snapshot().pixel(0, 0));
+
+        display.render().unwrap();
+        let frame = panel.snapshot();
+        assert!(frame.pixel(0, 0));
+        assert!(frame.pixel(127, 63));
+        assert!(frame.pixel(11, 11));
+        assert!(!frame.pixel(12, 12));
+        assert_eq!(frame.pages.iter().map(|b| b.count_ones()).sum::<u32>(), 6);
+    }
+
+    #[test]
+    fn test_drawing_outside_is_clipped() {
+        let panel = MemoryPanel::new(80, 64);
+        let mut display = Display::new(80, 64, Box::new(panel.clone())).unwrap();
+        display.draw_rect(78, 62, 4, 4, BinaryColor::On);
+        let pixels = [(-1, 0), (80, 0), (0, 64), (200, 200), (79, 63)]
+            .map(|(x, y)| Pixel(Point::new(x, y), BinaryColor::On));
+        display.draw_iter(pixels).unwrap();
+
+        display.render().unwrap();
+        let frame = panel.snapshot();
+        assert!(frame.pixel(78, 62));
+        assert!(frame.pixel(79, 63));
+        assert!(!frame.pixel(0, 0));
+        assert_eq!(frame.pages.iter().map(|b| b.count_ones()).sum::<u32>(), 4);
+    }
+
+    #[test]
+    fn test_contrast_and_power() {
+        let panel = MemoryPanel::new(128, 64);
+        let mut display = Display::new(128, 64, Box::new(panel.clone())).unwrap();
+        display.set_contrast(0x10).unwrap();
+        display.set_power(false).unwrap();
+        let frame = panel.snapshot();
+        assert_eq!(frame.contrast, 0x10);
+        assert!(!frame.powered);
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct RecordingPanel {
+        writes: Arc<Mutex<Vec<(u8, u8, usize)>>>,
+    }
+
+    impl Panel for RecordingPanel {
+        fn init(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<()> {
+            self.writes.lock().unwrap().push((page, column, data.len()));
+            Ok(())
+        }
+
+        fn set_contrast(&mut self, _contrast: u8) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_power(&mut self, _on: bool) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl RecordingPanel {
+        fn take(&self) -> Vec<(u8, u8, usize)> {
+            std::mem::take(&mut self.writes.lock().unwrap())
+        }
+    }
+
+    #[test]
+    fn test_render_only_sends_dirty_spans() {
+        let panel = RecordingPanel::default();
+        let mut display = Display::new(128, 64, Box::new(panel.clone())).unwrap();
+
+        display.render().unwrap();
+        let writes = panel.take();
+        assert_eq!(writes.len(), 8);
+        assert!(writes
+            .iter()
+            .all(|(_, column, len)| *column == 0 && *len == 128));
+
+        display.render().unwrap();
+        assert!(panel.take().is_empty());
+
+        // Redrawing the same content is not a change
+        display.fill(BinaryColor::Off);
+        display.render().unwrap();
+        assert!(panel.take().is_empty());
+
+        display.draw_pixel(10, 20, true);
+        display.draw_pixel(12, 20, true);
+        display.draw_pixel(100, 20, true);
+        display.draw_pixel(5, 63, true);
+        display.render().unwrap();
+        assert_eq!(panel.take(), vec![(2, 10, 3), (2, 100, 1), (7, 5, 1)]);
+
+        display.invalidate();
+        display.render().unwrap();
+        assert_eq!(panel.take().len(), 8);
+    }
+
+    #[test]
+    fn test_partial_renders_match_full_render() {
+        let panel = MemoryPanel::new(128, 64);
+        let mut display = Display::new(128, 64, Box::new(panel.clone())).unwrap();
+        for frame in 0..4u8 {
+            display.fill(BinaryColor::Off);
+            display.draw_rect(frame * 10, frame * 5, 20, 12, BinaryColor::On);
+            display.render().unwrap();
+            assert_eq!(panel.snapshot().pages, display.buffer);
+        }
+    }
+
+    #[test]
+    fn test_pbm_round_trip() {
+        let panel = MemoryPanel::new(16, 8);
+        let mut display = Display::new(16, 8, Box::new(panel.clone())).unwrap();
+        display.draw_pixel(0, 0, true);
+        display.draw_pixel(15, 7, true);
+        display.render().unwrap();
+
+        let frame = panel.snapshot();
+        let pbm = frame.to_pbm();
+        assert!(pbm.starts_with("P1\n16 8\n1000000000000000\n"));
+        assert_eq!(Framebuffer::from_pbm(&pbm).unwrap().pages, frame.pages);
+        assert!(Framebuffer::from_pbm("P1\n16 8\n0101").is_err());
+    }
+
+    #[test]
+    fn test_out_of_bounds_write() {
+        let mut panel = MemoryPanel::new(128, 64);
+        assert!(panel.write_page(0, 120, &[0xFF; 16]).is_err());
+        assert!(panel.write_page(8, 0, &[0xFF]).is_err());
+    }
+}
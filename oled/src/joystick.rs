@@ -1,36 +1,31 @@
 use anyhow::Result;
 use rppal::gpio::{Gpio, InputPin, Level};
 
-pub struct Joystick {
+use crate::config::JoystickConfig;
+
+/// Something that can tell which way the joystick is currently pushed
+pub trait JoystickInput: std::fmt::Debug {
+    fn read(&mut self) -> Result<State>;
+}
+
+#[derive(Debug)]
+pub struct GpioJoystick {
     up_pin: InputPin,
     down_pin: InputPin,
     left_pin: InputPin,
     right_pin: InputPin,
     click_pin: InputPin,
-
-    last_state: State,
-    just_switched: bool,
-}
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub enum State {
-    Up,
-    Down,
-    Left,
-    Right,
-    Click,
-    Neutral,
 }
 
-impl Joystick {
-    pub fn pi_zero_2_w() -> Result<Self> {
+impl GpioJoystick {
+    pub fn new(config: &JoystickConfig) -> Result<Self> {
         let gpio = Gpio::new()?;
 
-        let up_pin = gpio.get(6)?.into_input_pullup();
-        let down_pin = gpio.get(19)?.into_input_pullup();
-        let left_pin = gpio.get(5)?.into_input_pullup();
-        let right_pin = gpio.get(26)?.into_input_pullup();
-        let click_pin = gpio.get(13)?.into_input_pullup();
+        let up_pin = gpio.get(config.up_pin)?.into_input_pullup();
+        let down_pin = gpio.get(config.down_pin)?.into_input_pullup();
+        let left_pin = gpio.get(config.left_pin)?.into_input_pullup();
+        let right_pin = gpio.get(config.right_pin)?.into_input_pullup();
+        let click_pin = gpio.get(config.click_pin)?.into_input_pullup();
 
         Ok(Self {
             up_pin,
@@ -38,11 +33,11 @@ impl Joystick {
             left_pin,
             right_pin,
             click_pin,
-            last_state: State::Neutral,
-            just_switched: false,
         })
     }
+}
 
+impl JoystickInput for GpioJoystick {
     fn read(&mut self) -> Result<State> {
         if self.up_pin.read() == Level::Low {
             return Ok(State::Up);
@@ -62,14 +57,42 @@ impl Joystick {
 
         Ok(State::Neutral)
     }
+}
 
-    pub fn update(&mut self) -> Result<State> {
-        let new_state = self.read()?;
-        if new_state != self.last_state {
-            self.just_switched = true;
-        } else {
-            self.just_switched = false;
+#[derive(Debug)]
+pub struct Joystick {
+    input: Box<dyn JoystickInput>,
+
+    last_state: State,
+    just_switched: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Up,
+    Down,
+    Left,
+    Right,
+    Click,
+    Neutral,
+}
+
+impl Joystick {
+    pub fn new(input: Box<dyn JoystickInput>) -> Self {
+        Self {
+            input,
+            last_state: State::Neutral,
+            just_switched: false,
         }
+    }
+
+    pub fn gpio(config: &JoystickConfig) -> Result<Self> {
+        Ok(Self::new(Box::new(GpioJoystick::new(config)?)))
+    }
+
+    pub fn update(&mut self) -> Result<State> {
+        let new_state = self.input.read()?;
+        self.just_switched = new_state != self.last_state;
         self.last_state = new_state;
         Ok(new_state)
     }
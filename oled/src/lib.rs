@@ -0,0 +1,17 @@
+pub mod alarm;
+pub mod audio;
+pub mod bluetooth;
+pub mod browser;
+pub mod buttons;
+pub mod config;
+pub mod display;
+pub mod joystick;
+pub mod library;
+pub mod mpv;
+pub mod playlist;
+pub mod radio;
+pub mod sleep;
+pub mod state;
+pub mod storage;
+pub mod views;
+pub mod watcher;
@@ -0,0 +1,465 @@
+//! Tags of every audio file under the library roots. Reading tags is slow on
+//! a Pi, so the index is cached on disk and only new or changed files are
+//! read again. The roots are watched and indexed again whenever they change.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use lofty::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+use crate::watcher;
+
+/// Files with these extensions are indexed
+pub const AUDIO_EXTENSIONS: [&str; 5] = ["mp3", "flac", "ogg", "opus", "m4a"];
+
+/// Bumped whenever [`TrackInfo`] changes so old caches are read again
+const CACHE_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub genre: Option<String>,
+    /// Length in whole seconds
+    pub duration: Option<u32>,
+}
+
+impl TrackInfo {
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = lofty::read_from_path(path)?;
+        let duration = file.properties().duration().as_secs() as u32;
+        let mut info = TrackInfo {
+            duration: (duration > 0).then_some(duration),
+            ..Default::default()
+        };
+        if let Some(tag) = file.primary_tag().or_else(|| file.first_tag()) {
+            info.title = tag.title().map(|s| s.trim().to_string());
+            info.artist = tag.artist().map(|s| s.trim().to_string());
+            info.album = tag.album().map(|s| s.trim().to_string());
+            info.track_number = tag.track();
+            info.genre = tag.genre().map(|s| s.trim().to_string());
+        }
+        Ok(info)
+    }
+
+    /// "Artist - Title", or just the title. `None` without a title, the file
+    /// name is the better label then.
+    pub fn display_name(&self) -> Option<String> {
+        let title = self.title.as_deref().filter(|t| !t.is_empty())?;
+        Some(match self.artist.as_deref().filter(|a| !a.is_empty()) {
+            Some(artist) => format!("{} - {}", artist, title),
+            None => title.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CachedTrack {
+    path: PathBuf,
+    /// Modification time in seconds since the epoch
+    modified: u64,
+    size: u64,
+    /// When the file was first indexed, in seconds since the epoch
+    added: u64,
+    info: TrackInfo,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    tracks: Vec<CachedTrack>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Library {
+    tracks: HashMap<PathBuf, CachedTrack>,
+}
+
+impl Library {
+    /// Reads the cache at `path`. A missing, broken or outdated cache gives an
+    /// empty library that [`Library::index`] fills from scratch.
+    pub fn load(path: &Path) -> Self {
+        let cache = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str::<CacheFile>(&contents),
+            Err(_) => return Self::default(),
+        };
+        match cache {
+            Ok(cache) if cache.version == CACHE_VERSION => Self {
+                tracks: cache
+                    .tracks
+                    .into_iter()
+                    .map(|track| (track.path.clone(), track))
+                    .collect(),
+            },
+            Ok(_) => Self::default(),
+            Err(e) => {
+                warn!("Ignoring broken library cache {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// A library of tags that were read elsewhere, nothing is cached
+    pub fn from_tracks(tracks: impl IntoIterator<Item = (PathBuf, TrackInfo)>) -> Self {
+        Self {
+            tracks: tracks
+                .into_iter()
+                .map(|(path, info)| {
+                    let track = CachedTrack {
+                        path: path.clone(),
+                        modified: 0,
+                        size: 0,
+                        added: 0,
+                        info,
+                    };
+                    (path, track)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let cache = CacheFile {
+            version: CACHE_VERSION,
+            // JSON can't hold paths that are not UTF-8, those are read again
+            // on every start
+            tracks: self
+                .tracks
+                .values()
+                .filter(|track| track.path.to_str().is_some())
+                .cloned()
+                .collect(),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string(&cache)?)?;
+        Ok(())
+    }
+
+    /// Reads the tags of new and changed files under `roots` and forgets
+    /// files that are gone. Returns how many files had to be read.
+    pub fn index(&mut self, roots: &[PathBuf]) -> usize {
+        let mut files = Vec::new();
+        for root in roots {
+            find_audio_files(root, &mut files);
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_secs());
+        let mut read = 0;
+        let mut tracks = HashMap::with_capacity(files.len());
+        for path in files {
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                continue;
+            };
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |t| t.as_secs());
+            let size = metadata.len();
+
+            let known = self.tracks.remove(&path);
+            let track = match known {
+                Some(track) if track.modified == modified && track.size == size => track,
+                _ => {
+                    read += 1;
+                    let added = known.map_or(now, |track| track.added);
+                    let info = TrackInfo::read(&path).unwrap_or_else(|e| {
+                        debug!("Could not read tags of {:?}: {}", path, e);
+                        TrackInfo::default()
+                    });
+                    CachedTrack {
+                        path: path.clone(),
+                        modified,
+                        size,
+                        added,
+                        info,
+                    }
+                }
+            };
+            tracks.insert(path, track);
+        }
+        self.tracks = tracks;
+        read
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&TrackInfo> {
+        self.tracks.get(path).map(|track| &track.info)
+    }
+
+    pub fn tracks(&self) -> impl Iterator<Item = (&Path, &TrackInfo)> {
+        self.tracks
+            .values()
+            .map(|track| (track.path.as_path(), &track.info))
+    }
+
+    /// The `limit` files that were indexed last, newest first
+    pub fn recently_added(&self, limit: usize) -> Vec<PathBuf> {
+        let mut tracks: Vec<&CachedTrack> = self.tracks.values().collect();
+        // Everything found by the first index has the same time, the
+        // modification time orders those
+        tracks.sort_by(|a, b| (b.added, b.modified, &a.path).cmp(&(a.added, a.modified, &b.path)));
+        tracks
+            .into_iter()
+            .take(limit)
+            .map(|track| track.path.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+/// Collects audio files below `dir`, skipping hidden and unreadable entries
+fn find_audio_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Could not index {:?}: {}", dir, e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            find_audio_files(&path, files);
+        } else if is_audio_file(&path) {
+            files.push(path);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LibraryEvent {
+    /// Files under the roots were added, changed or removed and the changes
+    /// have settled, the index follows with [`LibraryEvent::Indexed`]
+    FilesChanged,
+    Indexed(Library),
+}
+
+/// Loads the cache, brings it up to date with the files under `roots` and
+/// sends the result once it's done. After that the roots are watched and
+/// indexed again once changes have stopped for `debounce`.
+pub fn spawn_indexer(
+    roots: Vec<PathBuf>,
+    cache_path: PathBuf,
+    debounce: Duration,
+    events: mpsc::Sender<LibraryEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // Watching starts first so nothing copied while indexing is missed
+        let (change_tx, mut change_rx) = mpsc::unbounded_channel();
+        let _watcher = match watcher::watch(&roots, change_tx) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                warn!("Not watching the audio roots for changes: {}", e);
+                None
+            }
+        };
+
+        let mut library = None;
+        loop {
+            let roots = roots.clone();
+            let cache_path = cache_path.clone();
+            let indexed = tokio::task::spawn_blocking(move || {
+                let mut library = library.unwrap_or_else(|| Library::load(&cache_path));
+                let known = library.len();
+                let read = library.index(&roots);
+                info!("Indexed {} tracks, read tags of {}", library.len(), read);
+                if read > 0 || library.len() != known {
+                    if let Err(e) = library.save(&cache_path) {
+                        warn!(
+                            "Could not save the library cache to {:?}: {}",
+                            cache_path, e
+                        );
+                    }
+                }
+                library
+            })
+            .await;
+            let Ok(indexed) = indexed else {
+                return;
+            };
+            if events
+                .send(LibraryEvent::Indexed(indexed.clone()))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            library = Some(indexed);
+
+            if !watcher::settled(&mut change_rx, debounce).await {
+                return;
+            }
+            debug!("Audio roots changed, indexing again");
+            if events.send(LibraryEvent::FilesChanged).await.is_err() {
+                return;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A FLAC file with only the STREAMINFO and VORBIS_COMMENT blocks, enough
+    /// for the tags and the duration to be read
+    fn flac(comments: &[&str], seconds: u64) -> Vec<u8> {
+        let mut data = b"fLaC".to_vec();
+
+        data.extend([0x00, 0x00, 0x00, 34]);
+        data.extend(4096u16.to_be_bytes());
+        data.extend(4096u16.to_be_bytes());
+        data.extend([0; 6]);
+        // 20 bits sample rate, 3 bits channels - 1, 5 bits bits per sample - 1,
+        // 36 bits total samples
+        let sample_rate: u64 = 44100;
+        let packed = (sample_rate << 44) | (1 << 41) | (15 << 36) | (sample_rate * seconds);
+        data.extend(packed.to_be_bytes());
+        data.extend([0; 16]);
+
+        let mut block = Vec::new();
+        let vendor = b"test";
+        block.extend((vendor.len() as u32).to_le_bytes());
+        block.extend(vendor);
+        block.extend((comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            block.extend((comment.len() as u32).to_le_bytes());
+            block.extend(comment.as_bytes());
+        }
+        data.push(0x80 | 4);
+        data.extend(&(block.len() as u32).to_be_bytes()[1..]);
+        data.extend(block);
+        data
+    }
+
+    #[test]
+    fn test_read_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        std::fs::write(
+            &path,
+            flac(
+                &[
+                    "TITLE=Björk Song",
+                    "ARTIST=Someone",
+                    "ALBUM=Debut",
+                    "TRACKNUMBER=3",
+                    "GENRE=Pop",
+                ],
+                95,
+            ),
+        )
+        .unwrap();
+
+        let info = TrackInfo::read(&path).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Björk Song"));
+        assert_eq!(info.album.as_deref(), Some("Debut"));
+        assert_eq!(info.track_number, Some(3));
+        assert_eq!(info.genre.as_deref(), Some("Pop"));
+        assert_eq!(info.duration, Some(95));
+        assert_eq!(info.display_name().as_deref(), Some("Someone - Björk Song"));
+    }
+
+    #[test]
+    fn test_index_uses_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("music");
+        std::fs::create_dir_all(root.join("Album")).unwrap();
+        let first = root.join("Album").join("01.flac");
+        let second = root.join("02.flac");
+        std::fs::write(&first, flac(&["TITLE=One"], 10)).unwrap();
+        std::fs::write(&second, flac(&["TITLE=Two"], 10)).unwrap();
+        std::fs::write(root.join("cover.jpg"), b"").unwrap();
+        std::fs::write(root.join("broken.mp3"), b"not audio").unwrap();
+
+        let cache = dir.path().join("library.json");
+        let roots = vec![root.clone()];
+        let mut library = Library::load(&cache);
+        assert_eq!(library.index(&roots), 3);
+        assert_eq!(library.len(), 3);
+        assert_eq!(library.get(&first).unwrap().title.as_deref(), Some("One"));
+        assert_eq!(
+            library.get(&root.join("broken.mp3")),
+            Some(&TrackInfo::default())
+        );
+        library.save(&cache).unwrap();
+
+        // Only the changed file is read again, the removed one is dropped
+        std::fs::write(&first, flac(&["TITLE=Uno", "ARTIST=Someone"], 10)).unwrap();
+        std::fs::remove_file(&second).unwrap();
+        let mut library = Library::load(&cache);
+        assert_eq!(library.len(), 3);
+        assert_eq!(library.index(&roots), 1);
+        assert_eq!(library.len(), 2);
+        assert_eq!(
+            library.get(&first).unwrap().display_name().as_deref(),
+            Some("Someone - Uno")
+        );
+        assert!(library.get(&second).is_none());
+    }
+
+    #[test]
+    fn test_recently_added() {
+        let track = |path: &str, added, modified| {
+            let track = CachedTrack {
+                path: PathBuf::from(path),
+                modified,
+                size: 0,
+                added,
+                info: TrackInfo::default(),
+            };
+            (track.path.clone(), track)
+        };
+        let library = Library {
+            tracks: HashMap::from([
+                track("old.mp3", 100, 5),
+                track("first.mp3", 200, 10),
+                track("second.mp3", 200, 20),
+                track("third.mp3", 200, 20),
+            ]),
+        };
+        assert_eq!(
+            library.recently_added(3),
+            ["second.mp3", "third.mp3", "first.mp3"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_outdated_cache_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("library.json");
+        std::fs::write(&cache, r#"{"version":0,"tracks":[]}"#).unwrap();
+        assert!(Library::load(&cache).is_empty());
+        std::fs::write(&cache, "garbage").unwrap();
+        assert!(Library::load(&cache).is_empty());
+    }
+}
@@ -1,9 +1,55 @@
 use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::time::Duration;
 use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
 
+pub mod ipc;
+pub mod modes;
+pub mod resume;
+pub mod speed;
+
+use crate::{config::StorageConfig, radio};
+use ipc::MpvIpc;
+use modes::{shuffle_moves, PlaybackModes, Repeat, ShuffleRng};
+use resume::{Bookmarks, ResumeState};
+use speed::Speeds;
+
+/// How long mpv gets to create its IPC socket after being started
+const SOCKET_WAIT: Duration = Duration::from_secs(3);
+
+/// Seconds into a file after which [`MpvRequest::Previous`] restarts it
+const PREVIOUS_RESTART_SECS: u32 = 3;
+
+/// How often the position is saved while playing. Other changes to what is
+/// playing are saved right away.
+const RESUME_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Longest wait between attempts to reconnect to a stream that dropped
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// How long a stream's server gets to answer before reconnecting
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Properties that make up [`MpvEvent::StatusUpdate`] and
+/// [`MpvEvent::QueueUpdate`]
+const OBSERVED_PROPERTIES: [&str; 10] = [
+    "pause",
+    "time-pos",
+    "duration",
+    "filename",
+    "path",
+    "playlist",
+    "chapter",
+    "chapter-list",
+    "speed",
+    "metadata/by-key/icy-title",
+];
+
 #[derive(Debug, Clone)]
 pub enum MpvEvent {
     Error(String),
@@ -12,191 +58,1566 @@ pub enum MpvEvent {
         position: u32,
         duration: u32,
         filename: Option<String>,
+        /// The file as it was loaded, for looking up its tags
+        path: Option<PathBuf>,
+        chapters: Vec<Chapter>,
+        /// Index of the current chapter, `None` before the first one
+        chapter: Option<usize>,
+        speed: f64,
+        /// What a radio station says is playing
+        stream_title: Option<String>,
+    },
+    FileLoaded,
+    /// Playback of a file stopped, `reason` is mpv's (eof, stop, error, ...)
+    TrackEnded {
+        reason: String,
     },
+    /// mpv's playlist changed, `current` is the index of the playing file
+    QueueUpdate {
+        files: Vec<PathBuf>,
+        current: Option<usize>,
+    },
+    ModesUpdate {
+        shuffle: bool,
+        repeat: Repeat,
+    },
+    /// Something was playing when the player last stopped, sent on startup
+    ResumeAvailable {
+        file: PathBuf,
+        position: u32,
+    },
+    /// Bookmarks of the playing file in seconds, sorted
+    BookmarksUpdate {
+        positions: Vec<u32>,
+    },
+    /// The stream dropped and `attempt` counts the tries to get it back,
+    /// `None` once it plays again or was given up on
+    Reconnecting {
+        attempt: Option<u32>,
+    },
+}
+
+/// Something mpv can play
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaSource {
+    File(PathBuf),
+    /// An HTTP(S) URL, reconnected to when it drops
+    Stream(String),
+}
+
+/// A chapter of the playing file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    /// Empty for untitled chapters
+    pub title: String,
+    /// Seconds from the start of the file
+    pub start: u32,
 }
 
 #[derive(Debug, Clone)]
 pub enum MpvRequest {
-    Play(PathBuf),
-    GetStatus,
+    /// Replaces the queue with a single file or stream and starts playing it
+    Play(MediaSource),
     TogglePause,
+    /// Pauses playback, does nothing if it already is
+    Pause,
+    /// Appends a file to the end of the queue
+    Enqueue(PathBuf),
+    /// Inserts a file right after the one that is playing
+    PlayNext(PathBuf),
+    /// Replaces the queue with these files, shuffled when shuffle is on
+    PlayAll(Vec<PathBuf>),
+    /// Appends these files to the queue
+    EnqueueAll(Vec<PathBuf>),
+    /// Removes everything from the queue except the playing file
+    ClearQueue,
+    MoveInQueue {
+        from: usize,
+        to: usize,
+    },
+    RemoveFromQueue(usize),
+    /// Plays the entry of the queue at this index
+    PlayFromQueue(usize),
+    Next,
+    /// Restarts the current file, or goes to the previous one if playback
+    /// only just started
+    Previous,
+    /// Seeks by this many seconds, negative values go back
+    SeekRelative(f64),
+    /// Seeks to this many seconds from the start of the file
+    SeekAbsolute(f64),
+    /// Stops playback and clears the queue, mpv keeps running idle
+    Stop,
+    ToggleShuffle,
+    /// Goes from off to repeat all to repeat one and back to off
+    CycleRepeat,
+    /// Loads the queue that was playing before the restart and continues
+    /// where it stopped
+    Resume,
+    /// Continues what played last, unlike [`MpvRequest::Resume`] as often as
+    /// asked. Only unpauses if that queue is still loaded
+    ResumeLast,
+    /// Bookmarks the current position of the playing file
+    AddBookmark,
+    /// Removes a bookmark of the playing file by its index
+    RemoveBookmark(usize),
+    NextChapter,
+    /// Goes to the start of the chapter, or to the previous one if it only
+    /// just started
+    PreviousChapter,
+    /// Plays the current file at this speed from now on, kept between
+    /// [`speed::SPEEDS`]
+    SetSpeed(f64),
+}
+
+/// The player status as last reported by mpv's property observers
+#[derive(Debug, Clone, PartialEq)]
+struct Status {
+    paused: bool,
+    position: u32,
+    duration: u32,
+    filename: Option<String>,
+    path: Option<PathBuf>,
+    chapters: Vec<Chapter>,
+    chapter: Option<usize>,
+    speed: f64,
+    stream_title: Option<String>,
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self {
+            paused: true,
+            position: 0,
+            duration: 0,
+            filename: None,
+            path: None,
+            chapters: Vec::new(),
+            chapter: None,
+            speed: 1.0,
+            stream_title: None,
+        }
+    }
+}
+
+impl Status {
+    /// Applies a `property-change` event, returns true if anything shown on
+    /// screen changed
+    fn apply(&mut self, name: &str, data: Option<&Value>) -> bool {
+        let before = self.clone();
+        match name {
+            "pause" => self.paused = data.and_then(Value::as_bool).unwrap_or(true),
+            "time-pos" => self.position = data.and_then(Value::as_f64).unwrap_or(0.0) as u32,
+            "duration" => self.duration = data.and_then(Value::as_f64).unwrap_or(0.0) as u32,
+            "filename" => self.filename = data.and_then(Value::as_str).map(str::to_string),
+            "path" => self.path = data.and_then(Value::as_str).map(PathBuf::from),
+            // -1 before the first chapter
+            "chapter" => {
+                self.chapter = data
+                    .and_then(Value::as_i64)
+                    .and_then(|c| usize::try_from(c).ok())
+            }
+            "chapter-list" => self.chapters = parse_chapters(data),
+            "speed" => self.speed = data.and_then(Value::as_f64).unwrap_or(1.0),
+            "metadata/by-key/icy-title" => {
+                self.stream_title = data
+                    .and_then(Value::as_str)
+                    .map(|title| title.trim().to_string())
+                    .filter(|title| !title.is_empty())
+            }
+            _ => {}
+        }
+        *self != before
+    }
+
+    fn event(&self) -> MpvEvent {
+        MpvEvent::StatusUpdate {
+            is_playing: !self.paused,
+            position: self.position,
+            duration: self.duration,
+            filename: self.filename.clone(),
+            path: self.path.clone(),
+            chapters: self.chapters.clone(),
+            chapter: self.chapter,
+            speed: self.speed,
+            stream_title: self.stream_title.clone(),
+        }
+    }
 }
 
 pub struct MpvManager {
-    event_channel: tokio::sync::mpsc::Sender<MpvEvent>,
-    request_channel: tokio::sync::mpsc::Receiver<MpvRequest>,
+    event_channel: mpsc::Sender<MpvEvent>,
+    request_channel: mpsc::Receiver<MpvRequest>,
     mpv_process: Option<Child>,
-    socket_path: String,
+    ipc: Option<MpvIpc>,
+    ipc_events: Option<mpsc::UnboundedReceiver<Value>>,
+    status: Status,
+    queue: Vec<PathBuf>,
+    queue_position: Option<usize>,
+    modes: PlaybackModes,
+    modes_path: PathBuf,
+    rng: ShuffleRng,
+    socket_path: PathBuf,
+    /// What was playing before the restart until it's resumed
+    resume_offer: Option<ResumeState>,
+    /// The state as last saved
+    resume: ResumeState,
+    resume_path: PathBuf,
+    resume_saved: Instant,
+    /// Position to seek to once the resumed file is loaded
+    pending_seek: Option<u32>,
+    bookmarks: Bookmarks,
+    bookmarks_path: PathBuf,
+    speeds: Speeds,
+    speeds_path: PathBuf,
+    /// The stream played last, until something else is played
+    stream: Option<String>,
+    /// When to try the stream again after it dropped
+    reconnect_at: Option<Instant>,
+    /// Attempts to reconnect since the stream last played
+    stream_retries: u32,
 }
 
 impl MpvManager {
     pub async fn new(
-        event_channel: tokio::sync::mpsc::Sender<MpvEvent>,
-        request_channel: tokio::sync::mpsc::Receiver<MpvRequest>,
+        event_channel: mpsc::Sender<MpvEvent>,
+        request_channel: mpsc::Receiver<MpvRequest>,
+        socket_path: PathBuf,
+        storage: &StorageConfig,
     ) -> Result<Self> {
+        let modes_path = storage.player_modes_path();
+        let modes = PlaybackModes::load(&modes_path).unwrap_or_else(|e| {
+            warn!("Could not read playback modes from {:?}: {}", modes_path, e);
+            PlaybackModes::default()
+        });
+        let resume_path = storage.resume_path();
+        let resume = ResumeState::load(&resume_path).unwrap_or_else(|e| {
+            warn!(
+                "Could not read the resume state from {:?}: {}",
+                resume_path, e
+            );
+            ResumeState::default()
+        });
+        let bookmarks_path = storage.bookmarks_path();
+        let bookmarks = Bookmarks::load(&bookmarks_path).unwrap_or_else(|e| {
+            warn!("Could not read bookmarks from {:?}: {}", bookmarks_path, e);
+            Bookmarks::default()
+        });
+        let speeds_path = storage.speeds_path();
+        let speeds = Speeds::load(&speeds_path).unwrap_or_else(|e| {
+            warn!(
+                "Could not read playback speeds from {:?}: {}",
+                speeds_path, e
+            );
+            Speeds::default()
+        });
         Ok(Self {
             event_channel,
             request_channel,
             mpv_process: None,
-            socket_path: "/tmp/mpvsocket".to_string(),
+            ipc: None,
+            ipc_events: None,
+            status: Status::default(),
+            queue: Vec::new(),
+            queue_position: None,
+            rng: ShuffleRng::new(modes.seed),
+            modes,
+            modes_path,
+            socket_path,
+            resume_offer: resume.current_file().is_some().then(|| resume.clone()),
+            resume,
+            resume_path,
+            resume_saved: Instant::now(),
+            pending_seek: None,
+            bookmarks,
+            bookmarks_path,
+            speeds,
+            speeds_path,
+            stream: None,
+            reconnect_at: None,
+            stream_retries: 0,
         })
     }
 
-    pub async fn process_requests(&mut self) -> Result<()> {
-        while let Ok(request) = self.request_channel.try_recv() {
-            info!("Processing MPV request: {:?}", request);
-            match request {
-                MpvRequest::Play(path_buf) => {
-                    self.toggle_play_pause(&path_buf).await?;
-                }
-                MpvRequest::GetStatus => {
-                    self.get_status().await?;
+    /// Handles requests and mpv events as they arrive, returns once the
+    /// request channel closes
+    pub async fn run(&mut self) -> Result<()> {
+        self.send(self.modes_event()).await;
+        if let Some(offer) = &self.resume_offer {
+            if let Some(file) = offer.current_file() {
+                let event = MpvEvent::ResumeAvailable {
+                    file: file.to_path_buf(),
+                    position: offer.position,
+                };
+                self.send(event).await;
+            }
+        }
+        loop {
+            tokio::select! {
+                request = self.request_channel.recv() => {
+                    let Some(request) = request else {
+                        return Ok(());
+                    };
+                    if let Err(e) = self.process_request(request).await {
+                        error!("Error processing MPV request: {}", e);
+                    }
                 }
-                MpvRequest::TogglePause => {
-                    self.toggle_pause().await?;
+                event = next_event(&mut self.ipc_events) => match event {
+                    Some(event) => self.handle_ipc_event(event).await,
+                    None => self.handle_disconnect().await,
+                },
+                _ = wait_until(self.reconnect_at) => {
+                    if let Err(e) = self.reconnect_stream().await {
+                        error!("Reconnecting to the stream failed: {}", e);
+                    }
                 }
-            };
+            }
         }
-        Ok(())
     }
 
-    async fn play(&mut self, path: &Path) -> Result<()> {
-        debug!("Playing a new file {:?}", path);
-        match Command::new("mpv")
-            .arg("--audio-buffer=0.5")
-            .arg("--input-ipc-server=/tmp/mpvsocket")
-            .arg(path)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()
-        {
-            Ok(child) => {
-                self.mpv_process = Some(child);
+    async fn process_request(&mut self, request: MpvRequest) -> Result<()> {
+        info!("Processing MPV request: {:?}", request);
+        match request {
+            MpvRequest::Play(MediaSource::File(path_buf)) => {
+                self.load_file(&path_buf, "replace").await?;
+            }
+            MpvRequest::Play(MediaSource::Stream(url)) => {
+                self.stream = Some(url.clone());
+                self.reconnect_at = None;
+                self.stream_retries = 0;
+                self.load_file(Path::new(&url), "replace").await?;
+            }
+            MpvRequest::TogglePause => {
+                self.toggle_pause().await?;
+            }
+            MpvRequest::Pause => {
+                if let Some(ipc) = &self.ipc {
+                    ipc.set_property("pause", true).await?;
+                }
+            }
+            MpvRequest::Enqueue(path_buf) => {
+                self.enqueue(&path_buf).await?;
+            }
+            MpvRequest::PlayNext(path_buf) => {
+                self.play_next(&path_buf).await?;
+            }
+            MpvRequest::PlayAll(mut paths) => {
+                if self.modes.shuffle {
+                    self.rng.shuffle(&mut paths);
+                }
+                for (i, path) in paths.iter().enumerate() {
+                    self.load_file(path, if i == 0 { "replace" } else { "append" })
+                        .await?;
+                }
             }
-            Err(e) => {
-                error!("Starting the MPV process failed with error {}", e);
+            MpvRequest::EnqueueAll(paths) => {
+                self.enqueue_all(&paths).await?;
+            }
+            MpvRequest::ClearQueue => {
+                self.command(&[json!("playlist-clear")]).await?;
+            }
+            MpvRequest::MoveInQueue { from, to } => {
+                self.move_in_queue(from, to).await?;
+            }
+            MpvRequest::RemoveFromQueue(index) => {
+                self.command(&[json!("playlist-remove"), json!(index)])
+                    .await?;
+            }
+            MpvRequest::PlayFromQueue(index) => {
+                self.command(&[json!("playlist-play-index"), json!(index)])
+                    .await?;
+            }
+            MpvRequest::Next => {
+                self.command(&[json!("playlist-next")]).await?;
+            }
+            MpvRequest::Previous => {
+                if self.status.position > PREVIOUS_RESTART_SECS || self.queue_position == Some(0) {
+                    self.seek(0.0, "absolute").await?;
+                } else {
+                    self.command(&[json!("playlist-prev")]).await?;
+                }
+            }
+            MpvRequest::SeekRelative(seconds) => {
+                self.seek(seconds, "relative").await?;
+            }
+            MpvRequest::SeekAbsolute(seconds) => {
+                self.seek(seconds, "absolute").await?;
+            }
+            MpvRequest::Stop => {
+                self.give_up_stream().await;
+                self.command(&[json!("stop")]).await?;
+            }
+            MpvRequest::ToggleShuffle => {
+                self.modes.toggle_shuffle();
+                self.rng = ShuffleRng::new(self.modes.seed);
+                self.modes_changed().await;
+                if self.modes.shuffle && self.ipc.is_some() {
+                    let start = self.queue_position.map_or(0, |position| position + 1);
+                    self.shuffle_queue(self.queue.len(), start).await?;
+                }
+            }
+            MpvRequest::CycleRepeat => {
+                self.modes.repeat = self.modes.repeat.next();
+                self.modes_changed().await;
+                if self.ipc.is_some() {
+                    self.apply_repeat().await?;
+                }
+            }
+            MpvRequest::Resume => {
+                if let Some(saved) = self.resume_offer.take() {
+                    self.resume_queue(saved).await?;
+                }
+            }
+            MpvRequest::ResumeLast => {
+                self.resume_offer = None;
+                self.resume_last().await?;
+            }
+            MpvRequest::AddBookmark => {
+                if let Some(path) = self.status.path.clone() {
+                    if self.bookmarks.add(&path, self.status.position) {
+                        self.bookmarks_changed().await;
+                    }
+                }
+            }
+            MpvRequest::RemoveBookmark(index) => {
+                if let Some(path) = self.status.path.clone() {
+                    if self.bookmarks.remove(&path, index) {
+                        self.bookmarks_changed().await;
+                    }
+                }
+            }
+            MpvRequest::NextChapter => {
+                self.command(&[json!("add"), json!("chapter"), json!(1)])
+                    .await?;
+            }
+            MpvRequest::PreviousChapter => {
+                self.command(&[json!("add"), json!("chapter"), json!(-1)])
+                    .await?;
+            }
+            MpvRequest::SetSpeed(speed) => {
+                let speed = speed::clamp(speed);
+                self.set_speed(speed).await?;
+                if let Some(path) = &self.status.path {
+                    self.speeds.set(path, speed);
+                    if let Err(e) = self.speeds.save(&self.speeds_path) {
+                        error!(
+                            "Could not save playback speeds to {:?}: {}",
+                            self.speeds_path, e
+                        );
+                    }
+                }
             }
         };
+        Ok(())
+    }
 
+    async fn load_file(&mut self, path: &Path, mode: &str) -> Result<()> {
+        debug!("Loading {:?} ({})", path, mode);
+        self.ensure_started().await?;
+        self.command(&[json!("loadfile"), json!(path), json!(mode)])
+            .await?;
         Ok(())
     }
 
-    async fn toggle_play_pause(&mut self, path: &Path) -> Result<()> {
-        match &self.mpv_process {
-            Some(_) => {
-                debug!("A process exists, trying to pause");
-                let output = Command::new("bash")
-                    .arg("-c")
-                    .arg("echo '{ \"command\": [\"cycle\", \"pause\"] }' | socat - /tmp/mpvsocket")
-                    .output()
-                    .await?;
-                debug!("{}", String::from_utf8_lossy(&output.stdout).to_string());
-            }
-            None => {
-                self.play(path).await?;
-            }
+    /// Appends a file to the queue, or with shuffle on puts it at a random
+    /// spot among the files that have not been played yet
+    async fn enqueue(&mut self, path: &Path) -> Result<()> {
+        // append-play starts playback if mpv is idle
+        self.load_file(path, "append-play").await?;
+        let (true, Some(position)) = (self.modes.shuffle, self.queue_position) else {
+            return Ok(());
         };
+        let appended = self.playlist_count().await?.saturating_sub(1);
+        if appended <= position {
+            return Ok(());
+        }
+        let target = position + 1 + self.rng.below(appended - position);
+        if target != appended {
+            self.command(&[json!("playlist-move"), json!(appended), json!(target)])
+                .await?;
+        }
         Ok(())
     }
 
-    async fn send_command(&self, command: &str) -> Result<String> {
-        debug!("Sending MPV command: {}", command);
+    /// Appends several files at once. With shuffle on they are mixed in
+    /// with the files that have not been played yet.
+    async fn enqueue_all(&mut self, paths: &[PathBuf]) -> Result<()> {
+        for path in paths {
+            self.load_file(path, "append-play").await?;
+        }
+        if self.modes.shuffle {
+            // When idle the first appended file starts playing and stays first
+            let len = self.playlist_count().await?;
+            let start = self
+                .queue_position
+                .map_or((len + 1).saturating_sub(paths.len()), |position| {
+                    position + 1
+                });
+            self.shuffle_queue(len, start).await?;
+        }
+        Ok(())
+    }
 
-        let bash_command = format!("echo '{}' | socat - {}", command, self.socket_path);
-        debug!("Full bash command: {}", bash_command);
+    /// Shuffles the first `len` entries of the queue from `start` onwards
+    async fn shuffle_queue(&mut self, len: usize, start: usize) -> Result<()> {
+        for (from, to) in shuffle_moves(len, start, &mut self.rng) {
+            self.command(&[json!("playlist-move"), json!(from), json!(to)])
+                .await?;
+        }
+        Ok(())
+    }
 
-        let output = Command::new("bash")
-            .arg("-c")
-            .arg(&bash_command)
-            .output()
-            .await?;
+    async fn apply_repeat(&self) -> Result<()> {
+        let (loop_file, loop_playlist) = match self.modes.repeat {
+            Repeat::Off => ("no", "no"),
+            Repeat::One => ("inf", "no"),
+            Repeat::All => ("no", "inf"),
+        };
+        let ipc = self
+            .ipc
+            .as_ref()
+            .ok_or_else(|| anyhow!("mpv is not running"))?;
+        ipc.set_property("loop-file", loop_file).await?;
+        ipc.set_property("loop-playlist", loop_playlist).await?;
+        Ok(())
+    }
 
-        if output.status.success() {
-            let response = String::from_utf8_lossy(&output.stdout).to_string();
-            debug!("MPV command successful, response: {}", response.trim());
-            Ok(response)
-        } else {
-            let error_msg = String::from_utf8_lossy(&output.stderr);
-            error!("MPV command failed: {}", error_msg);
-            Err(anyhow!("MPV command failed: {}", error_msg))
+    async fn modes_changed(&self) {
+        if let Err(e) = self.modes.save(&self.modes_path) {
+            error!(
+                "Could not save playback modes to {:?}: {}",
+                self.modes_path, e
+            );
         }
+        self.send(self.modes_event()).await;
     }
 
-    async fn get_status(&self) -> Result<()> {
-        if self.mpv_process.is_none() {
+    fn modes_event(&self) -> MpvEvent {
+        MpvEvent::ModesUpdate {
+            shuffle: self.modes.shuffle,
+            repeat: self.modes.repeat,
+        }
+    }
+
+    /// Loads a saved queue without playing it, then plays the saved file
+    /// and seeks once it's loaded
+    async fn resume_queue(&mut self, saved: ResumeState) -> Result<()> {
+        let Some(current) = saved.current.filter(|&i| i < saved.queue.len()) else {
             return Ok(());
+        };
+        info!("Resuming {:?} at {}s", saved.current_file(), saved.position);
+        self.ensure_started().await?;
+        self.command(&[json!("stop")]).await?;
+        for path in &saved.queue {
+            self.load_file(path, "append").await?;
         }
+        self.pending_seek = (saved.position > 0).then_some(saved.position);
+        self.command(&[json!("playlist-play-index"), json!(current)])
+            .await?;
+        Ok(())
+    }
 
-        let mut is_playing = false;
-        let mut position = 0u32;
-        let mut duration = 0u32;
-        let mut filename: Option<String> = None;
+    /// Loads [`Self::resume`] again unless its queue is still loaded
+    async fn resume_last(&mut self) -> Result<()> {
+        let loaded = self.queue_position.is_some() && self.queue == self.resume.queue;
+        match &self.ipc {
+            Some(ipc) if loaded => Ok(ipc.set_property("pause", false).await?),
+            _ => self.resume_queue(self.resume.clone()).await,
+        }
+    }
 
-        if let Ok(pause_response) = self.send_command(r#"{ "command": ["get_property", "pause"] }"#).await {
-            if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&pause_response) {
-                if let Some(data) = parsed.get("data") {
-                    is_playing = !data.as_bool().unwrap_or(true);
-                }
-            }
+    /// Saves what is playing. Changes of the position alone are only saved
+    /// every [`RESUME_SAVE_INTERVAL`] or when pausing.
+    fn remember(&mut self, force: bool) {
+        let playing = self.queue_position.and_then(|i| self.queue.get(i));
+        let same_file = playing.map(PathBuf::as_path) == self.resume.current_file();
+        let state = ResumeState {
+            queue: self.queue.clone(),
+            current: self.queue_position,
+            // The position may still be the one of the previous file
+            position: if same_file { self.status.position } else { 0 },
+        };
+        let only_position =
+            state.queue == self.resume.queue && state.current == self.resume.current;
+        if state == self.resume
+            || (only_position && !force && self.resume_saved.elapsed() < RESUME_SAVE_INTERVAL)
+        {
+            return;
+        }
+        if let Err(e) = state.save(&self.resume_path) {
+            error!(
+                "Could not save the resume state to {:?}: {}",
+                self.resume_path, e
+            );
         }
+        self.resume = state;
+        self.resume_saved = Instant::now();
+    }
 
-        if let Ok(pos_response) = self.send_command(r#"{ "command": ["get_property", "time-pos"] }"#).await {
-            if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&pos_response) {
-                if let Some(data) = parsed.get("data") {
-                    position = data.as_f64().unwrap_or(0.0) as u32;
+    async fn set_speed(&self, speed: f64) -> Result<()> {
+        let ipc = self
+            .ipc
+            .as_ref()
+            .ok_or_else(|| anyhow!("mpv is not running"))?;
+        ipc.set_property("speed", speed).await?;
+        Ok(())
+    }
+
+    async fn bookmarks_changed(&self) {
+        if let Err(e) = self.bookmarks.save(&self.bookmarks_path) {
+            error!(
+                "Could not save bookmarks to {:?}: {}",
+                self.bookmarks_path, e
+            );
+        }
+        self.send(self.bookmarks_event()).await;
+    }
+
+    /// Applies the speed remembered for the new file and sends its bookmarks
+    async fn file_changed(&mut self) {
+        // Playing something else gives up on the stream
+        let other = self.status.path.as_ref().is_some_and(|path| {
+            self.stream
+                .as_deref()
+                .is_some_and(|stream| path != Path::new(stream))
+        });
+        if other {
+            self.give_up_stream().await;
+        }
+        if let Some(path) = &self.status.path {
+            let speed = self.speeds.get(path);
+            if speed != self.status.speed {
+                if let Err(e) = self.set_speed(speed).await {
+                    error!("Setting the speed of {:?} failed: {}", path, e);
                 }
             }
         }
+        self.send(self.bookmarks_event()).await;
+    }
+
+    fn bookmarks_event(&self) -> MpvEvent {
+        let positions = match &self.status.path {
+            Some(path) => self.bookmarks.get(path).to_vec(),
+            None => Vec::new(),
+        };
+        MpvEvent::BookmarksUpdate { positions }
+    }
+
+    async fn play_next(&mut self, path: &Path) -> Result<()> {
+        let Some(position) = self.queue_position else {
+            return self.load_file(path, "append-play").await;
+        };
+        self.load_file(path, "append").await?;
+        // The file was appended at the end of the playlist
+        let appended = self.playlist_count().await?.saturating_sub(1);
+        self.command(&[json!("playlist-move"), json!(appended), json!(position + 1)])
+            .await?;
+        Ok(())
+    }
+
+    /// The length of mpv's playlist right now, `queue` only catches up once
+    /// mpv reports the change
+    async fn playlist_count(&self) -> Result<usize> {
+        let ipc = self
+            .ipc
+            .as_ref()
+            .ok_or_else(|| anyhow!("mpv is not running"))?;
+        Ok(ipc.get_property("playlist-count").await?)
+    }
+
+    async fn move_in_queue(&self, from: usize, to: usize) -> Result<()> {
+        // playlist-move inserts before the target, so moving down needs to
+        // point one past the destination
+        let target = if to > from { to + 1 } else { to };
+        self.command(&[json!("playlist-move"), json!(from), json!(target)])
+            .await?;
+        Ok(())
+    }
+
+    async fn seek(&self, seconds: f64, mode: &str) -> Result<()> {
+        self.command(&[json!("seek"), json!(seconds), json!(mode)])
+            .await?;
+        Ok(())
+    }
+
+    async fn command(&self, args: &[Value]) -> Result<Value> {
+        match &self.ipc {
+            Some(ipc) => Ok(ipc.command(args).await?),
+            None => Err(anyhow!("mpv is not running")),
+        }
+    }
+
+    /// Starts an idle mpv unless one is already connected. mpv keeps running
+    /// after the queue runs out so that later requests can reuse it, but it
+    /// can still crash or be quit from outside.
+    async fn ensure_started(&mut self) -> Result<()> {
+        let exited = match self.mpv_process.as_mut() {
+            Some(process) => !matches!(process.try_wait(), Ok(None)),
+            None => false,
+        };
+        if exited {
+            info!("mpv exited, restarting it");
+            self.mpv_process = None;
+            self.ipc = None;
+            self.ipc_events = None;
+        }
+        if self.ipc.as_ref().is_some_and(MpvIpc::is_connected) {
+            return Ok(());
+        }
+
+        debug!("Starting mpv");
+        if let Some(mut process) = self.mpv_process.take() {
+            let _ = process.start_kill();
+        }
+        let child = Command::new("mpv")
+            .arg("--idle=yes")
+            .arg("--audio-buffer=0.5")
+            .arg(format!("--input-ipc-server={}", self.socket_path.display()))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow!("Starting the MPV process failed with error {}", e))?;
+        self.mpv_process = Some(child);
+
+        let (ipc, events) = self.connect().await?;
+        for (id, name) in OBSERVED_PROPERTIES.iter().enumerate() {
+            ipc.command(&[json!("observe_property"), json!(id + 1), json!(name)])
+                .await?;
+        }
+        self.ipc = Some(ipc);
+        self.ipc_events = Some(events);
+        self.apply_repeat().await
+    }
 
-        if let Ok(dur_response) = self.send_command(r#"{ "command": ["get_property", "duration"] }"#).await {
-            if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&dur_response) {
-                if let Some(data) = parsed.get("data") {
-                    duration = data.as_f64().unwrap_or(0.0) as u32;
+    /// Waits for the freshly started mpv to open its socket and connects to it
+    async fn connect(&self) -> Result<(MpvIpc, mpsc::UnboundedReceiver<Value>)> {
+        let deadline = tokio::time::Instant::now() + SOCKET_WAIT;
+        loop {
+            match MpvIpc::connect(&self.socket_path).await {
+                Ok(connection) => return Ok(connection),
+                Err(e) if tokio::time::Instant::now() >= deadline => {
+                    return Err(anyhow!(
+                        "Could not connect to mpv at {:?}: {}",
+                        self.socket_path,
+                        e
+                    ));
                 }
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
             }
         }
+    }
 
-        if let Ok(file_response) = self.send_command(r#"{ "command": ["get_property", "filename"] }"#).await {
-            if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&file_response) {
-                if let Some(data) = parsed.get("data") {
-                    filename = data.as_str().map(|s| s.to_string());
+    async fn handle_ipc_event(&mut self, event: Value) {
+        match event.get("event").and_then(Value::as_str) {
+            Some("property-change") => {
+                let Some(name) = event.get("name").and_then(Value::as_str) else {
+                    return;
+                };
+                if name == "playlist" {
+                    let (files, current) = parse_playlist(event.get("data"));
+                    let wrapped = current == Some(0)
+                        && files.len() > 1
+                        && self.queue_position == Some(files.len() - 1);
+                    self.queue = files.clone();
+                    self.queue_position = current;
+                    self.send(MpvEvent::QueueUpdate { files, current }).await;
+                    self.remember(false);
+                    // Repeat all started over, play the rest in a new order
+                    if wrapped && self.modes.shuffle {
+                        if let Err(e) = self.shuffle_queue(self.queue.len(), 1).await {
+                            error!("Reshuffling the queue failed: {}", e);
+                        }
+                    }
+                } else {
+                    let path = self.status.path.clone();
+                    if self.status.apply(name, event.get("data")) {
+                        self.send(self.status.event()).await;
+                        if self.status.path != path {
+                            self.file_changed().await;
+                        }
+                        self.remember(name == "pause");
+                    }
+                }
+            }
+            Some("file-loaded") => {
+                if self.stream_retries > 0 {
+                    self.stream_retries = 0;
+                    self.send(MpvEvent::Reconnecting { attempt: None }).await;
+                }
+                if let Some(position) = self.pending_seek.take() {
+                    if let Err(e) = self.seek(position as f64, "absolute").await {
+                        error!("Seeking to the resumed position failed: {}", e);
+                    }
                 }
+                self.send(MpvEvent::FileLoaded).await
             }
+            Some("end-file") => {
+                let reason = event
+                    .get("reason")
+                    .and_then(Value::as_str)
+                    .unwrap_or("unknown")
+                    .to_string();
+                if reason == "error" {
+                    let error = event
+                        .get("file_error")
+                        .and_then(Value::as_str)
+                        .unwrap_or("unknown error");
+                    self.send(MpvEvent::Error(error.to_string())).await;
+                }
+                // Streams never end by themselves, the connection dropped
+                if self.stream.is_some() && (reason == "eof" || reason == "error") {
+                    self.schedule_reconnect().await;
+                }
+                self.send(MpvEvent::TrackEnded { reason }).await;
+            }
+            // The position jumps without time-pos crossing a second boundary
+            Some("seek") | Some("playback-restart") => self.send(self.status.event()).await,
+            _ => debug!("Ignoring mpv event {}", event),
+        }
+    }
+
+    /// Tries the stream again after a delay that doubles with every attempt
+    async fn schedule_reconnect(&mut self) {
+        let delay = Duration::from_secs(1 << self.stream_retries.min(6)).min(MAX_RECONNECT_DELAY);
+        self.stream_retries += 1;
+        info!("Stream dropped, reconnecting in {:?}", delay);
+        self.reconnect_at = Some(Instant::now() + delay);
+        self.send(MpvEvent::Reconnecting {
+            attempt: Some(self.stream_retries),
+        })
+        .await;
+    }
+
+    /// Stops reconnecting to the stream, nothing is played from it anymore
+    async fn give_up_stream(&mut self) {
+        self.stream = None;
+        if self.reconnect_at.take().is_some() {
+            self.stream_retries = 0;
+            self.send(MpvEvent::Reconnecting { attempt: None }).await;
         }
+    }
 
-        let event = MpvEvent::StatusUpdate {
-            is_playing,
-            position,
-            duration,
-            filename,
+    /// Loads the stream again if its server answers, waits longer otherwise
+    async fn reconnect_stream(&mut self) -> Result<()> {
+        self.reconnect_at = None;
+        let Some(url) = self.stream.clone() else {
+            return Ok(());
         };
+        if let Err(e) = radio::probe(&url, PROBE_TIMEOUT).await {
+            warn!("{}", e);
+            self.schedule_reconnect().await;
+            return Ok(());
+        }
+        self.load_file(Path::new(&url), "replace").await
+    }
 
-        if let Err(e) = self.event_channel.try_send(event) {
-            debug!("Failed to send status update: {}", e);
+    async fn handle_disconnect(&mut self) {
+        warn!("Lost the connection to mpv");
+        // mpv crashed or was quit, the next request has to start a new one
+        if let Some(mut process) = self.mpv_process.take() {
+            let _ = process.start_kill();
         }
+        self.ipc = None;
+        self.ipc_events = None;
+        self.status = Status::default();
+        self.queue.clear();
+        self.queue_position = None;
+        self.send(self.status.event()).await;
+        self.send(MpvEvent::QueueUpdate {
+            files: Vec::new(),
+            current: None,
+        })
+        .await;
+    }
 
-        Ok(())
+    async fn send(&self, event: MpvEvent) {
+        if let Err(e) = self.event_channel.send(event).await {
+            debug!("Failed to send MPV event: {}", e);
+        }
     }
 
     async fn toggle_pause(&self) -> Result<()> {
-        if self.mpv_process.is_some() {
+        if let Some(ipc) = &self.ipc {
             debug!("Toggling pause state");
-            let _output = Command::new("bash")
-                .arg("-c")
-                .arg("echo '{ \"command\": [\"cycle\", \"pause\"] }' | socat - /tmp/mpvsocket")
-                .output()
-                .await?;
+            ipc.command(&[json!("cycle"), json!("pause")]).await?;
         }
         Ok(())
     }
 }
 
+/// Reads the files and the current index out of mpv's `playlist` property
+fn parse_playlist(data: Option<&Value>) -> (Vec<PathBuf>, Option<usize>) {
+    let entries = data
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let files = entries
+        .iter()
+        .filter_map(|entry| entry.get("filename").and_then(Value::as_str))
+        .map(PathBuf::from)
+        .collect();
+    let current = entries
+        .iter()
+        .position(|entry| entry.get("current").and_then(Value::as_bool) == Some(true));
+    (files, current)
+}
+
+/// Reads the titles and start times out of mpv's `chapter-list` property
+fn parse_chapters(data: Option<&Value>) -> Vec<Chapter> {
+    data.and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|chapter| {
+            let title = chapter.get("title").and_then(Value::as_str);
+            let start = chapter.get("time").and_then(Value::as_f64);
+            Chapter {
+                title: title.unwrap_or_default().trim().to_string(),
+                start: start.unwrap_or(0.0) as u32,
+            }
+        })
+        .collect()
+}
+
+/// Waits until `at`, forever if there is nothing to wait for
+async fn wait_until(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for the next event, forever if there is no connection
+async fn next_event(events: &mut Option<mpsc::UnboundedReceiver<Value>>) -> Option<Value> {
+    match events {
+        Some(events) => events.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
 impl Drop for MpvManager {
     fn drop(&mut self) {
         if let Some(mut process) = self.mpv_process.take() {
-            let _ = process.kill();
+            let _ = process.start_kill();
         }
         // Clean up socket file
         let _ = std::fs::remove_file(&self.socket_path);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_only_changes_on_whole_seconds() {
+        let mut status = Status::default();
+        assert!(status.apply("pause", Some(&json!(false))));
+        assert!(status.apply("time-pos", Some(&json!(1.2))));
+        assert!(!status.apply("time-pos", Some(&json!(1.7))));
+        assert!(status.apply("time-pos", Some(&json!(2.01))));
+        assert!(!status.apply("volume", Some(&json!(50))));
+        assert!(status.apply("filename", Some(&json!("song.mp3"))));
+
+        // Properties become unavailable when the file is unloaded
+        assert!(status.apply("filename", None));
+        assert!(status.apply("pause", None));
+        match status.event() {
+            MpvEvent::StatusUpdate {
+                is_playing,
+                position,
+                filename,
+                ..
+            } => {
+                assert!(!is_playing);
+                assert_eq!(position, 2);
+                assert_eq!(filename, None);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_end_file_is_pushed() {
+        let (event_tx, mut event_rx) = mpsc::channel(10);
+        let (_request_tx, request_rx) = mpsc::channel(10);
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = MpvManager::new(
+            event_tx,
+            request_rx,
+            dir.path().join("mpvsocket"),
+            &StorageConfig {
+                state_dir: dir.path().to_path_buf(),
+            },
+        )
+        .await
+        .unwrap();
+
+        manager
+            .handle_ipc_event(json!({ "event": "end-file", "reason": "eof" }))
+            .await;
+        match event_rx.recv().await.unwrap() {
+            MpvEvent::TrackEnded { reason } => assert_eq!(reason, "eof"),
+            other => panic!("Unexpected event {:?}", other),
+        }
+
+        manager.handle_disconnect().await;
+        match event_rx.recv().await.unwrap() {
+            MpvEvent::StatusUpdate {
+                is_playing,
+                filename,
+                ..
+            } => {
+                assert!(!is_playing);
+                assert_eq!(filename, None);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_playlist() {
+        let data = json!([
+            { "filename": "/music/a.mp3", "id": 1 },
+            { "filename": "/music/b.mp3", "id": 2, "current": true, "playing": true },
+            { "filename": "/music/c.mp3", "id": 3 },
+        ]);
+        let (files, current) = parse_playlist(Some(&data));
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[2], PathBuf::from("/music/c.mp3"));
+        assert_eq!(current, Some(1));
+
+        assert_eq!(parse_playlist(None), (Vec::new(), None));
+    }
+
+    /// A manager connected to a fake mpv that accepts every command. The
+    /// commands it received are sent to the returned channel.
+    async fn connected_manager(
+        dir: &tempfile::TempDir,
+    ) -> (MpvManager, mpsc::UnboundedReceiver<Value>) {
+        connected_manager_with_playlist(dir, 0).await
+    }
+
+    /// Like [`connected_manager`] with `len` files in mpv's playlist. The fake
+    /// keeps count of the files loaded and removed to answer `playlist-count`,
+    /// which isn't passed on.
+    async fn connected_manager_with_playlist(
+        dir: &tempfile::TempDir,
+        len: usize,
+    ) -> (MpvManager, mpsc::UnboundedReceiver<Value>) {
+        let path = dir.path().join("mpvsocket");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut count = len;
+            while let Ok(Some(line)) = lines.next_line().await {
+                let request: Value = serde_json::from_str(&line).unwrap();
+                let command = &request["command"];
+                let mut reply = json!({ "error": "success", "request_id": request["request_id"] });
+                match (command[0].as_str(), command[2].as_str()) {
+                    (Some("get_property"), _) if command[1] == "playlist-count" => {
+                        reply["data"] = json!(count);
+                    }
+                    (Some("loadfile"), Some("replace")) => count = 1,
+                    (Some("loadfile"), _) => count += 1,
+                    (Some("playlist-remove"), _) => count = count.saturating_sub(1),
+                    (Some("playlist-clear"), _) => count = count.min(1),
+                    _ => {}
+                }
+                writer
+                    .write_all(format!("{}\n", reply).as_bytes())
+                    .await
+                    .unwrap();
+                if reply.get("data").is_none() {
+                    command_tx.send(command.clone()).unwrap();
+                }
+            }
+        });
+
+        let (event_tx, _event_rx) = mpsc::channel(10);
+        let (_request_tx, request_rx) = mpsc::channel(10);
+        let mut manager = MpvManager::new(
+            event_tx,
+            request_rx,
+            path.clone(),
+            &StorageConfig {
+                state_dir: dir.path().to_path_buf(),
+            },
+        )
+        .await
+        .unwrap();
+        let (ipc, events) = MpvIpc::connect(&path).await.unwrap();
+        manager.ipc = Some(ipc);
+        manager.ipc_events = Some(events);
+        (manager, command_rx)
+    }
+
+    #[tokio::test]
+    async fn test_stream_reconnects() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let (mut manager, mut command_rx) = connected_manager(&dir).await;
+        // The server is still down on the first try
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/live", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for response in [
+                "HTTP/1.0 503 Service Unavailable\r\n\r\n",
+                "ICY 200 OK\r\n\r\n",
+            ] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let _ = socket.read(&mut [0; 1024]).await;
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        manager
+            .process_request(MpvRequest::Play(MediaSource::Stream(url.clone())))
+            .await
+            .unwrap();
+        let load = json!(["loadfile", url, "replace"]);
+        assert_eq!(command_rx.recv().await.unwrap(), load);
+
+        manager
+            .handle_ipc_event(json!({ "event": "end-file", "reason": "error" }))
+            .await;
+        assert!(manager.reconnect_at.is_some());
+        manager.reconnect_stream().await.unwrap();
+        assert_eq!(manager.stream_retries, 2);
+        assert!(manager.reconnect_at.is_some());
+        manager.reconnect_stream().await.unwrap();
+        assert_eq!(command_rx.recv().await.unwrap(), load);
+        manager
+            .handle_ipc_event(json!({ "event": "file-loaded" }))
+            .await;
+        assert_eq!(manager.stream_retries, 0);
+
+        // Playing a file gives up on the stream
+        manager
+            .handle_ipc_event(
+                json!({ "event": "property-change", "name": "path", "data": "a.mp3" }),
+            )
+            .await;
+        assert_eq!(manager.stream, None);
+        manager
+            .handle_ipc_event(json!({ "event": "end-file", "reason": "eof" }))
+            .await;
+        assert_eq!(manager.reconnect_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_queue_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut manager, mut command_rx) = connected_manager_with_playlist(&dir, 2).await;
+        manager
+            .handle_ipc_event(json!({
+                "event": "property-change",
+                "name": "playlist",
+                "data": [
+                    { "filename": "a.mp3", "current": true },
+                    { "filename": "b.mp3" },
+                ],
+            }))
+            .await;
+
+        manager
+            .process_request(MpvRequest::PlayNext(PathBuf::from("c.mp3")))
+            .await
+            .unwrap();
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["loadfile", "c.mp3", "append"])
+        );
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["playlist-move", 2, 1])
+        );
+
+        // mpv hasn't reported the new playlist yet
+        manager
+            .process_request(MpvRequest::PlayNext(PathBuf::from("d.mp3")))
+            .await
+            .unwrap();
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["loadfile", "d.mp3", "append"])
+        );
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["playlist-move", 3, 1])
+        );
+
+        manager
+            .process_request(MpvRequest::MoveInQueue { from: 0, to: 2 })
+            .await
+            .unwrap();
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["playlist-move", 0, 3])
+        );
+
+        manager
+            .process_request(MpvRequest::RemoveFromQueue(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["playlist-remove", 1])
+        );
+
+        manager
+            .process_request(MpvRequest::PlayFromQueue(2))
+            .await
+            .unwrap();
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["playlist-play-index", 2])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_play_and_enqueue_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut manager, mut command_rx) = connected_manager(&dir).await;
+        let paths = vec![PathBuf::from("a.mp3"), PathBuf::from("b.mp3")];
+
+        manager
+            .process_request(MpvRequest::PlayAll(paths.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["loadfile", "a.mp3", "replace"])
+        );
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["loadfile", "b.mp3", "append"])
+        );
+
+        manager
+            .process_request(MpvRequest::EnqueueAll(paths))
+            .await
+            .unwrap();
+        for file in ["a.mp3", "b.mp3"] {
+            assert_eq!(
+                command_rx.recv().await.unwrap(),
+                json!(["loadfile", file, "append-play"])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_and_bookmarks() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageConfig {
+            state_dir: dir.path().to_path_buf(),
+        };
+        let saved = ResumeState {
+            queue: vec![PathBuf::from("a.mp3"), PathBuf::from("b.mp3")],
+            current: Some(1),
+            position: 754,
+        };
+        saved.save(&storage.resume_path()).unwrap();
+        let (mut manager, mut command_rx) = connected_manager(&dir).await;
+
+        manager.process_request(MpvRequest::Resume).await.unwrap();
+        let expected = [
+            json!(["stop"]),
+            json!(["loadfile", "a.mp3", "append"]),
+            json!(["loadfile", "b.mp3", "append"]),
+            json!(["playlist-play-index", 1]),
+        ];
+        for command in expected {
+            assert_eq!(command_rx.recv().await.unwrap(), command);
+        }
+        manager
+            .handle_ipc_event(json!({ "event": "file-loaded" }))
+            .await;
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["seek", 754.0, "absolute"])
+        );
+        // Only offered once
+        manager.process_request(MpvRequest::Resume).await.unwrap();
+
+        let events = [
+            json!({ "event": "property-change", "name": "path", "data": "b.mp3" }),
+            json!({ "event": "property-change", "name": "pause", "data": false }),
+            json!({ "event": "property-change", "name": "playlist", "data": [
+                { "filename": "a.mp3" },
+                { "filename": "b.mp3", "current": true },
+            ] }),
+            json!({ "event": "property-change", "name": "time-pos", "data": 800.0 }),
+            json!({ "event": "property-change", "name": "pause", "data": true }),
+        ];
+        for event in events {
+            manager.handle_ipc_event(event).await;
+        }
+        let resumed = ResumeState::load(&storage.resume_path()).unwrap();
+        assert_eq!(resumed.position, 800);
+        assert_eq!(resumed.current, Some(1));
+
+        manager
+            .process_request(MpvRequest::AddBookmark)
+            .await
+            .unwrap();
+        let bookmarks = Bookmarks::load(&storage.bookmarks_path()).unwrap();
+        assert_eq!(bookmarks.get(Path::new("b.mp3")), [800]);
+        manager
+            .process_request(MpvRequest::RemoveBookmark(0))
+            .await
+            .unwrap();
+        let bookmarks = Bookmarks::load(&storage.bookmarks_path()).unwrap();
+        assert!(bookmarks.get(Path::new("b.mp3")).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resume_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageConfig {
+            state_dir: dir.path().to_path_buf(),
+        };
+        let saved = ResumeState {
+            queue: vec![PathBuf::from("a.mp3"), PathBuf::from("b.mp3")],
+            current: Some(1),
+            position: 754,
+        };
+        saved.save(&storage.resume_path()).unwrap();
+        let (mut manager, mut command_rx) = connected_manager(&dir).await;
+
+        // Like an alarm going off before the offer was taken
+        manager
+            .process_request(MpvRequest::ResumeLast)
+            .await
+            .unwrap();
+        let expected = [
+            json!(["stop"]),
+            json!(["loadfile", "a.mp3", "append"]),
+            json!(["loadfile", "b.mp3", "append"]),
+            json!(["playlist-play-index", 1]),
+        ];
+        for command in expected {
+            assert_eq!(command_rx.recv().await.unwrap(), command);
+        }
+        // It takes the offer with it
+        manager.process_request(MpvRequest::Resume).await.unwrap();
+        manager.process_request(MpvRequest::Pause).await.unwrap();
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["set_property", "pause", true])
+        );
+
+        let events = [
+            json!({ "event": "property-change", "name": "path", "data": "b.mp3" }),
+            json!({ "event": "property-change", "name": "playlist", "data": [
+                { "filename": "a.mp3" },
+                { "filename": "b.mp3", "current": true },
+            ] }),
+            json!({ "event": "property-change", "name": "time-pos", "data": 800.0 }),
+            json!({ "event": "property-change", "name": "pause", "data": true }),
+        ];
+        for event in events {
+            manager.handle_ipc_event(event).await;
+        }
+        // The queue is still loaded, every alarm just unpauses it
+        for _ in 0..2 {
+            manager
+                .process_request(MpvRequest::ResumeLast)
+                .await
+                .unwrap();
+            assert_eq!(
+                command_rx.recv().await.unwrap(),
+                json!(["set_property", "pause", false])
+            );
+        }
+    }
+
+    #[test]
+    fn test_chapters_and_speed() {
+        let mut status = Status::default();
+        let chapters = json!([
+            { "title": "Intro", "time": 0.0 },
+            { "time": 300.0 },
+        ]);
+        assert!(status.apply("chapter-list", Some(&chapters)));
+        assert!(status.apply("chapter", Some(&json!(1))));
+        assert!(status.apply("speed", Some(&json!(1.5))));
+        assert!(status.apply("metadata/by-key/icy-title", Some(&json!(" Artist - Song "))));
+        assert_eq!(status.stream_title.as_deref(), Some("Artist - Song"));
+        let titles: Vec<&str> = status.chapters.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, ["Intro", ""]);
+        assert_eq!(status.chapters[1].start, 300);
+        assert_eq!(status.chapter, Some(1));
+        assert_eq!(status.speed, 1.5);
+
+        assert!(status.apply("chapter", Some(&json!(-1))));
+        assert_eq!(status.chapter, None);
+        assert!(status.apply("chapter-list", None));
+        assert!(status.chapters.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_speed_is_remembered_per_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut manager, mut command_rx) = connected_manager(&dir).await;
+        let property = |name: &str, data: Value| json!({ "event": "property-change", "name": name, "data": data });
+
+        manager
+            .handle_ipc_event(property("path", json!("book.m4a")))
+            .await;
+        manager
+            .process_request(MpvRequest::SetSpeed(1.5))
+            .await
+            .unwrap();
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["set_property", "speed", 1.5])
+        );
+        manager
+            .handle_ipc_event(property("speed", json!(1.5)))
+            .await;
+
+        // Other files play at normal speed, the book at its own again
+        manager
+            .handle_ipc_event(property("path", json!("song.mp3")))
+            .await;
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["set_property", "speed", 1.0])
+        );
+        manager
+            .handle_ipc_event(property("speed", json!(1.0)))
+            .await;
+        manager
+            .handle_ipc_event(property("path", json!("book.m4a")))
+            .await;
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["set_property", "speed", 1.5])
+        );
+
+        let speeds = Speeds::load(&dir.path().join("speeds.json")).unwrap();
+        assert_eq!(speeds.get(Path::new("book.m4a")), 1.5);
+    }
+
+    #[tokio::test]
+    async fn test_transport_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut manager, mut command_rx) = connected_manager(&dir).await;
+        manager
+            .handle_ipc_event(json!({
+                "event": "property-change",
+                "name": "playlist",
+                "data": [{ "filename": "a.mp3" }, { "filename": "b.mp3", "current": true }],
+            }))
+            .await;
+
+        let requests = [
+            (MpvRequest::Next, json!(["playlist-next"])),
+            (MpvRequest::Previous, json!(["playlist-prev"])),
+            (
+                MpvRequest::SeekRelative(-10.0),
+                json!(["seek", -10.0, "relative"]),
+            ),
+            (
+                MpvRequest::SeekAbsolute(30.0),
+                json!(["seek", 30.0, "absolute"]),
+            ),
+            (MpvRequest::Stop, json!(["stop"])),
+            (MpvRequest::Pause, json!(["set_property", "pause", true])),
+            (MpvRequest::NextChapter, json!(["add", "chapter", 1])),
+            (MpvRequest::PreviousChapter, json!(["add", "chapter", -1])),
+        ];
+        for (request, command) in requests {
+            manager.process_request(request).await.unwrap();
+            assert_eq!(command_rx.recv().await.unwrap(), command);
+        }
+
+        // Past the first few seconds Previous restarts the current file
+        manager
+            .handle_ipc_event(
+                json!({ "event": "property-change", "name": "time-pos", "data": 42.0 }),
+            )
+            .await;
+        manager.process_request(MpvRequest::Previous).await.unwrap();
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["seek", 0.0, "absolute"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shuffle_and_repeat() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut manager, mut command_rx) = connected_manager_with_playlist(&dir, 5).await;
+        manager
+            .handle_ipc_event(json!({
+                "event": "property-change",
+                "name": "playlist",
+                "data": [
+                    { "filename": "a.mp3", "current": true },
+                    { "filename": "b.mp3" },
+                    { "filename": "c.mp3" },
+                    { "filename": "d.mp3" },
+                    { "filename": "e.mp3" },
+                ],
+            }))
+            .await;
+
+        manager
+            .process_request(MpvRequest::ToggleShuffle)
+            .await
+            .unwrap();
+        let mut rng = ShuffleRng::new(manager.modes.seed);
+        let expected = shuffle_moves(5, 1, &mut rng);
+        for (from, to) in expected {
+            assert_eq!(
+                command_rx.recv().await.unwrap(),
+                json!(["playlist-move", from, to])
+            );
+        }
+
+        manager
+            .process_request(MpvRequest::CycleRepeat)
+            .await
+            .unwrap();
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["set_property", "loop-file", "no"])
+        );
+        assert_eq!(
+            command_rx.recv().await.unwrap(),
+            json!(["set_property", "loop-playlist", "inf"])
+        );
+
+        // Files are mixed in after the playing one, going by mpv's playlist
+        // even before it has reported the new one
+        for (file, appended) in [("f.mp3", 5), ("g.mp3", 6)] {
+            manager
+                .process_request(MpvRequest::Enqueue(PathBuf::from(file)))
+                .await
+                .unwrap();
+            assert_eq!(
+                command_rx.recv().await.unwrap(),
+                json!(["loadfile", file, "append-play"])
+            );
+            let target = 1 + rng.below(appended);
+            if target != appended {
+                assert_eq!(
+                    command_rx.recv().await.unwrap(),
+                    json!(["playlist-move", appended, target])
+                );
+            }
+        }
+
+        let saved = PlaybackModes::load(&dir.path().join("player.json")).unwrap();
+        assert!(saved.shuffle);
+        assert_eq!(saved.repeat, Repeat::All);
+        assert_eq!(saved.seed, manager.modes.seed);
+    }
+}
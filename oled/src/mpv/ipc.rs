@@ -0,0 +1,361 @@
+//! Client for mpv's JSON IPC protocol, see <https://mpv.io/manual/stable/#json-ipc>
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{unix::OwnedWriteHalf, UnixStream},
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+
+/// How long to wait for mpv to answer a command
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum MpvError {
+    Io(std::io::Error),
+    /// The connection closed before mpv replied
+    Disconnected,
+    /// mpv replied with an error, e.g. "property unavailable"
+    Command(String),
+    /// mpv sent something that could not be understood
+    Protocol(String),
+    Timeout,
+}
+
+impl fmt::Display for MpvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MpvError::Io(e) => write!(f, "mpv IPC I/O error: {}", e),
+            MpvError::Disconnected => write!(f, "mpv IPC connection closed"),
+            MpvError::Command(e) => write!(f, "mpv command failed: {}", e),
+            MpvError::Protocol(e) => write!(f, "Unexpected reply from mpv: {}", e),
+            MpvError::Timeout => write!(f, "mpv did not reply in time"),
+        }
+    }
+}
+
+impl std::error::Error for MpvError {}
+
+impl From<std::io::Error> for MpvError {
+    fn from(e: std::io::Error) -> Self {
+        MpvError::Io(e)
+    }
+}
+
+/// Reply channels by request id, `None` once the connection has closed
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Result<Value, MpvError>>>>>>;
+
+/// A persistent connection to mpv's IPC socket. Replies are matched to
+/// commands by `request_id`, everything else mpv sends is an event and is
+/// forwarded to the receiver returned by [`MpvIpc::connect`].
+#[derive(Debug)]
+pub struct MpvIpc {
+    writer: tokio::sync::Mutex<OwnedWriteHalf>,
+    pending: Pending,
+    next_request_id: AtomicU64,
+    reader_task: JoinHandle<()>,
+}
+
+impl MpvIpc {
+    pub async fn connect(path: &Path) -> Result<(Self, mpsc::UnboundedReceiver<Value>), MpvError> {
+        let stream = UnixStream::connect(path).await?;
+        let (reader, writer) = stream.into_split();
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        // Unbounded so no event is lost in a burst, and the reader never
+        // waits on the events while a command waits on the reader for a reply
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
+        let reader_pending = pending.clone();
+        let reader_task = tokio::spawn(async move {
+            let mut lines = BufReader::new(reader).lines();
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) => handle_line(&line, &reader_pending, &event_tx),
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("Reading from the mpv socket failed: {}", e);
+                        break;
+                    }
+                }
+            }
+            // Dropping the senders wakes everyone still waiting for a reply
+            reader_pending.lock().unwrap().take();
+        });
+
+        Ok((
+            Self {
+                writer: tokio::sync::Mutex::new(writer),
+                pending,
+                next_request_id: AtomicU64::new(1),
+                reader_task,
+            },
+            event_rx,
+        ))
+    }
+
+    /// False once mpv has closed the connection
+    pub fn is_connected(&self) -> bool {
+        self.pending.lock().unwrap().is_some()
+    }
+
+    /// Sends `args` as a command and waits for the matching reply
+    pub async fn command(&self, args: &[Value]) -> Result<Value, MpvError> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(request_id, tx),
+            None => return Err(MpvError::Disconnected),
+        };
+
+        let mut message = json!({ "command": args, "request_id": request_id }).to_string();
+        message.push('\n');
+        debug!("Sending MPV command: {}", message.trim());
+        let written = self.writer.lock().await.write_all(message.as_bytes()).await;
+        if let Err(e) = written {
+            self.forget(request_id);
+            return Err(e.into());
+        }
+
+        match tokio::time::timeout(REPLY_TIMEOUT, rx).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => Err(MpvError::Disconnected),
+            Err(_) => {
+                self.forget(request_id);
+                Err(MpvError::Timeout)
+            }
+        }
+    }
+
+    fn forget(&self, request_id: u64) {
+        if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+            pending.remove(&request_id);
+        }
+    }
+
+    pub async fn get_property<T: DeserializeOwned>(&self, name: &str) -> Result<T, MpvError> {
+        let data = self.command(&[json!("get_property"), json!(name)]).await?;
+        serde_json::from_value(data.clone())
+            .map_err(|_| MpvError::Protocol(format!("{} has unexpected value {}", name, data)))
+    }
+
+    pub async fn set_property<T: Serialize>(&self, name: &str, value: T) -> Result<(), MpvError> {
+        let value = serde_json::to_value(value)
+            .map_err(|e| MpvError::Protocol(format!("Could not encode {}: {}", name, e)))?;
+        self.command(&[json!("set_property"), json!(name), value])
+            .await?;
+        Ok(())
+    }
+}
+
+impl Drop for MpvIpc {
+    fn drop(&mut self) {
+        self.reader_task.abort();
+    }
+}
+
+fn handle_line(line: &str, pending: &Pending, events: &mpsc::UnboundedSender<Value>) {
+    let message: Value = match serde_json::from_str(line) {
+        Ok(message) => message,
+        Err(e) => {
+            warn!("Ignoring malformed line from mpv ({}): {}", e, line);
+            return;
+        }
+    };
+
+    if message.get("event").is_some() {
+        if events.send(message).is_err() {
+            debug!("Dropping mpv event, nobody is listening");
+        }
+        return;
+    }
+
+    let Some(request_id) = message.get("request_id").and_then(Value::as_u64) else {
+        debug!("Ignoring reply without a request_id: {}", line);
+        return;
+    };
+    let reply_tx = pending
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|pending| pending.remove(&request_id));
+    let Some(reply_tx) = reply_tx else {
+        debug!("Ignoring reply to unknown request {}", request_id);
+        return;
+    };
+
+    let reply = match message.get("error").and_then(Value::as_str) {
+        Some("success") => Ok(message.get("data").cloned().unwrap_or(Value::Null)),
+        Some(error) => Err(MpvError::Command(error.to_string())),
+        None => Err(MpvError::Protocol(format!(
+            "Reply without status: {}",
+            line
+        ))),
+    };
+    let _ = reply_tx.send(reply);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixListener;
+
+    /// Accepts one connection and answers every request with `respond`. The
+    /// replies are sent in reverse order of arrival for every batch of
+    /// `batch` requests to check that they are correlated by id.
+    fn fake_mpv(
+        listener: UnixListener,
+        batch: usize,
+        respond: fn(&Value) -> Value,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut queued = Vec::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let request: Value = serde_json::from_str(&line).unwrap();
+                let mut reply = respond(&request["command"]);
+                reply["request_id"] = request["request_id"].clone();
+                queued.push(reply);
+                if queued.len() == batch {
+                    for reply in queued.drain(..).rev() {
+                        let line = format!("{}\n", reply);
+                        writer.write_all(line.as_bytes()).await.unwrap();
+                    }
+                }
+            }
+        })
+    }
+
+    fn listener() -> (tempfile::TempDir, std::path::PathBuf, UnixListener) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mpvsocket");
+        let listener = UnixListener::bind(&path).unwrap();
+        (dir, path, listener)
+    }
+
+    fn properties(command: &Value) -> Value {
+        match command[1].as_str() {
+            Some("pause") => json!({ "error": "success", "data": false }),
+            Some("time-pos") => json!({ "error": "success", "data": 12.5 }),
+            Some("filename") => json!({ "error": "property unavailable" }),
+            _ => json!({ "error": "success" }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replies_are_matched_by_request_id() {
+        let (_dir, path, listener) = listener();
+        let _server = fake_mpv(listener, 2, properties);
+        let (ipc, _events) = MpvIpc::connect(&path).await.unwrap();
+
+        let (pause, position) = tokio::join!(
+            ipc.get_property::<bool>("pause"),
+            ipc.get_property::<f64>("time-pos"),
+        );
+        assert!(!pause.unwrap());
+        assert_eq!(position.unwrap(), 12.5);
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_typed() {
+        let (_dir, path, listener) = listener();
+        let _server = fake_mpv(listener, 1, properties);
+        let (ipc, _events) = MpvIpc::connect(&path).await.unwrap();
+
+        match ipc.get_property::<String>("filename").await {
+            Err(MpvError::Command(e)) => assert_eq!(e, "property unavailable"),
+            other => panic!("Expected a command error, got {:?}", other),
+        }
+        match ipc.get_property::<String>("pause").await {
+            Err(MpvError::Protocol(_)) => {}
+            other => panic!("Expected a protocol error, got {:?}", other),
+        }
+        ipc.set_property("volume", 50).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_events_are_forwarded() {
+        let (_dir, path, listener) = listener();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream
+                .write_all(b"{\"event\":\"file-loaded\"}\nnot json\n{\"event\":\"pause\"}\n")
+                .await
+                .unwrap();
+            stream
+        });
+        let (_ipc, mut events) = MpvIpc::connect(&path).await.unwrap();
+        let _stream = server.await.unwrap();
+
+        assert_eq!(events.recv().await.unwrap()["event"], "file-loaded");
+        assert_eq!(events.recv().await.unwrap()["event"], "pause");
+    }
+
+    #[tokio::test]
+    async fn test_event_burst_is_not_dropped() {
+        let (_dir, path, listener) = listener();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let request: Value =
+                serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+            // More events than any channel would buffer before the reply
+            for id in 0..500 {
+                let line = format!("{}\n", json!({ "event": "property-change", "id": id }));
+                writer.write_all(line.as_bytes()).await.unwrap();
+            }
+            let reply = json!({ "error": "success", "request_id": request["request_id"] });
+            writer
+                .write_all(format!("{}\n", reply).as_bytes())
+                .await
+                .unwrap();
+            (lines, writer)
+        });
+        let (ipc, mut events) = MpvIpc::connect(&path).await.unwrap();
+
+        ipc.command(&[json!("stop")]).await.unwrap();
+        let _stream = server.await.unwrap();
+        for id in 0..500 {
+            assert_eq!(events.recv().await.unwrap()["id"], id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_fails_pending_commands() {
+        let (_dir, path, listener) = listener();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            // Read the request and hang up without answering
+            lines.next_line().await.unwrap();
+        });
+        let (ipc, _events) = MpvIpc::connect(&path).await.unwrap();
+
+        match ipc.command(&[json!("cycle"), json!("pause")]).await {
+            Err(MpvError::Disconnected) => {}
+            other => panic!("Expected a disconnect, got {:?}", other),
+        }
+        server.await.unwrap();
+        assert!(!ipc.is_connected());
+        assert!(matches!(
+            ipc.command(&[json!("stop")]).await,
+            Err(MpvError::Disconnected)
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+//! Shuffle and repeat settings. They survive restarts by being written to a
+//! small JSON file whenever they change.
+
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{load_json, save_json};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Repeat {
+    #[default]
+    Off,
+    /// Loop the current file
+    One,
+    /// Start over from the top of the queue after the last file
+    All,
+}
+
+impl Repeat {
+    pub fn next(self) -> Self {
+        match self {
+            Repeat::Off => Repeat::All,
+            Repeat::All => Repeat::One,
+            Repeat::One => Repeat::Off,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaybackModes {
+    pub shuffle: bool,
+    pub repeat: Repeat,
+    /// Seed of the current shuffle order
+    pub seed: u64,
+}
+
+impl PlaybackModes {
+    /// Reads the modes from `path`, a missing file means everything is off
+    pub fn load(path: &Path) -> Result<Self> {
+        load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_json(path, self)
+    }
+
+    pub fn toggle_shuffle(&mut self) {
+        self.shuffle = !self.shuffle;
+        if self.shuffle {
+            self.seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|t| t.as_nanos() as u64)
+                .unwrap_or_default();
+        }
+    }
+}
+
+/// SplitMix64, small and the same on every platform so a seed always gives
+/// the same order
+#[derive(Debug, Clone)]
+pub struct ShuffleRng(u64);
+
+impl ShuffleRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, `n` must not be 0
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Fisher-Yates
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
+
+/// Shuffles the entries from `start` to the end of a playlist of `len`
+/// entries. Returns `(from, to)` pairs for mpv's `playlist-move`, applied in
+/// order they move every entry into place. Entries before `start` (the one
+/// playing and those already played) stay where they are, so nothing repeats
+/// until the whole list has been played.
+pub fn shuffle_moves(len: usize, start: usize, rng: &mut ShuffleRng) -> Vec<(usize, usize)> {
+    let mut order: Vec<usize> = (start..len).collect();
+    rng.shuffle(&mut order);
+
+    let mut playlist: Vec<usize> = (0..len).collect();
+    let mut moves = Vec::new();
+    for (offset, wanted) in order.into_iter().enumerate() {
+        let target = start + offset;
+        let from = playlist.iter().position(|&entry| entry == wanted).unwrap();
+        if from != target {
+            let entry = playlist.remove(from);
+            playlist.insert(target, entry);
+            moves.push((from, target));
+        }
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(len: usize, moves: &[(usize, usize)]) -> Vec<usize> {
+        let mut playlist: Vec<usize> = (0..len).collect();
+        for &(from, to) in moves {
+            // playlist-move inserts before `to`, moves always go up the list
+            assert!(from > to);
+            let entry = playlist.remove(from);
+            playlist.insert(to, entry);
+        }
+        playlist
+    }
+
+    #[test]
+    fn test_shuffle_keeps_played_entries_and_is_seeded() {
+        let moves = shuffle_moves(20, 3, &mut ShuffleRng::new(42));
+        let shuffled = apply(20, &moves);
+        assert_eq!(&shuffled[..3], &[0, 1, 2]);
+        let mut sorted = shuffled.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+        assert_ne!(shuffled, (0..20).collect::<Vec<_>>());
+
+        assert_eq!(moves, shuffle_moves(20, 3, &mut ShuffleRng::new(42)));
+        assert_ne!(moves, shuffle_moves(20, 3, &mut ShuffleRng::new(43)));
+        assert!(shuffle_moves(1, 0, &mut ShuffleRng::new(42)).is_empty());
+        assert!(shuffle_moves(5, 5, &mut ShuffleRng::new(42)).is_empty());
+    }
+
+    #[test]
+    fn test_modes_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("player.json");
+        assert_eq!(
+            PlaybackModes::load(&path).unwrap(),
+            PlaybackModes::default()
+        );
+
+        let modes = PlaybackModes {
+            shuffle: true,
+            repeat: Repeat::One,
+            seed: 7,
+        };
+        modes.save(&path).unwrap();
+        assert_eq!(PlaybackModes::load(&path).unwrap(), modes);
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("\"repeat\": \"one\""));
+    }
+}
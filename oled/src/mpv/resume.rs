@@ -0,0 +1,128 @@
+//! What was playing and where, so playback can pick up again after a restart,
+//! and bookmarks for finding places in long files like audiobooks.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{load_json, save_json};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResumeState {
+    pub queue: Vec<PathBuf>,
+    /// Index of the playing file in `queue`
+    pub current: Option<usize>,
+    /// Seconds into the playing file
+    pub position: u32,
+}
+
+impl ResumeState {
+    /// Reads the state from `path`, a missing file means nothing to resume
+    pub fn load(path: &Path) -> Result<Self> {
+        load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_json(path, self)
+    }
+
+    pub fn current_file(&self) -> Option<&Path> {
+        self.queue.get(self.current?).map(PathBuf::as_path)
+    }
+}
+
+/// Positions in whole seconds, sorted, per file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Bookmarks(BTreeMap<PathBuf, Vec<u32>>);
+
+impl Bookmarks {
+    /// Reads the bookmarks from `path`, a missing file means there are none
+    pub fn load(path: &Path) -> Result<Self> {
+        load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_json(path, self)
+    }
+
+    pub fn get(&self, file: &Path) -> &[u32] {
+        self.0.get(file).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Returns false if there already is a bookmark at `position`
+    pub fn add(&mut self, file: &Path, position: u32) -> bool {
+        let positions = self.0.entry(file.to_path_buf()).or_default();
+        match positions.binary_search(&position) {
+            Ok(_) => false,
+            Err(index) => {
+                positions.insert(index, position);
+                true
+            }
+        }
+    }
+
+    /// Removes the `index`th bookmark of `file`, returns false if there is none
+    pub fn remove(&mut self, file: &Path, index: usize) -> bool {
+        let Some(positions) = self.0.get_mut(file) else {
+            return false;
+        };
+        if index >= positions.len() {
+            return false;
+        }
+        positions.remove(index);
+        if positions.is_empty() {
+            self.0.remove(file);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("resume.json");
+        assert_eq!(ResumeState::load(&path).unwrap(), ResumeState::default());
+
+        let state = ResumeState {
+            queue: vec![PathBuf::from("/music/a.mp3"), PathBuf::from("/music/b.mp3")],
+            current: Some(1),
+            position: 754,
+        };
+        state.save(&path).unwrap();
+        let loaded = ResumeState::load(&path).unwrap();
+        assert_eq!(loaded, state);
+        assert_eq!(loaded.current_file(), Some(Path::new("/music/b.mp3")));
+        assert_eq!(ResumeState::default().current_file(), None);
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bookmarks.json");
+        let book = Path::new("/books/long.m4a");
+        let mut bookmarks = Bookmarks::load(&path).unwrap();
+        assert!(bookmarks.get(book).is_empty());
+
+        assert!(bookmarks.add(book, 3600));
+        assert!(bookmarks.add(book, 60));
+        assert!(!bookmarks.add(book, 60));
+        assert_eq!(bookmarks.get(book), [60, 3600]);
+        bookmarks.save(&path).unwrap();
+        assert_eq!(Bookmarks::load(&path).unwrap(), bookmarks);
+
+        assert!(!bookmarks.remove(book, 2));
+        assert!(bookmarks.remove(book, 0));
+        assert!(bookmarks.remove(book, 0));
+        assert_eq!(bookmarks, Bookmarks::default());
+    }
+}
@@ -0,0 +1,87 @@
+//! Playback speed, remembered per file so an audiobook keeps playing at the
+//! speed it was listened to.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{load_json, save_json};
+
+/// The speeds [`next`] steps through
+pub const SPEEDS: [f64; 6] = [0.75, 1.0, 1.25, 1.5, 1.75, 2.0];
+
+/// The next faster speed, wrapping around to the slowest after the fastest
+pub fn next(speed: f64) -> f64 {
+    SPEEDS
+        .iter()
+        .copied()
+        .find(|&s| s > speed + 0.01)
+        .unwrap_or(SPEEDS[0])
+}
+
+/// Keeps `speed` within the slowest and fastest of [`SPEEDS`]
+pub fn clamp(speed: f64) -> f64 {
+    speed.clamp(SPEEDS[0], SPEEDS[SPEEDS.len() - 1])
+}
+
+/// Files played at anything other than normal speed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Speeds(BTreeMap<PathBuf, f64>);
+
+impl Speeds {
+    /// Reads the speeds from `path`, a missing file means normal speed
+    /// everywhere
+    pub fn load(path: &Path) -> Result<Self> {
+        load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_json(path, self)
+    }
+
+    pub fn get(&self, file: &Path) -> f64 {
+        self.0.get(file).copied().unwrap_or(1.0)
+    }
+
+    pub fn set(&mut self, file: &Path, speed: f64) {
+        if speed == 1.0 {
+            self.0.remove(file);
+        } else {
+            self.0.insert(file.to_path_buf(), speed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_speed() {
+        assert_eq!(next(1.0), 1.25);
+        assert_eq!(next(1.1), 1.25);
+        assert_eq!(next(2.0), 0.75);
+        assert_eq!(clamp(3.0), 2.0);
+        assert_eq!(clamp(0.5), 0.75);
+    }
+
+    #[test]
+    fn test_speeds_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("speeds.json");
+        let book = Path::new("/books/long.m4a");
+        let mut speeds = Speeds::load(&path).unwrap();
+        assert_eq!(speeds.get(book), 1.0);
+
+        speeds.set(book, 1.5);
+        speeds.save(&path).unwrap();
+        assert_eq!(Speeds::load(&path).unwrap().get(book), 1.5);
+        speeds.set(book, 1.0);
+        assert_eq!(speeds, Speeds::default());
+    }
+}
@@ -0,0 +1,244 @@
+//! Reading and writing M3U and PLS playlists, used for the radio stations
+//! and playlist files in the audio roots.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::{radio, storage};
+
+/// Files with these extensions are listed as playlists on the Files tab
+pub const PLAYLIST_EXTENSIONS: [&str; 3] = ["m3u", "m3u8", "pls"];
+
+/// A file or URL in a playlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub location: String,
+    /// From `#EXTINF` or `TitleN=`, if the playlist has one
+    pub title: Option<String>,
+}
+
+/// Reads a playlist, PLS if it starts with `[playlist]` and M3U otherwise
+pub fn load(path: &Path) -> Result<Vec<Entry>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read playlist {:?}", path))?;
+    Ok(parse(&contents))
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            PLAYLIST_EXTENSIONS
+                .iter()
+                .any(|known| ext.eq_ignore_ascii_case(known))
+        })
+}
+
+/// Reads the playlist at `path` with relative locations resolved against its
+/// directory. Local files that don't exist are left out.
+pub fn load_tracks(path: &Path) -> Result<Vec<Entry>> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let tracks = load(path)?
+        .into_iter()
+        .filter_map(|entry| {
+            if radio::is_stream(&entry.location) {
+                return Some(entry);
+            }
+            let location = entry
+                .location
+                .strip_prefix("file://")
+                .unwrap_or(&entry.location);
+            let track = base.join(location);
+            if !track.is_file() {
+                debug!("Skipping missing {:?} in {:?}", track, path);
+                return None;
+            }
+            Some(Entry {
+                location: track.to_string_lossy().into_owned(),
+                title: entry.title,
+            })
+        })
+        .collect();
+    Ok(tracks)
+}
+
+/// The paths of the tracks in the playlist at `path`, see [`load_tracks`]
+pub fn track_paths(path: &Path) -> Result<Vec<PathBuf>> {
+    let tracks = load_tracks(path)?;
+    Ok(tracks
+        .into_iter()
+        .map(|entry| entry.location.into())
+        .collect())
+}
+
+/// Writes `entries` as an extended M3U playlist
+pub fn save_m3u(path: &Path, entries: &[Entry]) -> Result<()> {
+    storage::save_text(path, &to_m3u(entries))
+        .with_context(|| format!("Could not write playlist {:?}", path))
+}
+
+pub fn to_m3u(entries: &[Entry]) -> String {
+    let mut contents = "#EXTM3U\n".to_string();
+    for entry in entries {
+        if let Some(title) = &entry.title {
+            let _ = writeln!(contents, "#EXTINF:-1,{}", title);
+        }
+        let _ = writeln!(contents, "{}", entry.location);
+    }
+    contents
+}
+
+pub fn parse(contents: &str) -> Vec<Entry> {
+    let first = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty());
+    if first.is_some_and(|line| line.eq_ignore_ascii_case("[playlist]")) {
+        parse_pls(contents)
+    } else {
+        parse_m3u(contents)
+    }
+}
+
+/// Plain and extended M3U, `#EXTINF:<length>,<title>` names the next entry
+pub fn parse_m3u(contents: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut title = None;
+    for line in contents.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            title = info
+                .split_once(',')
+                .map(|(_, title)| title.trim().to_string())
+                .filter(|title| !title.is_empty());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entries.push(Entry {
+                location: line.to_string(),
+                title: title.take(),
+            });
+        }
+    }
+    entries
+}
+
+/// `FileN=` and `TitleN=` pairs, in the order of N
+pub fn parse_pls(contents: &str) -> Vec<Entry> {
+    let mut entries: Vec<(u32, Entry)> = Vec::new();
+    let mut titles: Vec<(u32, String)> = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim().to_string();
+        if let Some(number) = key.strip_prefix("file").and_then(|n| n.parse().ok()) {
+            let entry = Entry {
+                location: value,
+                title: None,
+            };
+            entries.push((number, entry));
+        } else if let Some(number) = key.strip_prefix("title").and_then(|n| n.parse().ok()) {
+            titles.push((number, value));
+        }
+    }
+    entries.sort_by_key(|(number, _)| *number);
+    for (number, title) in titles {
+        if let Some((_, entry)) = entries.iter_mut().find(|(n, _)| *n == number) {
+            entry.title = Some(title).filter(|title| !title.is_empty());
+        }
+    }
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(location: &str, title: Option<&str>) -> Entry {
+        Entry {
+            location: location.to_string(),
+            title: title.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_parse_m3u() {
+        let contents = "#EXTM3U\n\
+            #EXTINF:-1,Radio One\n\
+            http://radio.example/one\n\
+            \n\
+            /music/a.mp3\n\
+            #EXTINF:-1,\n\
+            http://radio.example/two\n";
+        assert_eq!(
+            parse(contents),
+            [
+                entry("http://radio.example/one", Some("Radio One")),
+                entry("/music/a.mp3", None),
+                entry("http://radio.example/two", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_pls() {
+        let contents = "[playlist]\n\
+            File2=http://radio.example/two\n\
+            Title1=Radio One\n\
+            File1=http://radio.example/one\n\
+            Length1=-1\n\
+            NumberOfEntries=2\n\
+            Version=2\n";
+        assert_eq!(
+            parse(contents),
+            [
+                entry("http://radio.example/one", Some("Radio One")),
+                entry("http://radio.example/two", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_save_and_load_tracks() {
+        let dir = tempfile::tempdir().unwrap();
+        let album = dir.path().join("album");
+        std::fs::create_dir_all(&album).unwrap();
+        std::fs::write(album.join("01.mp3"), b"").unwrap();
+        assert!(is_playlist(Path::new("mix.M3U8")));
+        assert!(!is_playlist(&album.join("01.mp3")));
+
+        // Relative to the playlist, the missing file is skipped
+        std::fs::write(
+            dir.path().join("mix.pls"),
+            "[playlist]\nFile1=album/01.mp3\nFile2=album/02.mp3\nFile3=http://radio.example/one\n",
+        )
+        .unwrap();
+        let tracks = load_tracks(&dir.path().join("mix.pls")).unwrap();
+        let first = album.join("01.mp3").to_string_lossy().into_owned();
+        assert_eq!(
+            tracks,
+            [entry(&first, None), entry("http://radio.example/one", None)]
+        );
+
+        let saved = [
+            entry(&first, Some("Artist - One")),
+            entry("http://radio.example/one", None),
+        ];
+        let path = dir.path().join("saved/queue.m3u");
+        save_m3u(&path, &saved).unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("#EXTM3U\n#EXTINF:-1,Artist - One\n"));
+        assert_eq!(load_tracks(&path).unwrap(), saved);
+        // Written next to it first and renamed, nothing else is left behind
+        let files: Vec<_> = std::fs::read_dir(dir.path().join("saved"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["queue.m3u"]);
+    }
+}
@@ -0,0 +1,140 @@
+//! Internet radio stations and checking whether a stream answers again
+//! after it dropped.
+
+use std::{path::Path, time::Duration};
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::playlist;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Station {
+    pub name: String,
+    pub url: String,
+}
+
+/// Reads the stations from an M3U or PLS playlist, entries that are not
+/// URLs are skipped and a missing file means there are none
+pub fn load_stations(path: &Path) -> Result<Vec<Station>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let stations = playlist::load(path)?
+        .into_iter()
+        .filter(|entry| is_stream(&entry.location))
+        .map(|entry| Station {
+            name: entry.title.unwrap_or_else(|| entry.location.clone()),
+            url: entry.location,
+        })
+        .collect();
+    Ok(stations)
+}
+
+pub fn is_stream(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Requests `url` and checks that the server answers with a success status.
+/// Only plain HTTP can be checked, https URLs are left for mpv to try.
+pub async fn probe(url: &str, timeout: Duration) -> Result<()> {
+    let Some(rest) = url.strip_prefix("http://") else {
+        return Ok(());
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let status = tokio::time::timeout(timeout, async {
+        let mut stream = TcpStream::connect(&address).await?;
+        let request = format!(
+            "GET {} HTTP/1.0\r\nHost: {}\r\nIcy-MetaData: 1\r\nConnection: close\r\n\r\n",
+            path, authority
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status).await?;
+        Ok::<String, std::io::Error>(status)
+    })
+    .await
+    .map_err(|_| anyhow!("{} did not answer within {:?}", url, timeout))??;
+
+    // "HTTP/1.1 200 OK", or "ICY 200 OK" from older Shoutcast servers
+    let code = status
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok());
+    match code {
+        Some(200..=399) => Ok(()),
+        _ => Err(anyhow!("{} answered {:?}", url, status.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    /// Answers a single request with `response`
+    async fn serve(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+        format!("http://{}/stream", address)
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let timeout = Duration::from_secs(5);
+        let url = serve("ICY 200 OK\r\nicy-name: Test FM\r\n\r\n").await;
+        probe(&url, timeout).await.unwrap();
+
+        let url = serve("HTTP/1.1 404 Not Found\r\n\r\n").await;
+        let err = probe(&url, timeout).await.unwrap_err();
+        assert!(err.to_string().contains("404"), "{}", err);
+
+        // Nothing listens there anymore
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(probe(&url, timeout).await.is_err());
+
+        probe("https://radio.example/stream", timeout)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_load_stations() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stations.pls");
+        assert!(load_stations(&path).unwrap().is_empty());
+
+        std::fs::write(
+            &path,
+            "[playlist]\nFile1=http://radio.example/jazz\nTitle1=Jazz\nFile2=/music/a.mp3\n",
+        )
+        .unwrap();
+        assert_eq!(
+            load_stations(&path).unwrap(),
+            [Station {
+                name: "Jazz".to_string(),
+                url: "http://radio.example/jazz".to_string(),
+            }]
+        );
+    }
+}
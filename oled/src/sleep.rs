@@ -0,0 +1,251 @@
+//! Sleep timer that stops playback after a while or at the end of the
+//! playing track or chapter, fading the volume out before it does.
+
+use std::time::{Duration, Instant};
+
+use crate::mpv::Chapter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepMode {
+    Minutes(u64),
+    EndOfTrack,
+    EndOfChapter,
+}
+
+/// What the Player menu steps through, the timer is off after the last one
+const MODES: [SleepMode; 5] = [
+    SleepMode::Minutes(15),
+    SleepMode::Minutes(30),
+    SleepMode::Minutes(60),
+    SleepMode::EndOfTrack,
+    SleepMode::EndOfChapter,
+];
+
+impl SleepMode {
+    /// The mode after `mode` in the Player menu, `None` being off. End of
+    /// chapter is skipped for files without chapters.
+    pub fn next(mode: Option<SleepMode>, has_chapters: bool) -> Option<SleepMode> {
+        let start = match mode {
+            Some(mode) => MODES.iter().position(|&m| m == mode).map_or(0, |i| i + 1),
+            None => 0,
+        };
+        MODES[start..]
+            .iter()
+            .copied()
+            .find(|&m| m != SleepMode::EndOfChapter || has_chapters)
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            SleepMode::Minutes(minutes) => format!("{} min", minutes),
+            SleepMode::EndOfTrack => "end of track".to_string(),
+            SleepMode::EndOfChapter => "end of chapter".to_string(),
+        }
+    }
+}
+
+/// Where playback is, times are in seconds of the file
+#[derive(Debug, Clone, Copy)]
+pub struct Playback<'a> {
+    pub position: u32,
+    pub duration: u32,
+    pub chapters: &'a [Chapter],
+    pub chapter: Option<usize>,
+    pub speed: f64,
+}
+
+impl Playback<'_> {
+    /// Where the current chapter ends, the last one ends with the file
+    fn chapter_end(&self) -> Option<u32> {
+        if self.chapters.is_empty() {
+            return None;
+        }
+        let next = self.chapter.map_or(0, |chapter| chapter + 1);
+        match self.chapters.get(next) {
+            Some(chapter) => Some(chapter.start),
+            None if self.duration > 0 => Some(self.duration),
+            None => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepAction {
+    Wait,
+    /// Fading out, the volume should be at this many percent now
+    Volume(u8),
+    /// Time is up, pause playback
+    Sleep,
+}
+
+#[derive(Debug, Clone)]
+pub struct SleepTimer {
+    pub mode: SleepMode,
+    started: Instant,
+    fade: Duration,
+    /// The volume when fading started, restored once playback is paused
+    fade_from: Option<u8>,
+    /// Playback went past the end of the track or chapter in between ticks
+    reached: bool,
+}
+
+impl SleepTimer {
+    pub fn new(mode: SleepMode, now: Instant, fade: Duration) -> Self {
+        Self {
+            mode,
+            started: now,
+            fade,
+            fade_from: None,
+            reached: false,
+        }
+    }
+
+    /// Time left until playback is paused, `None` while that is not known
+    /// yet, like before mpv reported the duration of the file
+    pub fn remaining(&self, now: Instant, playback: &Playback) -> Option<Duration> {
+        if self.reached {
+            return Some(Duration::ZERO);
+        }
+        let end = match self.mode {
+            SleepMode::Minutes(minutes) => {
+                let deadline = self.started + Duration::from_secs(minutes * 60);
+                return Some(deadline.saturating_duration_since(now));
+            }
+            SleepMode::EndOfTrack if playback.duration > 0 => playback.duration,
+            SleepMode::EndOfTrack => return None,
+            SleepMode::EndOfChapter => playback.chapter_end()?,
+        };
+        // Faster playback gets there sooner
+        let left = end.saturating_sub(playback.position) as f64 / playback.speed.max(0.1);
+        Some(Duration::from_secs_f64(left))
+    }
+
+    /// Called every frame, `volume` is where a fade starts from
+    pub fn tick(&mut self, now: Instant, volume: u8, playback: &Playback) -> SleepAction {
+        let Some(remaining) = self.remaining(now, playback) else {
+            return SleepAction::Wait;
+        };
+        if remaining.is_zero() {
+            return SleepAction::Sleep;
+        }
+        if remaining >= self.fade {
+            return SleepAction::Wait;
+        }
+        let from = *self.fade_from.get_or_insert(volume);
+        let left = remaining.as_secs_f64() / self.fade.as_secs_f64();
+        SleepAction::Volume((from as f64 * left).ceil() as u8)
+    }
+
+    /// Called when the playing file finished, ends a timer waiting for the
+    /// end of the track
+    pub fn track_ended(&mut self) {
+        if self.mode == SleepMode::EndOfTrack {
+            self.reached = true;
+        }
+    }
+
+    /// Called when the chapter changed from the one in `before`, ends a
+    /// timer waiting for the end of the chapter if it was about to end rather
+    /// than skipped
+    pub fn chapter_changed(&mut self, before: &Playback) {
+        if self.mode != SleepMode::EndOfChapter {
+            return;
+        }
+        if let Some(end) = before.chapter_end() {
+            self.reached |= end.saturating_sub(before.position) <= 2;
+        }
+    }
+
+    /// The volume to go back to after pausing, if fading changed it
+    pub fn fade_from(&self) -> Option<u8> {
+        self.fade_from
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FADE: Duration = Duration::from_secs(60);
+
+    fn playback(position: u32, chapters: &[Chapter]) -> Playback<'_> {
+        Playback {
+            position,
+            duration: 600,
+            chapters,
+            chapter: Some(0),
+            speed: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_next_mode() {
+        let mut mode = None;
+        let mut seen = Vec::new();
+        loop {
+            mode = SleepMode::next(mode, false);
+            match mode {
+                Some(mode) => seen.push(mode.label()),
+                None => break,
+            }
+        }
+        assert_eq!(seen, ["15 min", "30 min", "60 min", "end of track"]);
+        assert_eq!(
+            SleepMode::next(Some(SleepMode::EndOfTrack), true),
+            Some(SleepMode::EndOfChapter)
+        );
+    }
+
+    #[test]
+    fn test_fades_out_over_the_last_minute() {
+        let start = Instant::now();
+        let mut timer = SleepTimer::new(SleepMode::Minutes(15), start, FADE);
+        let playing = playback(0, &[]);
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(timer.tick(at(60), 80, &playing), SleepAction::Wait);
+        assert_eq!(
+            timer.remaining(at(60), &playing),
+            Some(Duration::from_secs(14 * 60))
+        );
+        assert_eq!(timer.tick(at(870), 80, &playing), SleepAction::Volume(40));
+        // Fades from where it started even though the volume went down
+        assert_eq!(timer.tick(at(885), 40, &playing), SleepAction::Volume(20));
+        assert_eq!(timer.tick(at(900), 20, &playing), SleepAction::Sleep);
+        assert_eq!(timer.fade_from(), Some(80));
+    }
+
+    #[test]
+    fn test_end_of_track_and_chapter() {
+        let now = Instant::now();
+        let mut timer = SleepTimer::new(SleepMode::EndOfTrack, now, FADE);
+        let mut fast = playback(300, &[]);
+        fast.speed = 2.0;
+        assert_eq!(timer.remaining(now, &fast), Some(Duration::from_secs(150)));
+        timer.track_ended();
+        assert_eq!(timer.tick(now, 50, &fast), SleepAction::Sleep);
+
+        let chapters = [
+            Chapter {
+                title: String::new(),
+                start: 0,
+            },
+            Chapter {
+                title: String::new(),
+                start: 240,
+            },
+        ];
+        let mut timer = SleepTimer::new(SleepMode::EndOfChapter, now, FADE);
+        assert_eq!(
+            timer.remaining(now, &playback(200, &chapters)),
+            Some(Duration::from_secs(40))
+        );
+        // Skipping to the next chapter does not count
+        let mut next = playback(240, &chapters);
+        next.chapter = Some(1);
+        timer.chapter_changed(&playback(100, &chapters));
+        assert_eq!(timer.tick(now, 50, &next), SleepAction::Wait);
+        timer.chapter_changed(&playback(239, &chapters));
+        assert_eq!(timer.tick(now, 50, &next), SleepAction::Sleep);
+    }
+}
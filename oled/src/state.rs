@@ -0,0 +1,1969 @@
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use bitmap_font::{tamzen::FONT_5x9, TextStyle};
+use chrono::{Local, NaiveDateTime};
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*, text::Text};
+use local_ip_address::local_ip;
+use macaddr::MacAddr6;
+use tokio::process::Command;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    alarm::{Alarm, Alarms, Ramp},
+    audio::AudioEvent,
+    bluetooth::{BluetoothEvent, BluetoothRequest, Device, Pairing, PairingReply},
+    browser::{Browser, EntryKind},
+    buttons::{Button, Buttons},
+    config::{Config, SleepConfig},
+    display::Display,
+    joystick::{self, Joystick},
+    library::{Library, LibraryEvent},
+    mpv::{modes::Repeat, speed, Chapter, MediaSource, MpvEvent, MpvRequest},
+    playlist,
+    radio::{self, Station},
+    sleep::{Playback, SleepAction, SleepMode, SleepTimer},
+    views::{Node, View, ViewEntry, Views},
+};
+
+/// How far B2 + up/down seeks on the Player tab
+const SEEK_STEP_SECS: f64 = 10.0;
+
+/// 8x7 icons, one byte per row with the leftmost pixel in the highest bit
+const SHUFFLE_ICON: [u8; 7] = [
+    0b00000100, 0b11001111, 0b00100100, 0b00011000, 0b00100100, 0b11001111, 0b00000100,
+];
+const REPEAT_ICON: [u8; 7] = [
+    0b00001000, 0b01111110, 0b01001010, 0b01000010, 0b01010010, 0b01111110, 0b00010000,
+];
+/// Only 5 pixels wide so it takes up a single character in the header
+const SLEEP_ICON: [u8; 7] = [
+    0b00110000, 0b01100000, 0b11000000, 0b11000000, 0b11000000, 0b01100000, 0b00110000,
+];
+/// Shown next to the left arrow while a known Bluetooth device is reconnected
+const BLUETOOTH_ICON: [u8; 7] = [
+    0b00110000, 0b10101000, 0b01110000, 0b00100000, 0b01110000, 0b10101000, 0b00110000,
+];
+
+/// Rows of the menu opened by clicking on the Player tab before the
+/// bookmarks of the playing file
+const PLAYER_MENU_ROWS: i32 = 5;
+
+/// Rows of the menu opened by clicking on an alarm on the Clock tab
+const ALARM_MENU_ROWS: i32 = 6;
+
+/// How long after the sleep timer paused playback the volume goes back up,
+/// so what mpv has buffered is not heard
+const VOLUME_RESTORE_DELAY: Duration = Duration::from_secs(1);
+/// How long a failed connection is shown on the Bluetooth tab
+const BT_NOTICE_DURATION: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tab {
+    Files,
+    Network,
+    Bluetooth,
+    Player,
+    Queue,
+    Clock,
+}
+
+pub struct State {
+    pub display: Display,
+    pub joystick: Joystick,
+    pub buttons: Buttons,
+    pub devices: Vec<Device>,
+    pub open_tab: Tab,
+    pub ip: Option<IpAddr>,
+    pub browser: Browser,
+    /// Where the Files tab is, the browser is used in the Folders view
+    pub views: Views,
+    library: Library,
+    font_width: i32,
+    font_height: i32,
+    pub running: bool,
+    max_files: i32,
+    max_len: usize,
+    bt_scroll: i32,
+    bt_cursor: i32,
+    /// Shown at the bottom of the Bluetooth tab until the instant passes
+    bt_notice: Option<(String, Instant)>,
+    /// Question of a device being paired, shown over every tab
+    pairing_prompt: Option<(MacAddr6, Pairing, Option<PairingReply>)>,
+    /// Attempts to connect a known Bluetooth device again
+    bt_reconnecting: Option<u32>,
+    /// What audio is played through, shown on the Player tab
+    output: Option<String>,
+    bt_channel: tokio::sync::mpsc::Sender<BluetoothRequest>,
+    pub mpv_channel: tokio::sync::mpsc::Sender<MpvRequest>,
+    player_status: PlayerStatus,
+    system_volume: u8,
+    track_position: u32,
+    track_duration: u32,
+    filename_scroll_offset: usize,
+    filename_scroll_counter: u32,
+    wifi_enabled: bool,
+    queue: Vec<PathBuf>,
+    queue_current: Option<usize>,
+    queue_scroll: i32,
+    queue_cursor: i32,
+    /// Where the queue is saved as a playlist
+    playlists_dir: PathBuf,
+    /// File name the queue was saved as, until it changes
+    saved_queue: Option<String>,
+    shuffle: bool,
+    repeat: Repeat,
+    /// Selected row while the shuffle/repeat menu is open
+    player_menu: Option<i32>,
+    /// Bookmarks of the playing file in seconds
+    bookmarks: Vec<u32>,
+    /// File and position that can be resumed from before the restart
+    resume_offer: Option<(PathBuf, u32)>,
+    sleep_config: SleepConfig,
+    sleep_timer: Option<SleepTimer>,
+    /// Volume to set again once the sleep timer's fade is over
+    volume_restore: Option<(Instant, u8)>,
+    /// The sleep timer turned the display off
+    display_asleep: bool,
+    /// Local time as of the last update, alarms due since then go off
+    pub now: NaiveDateTime,
+    pub alarms: Alarms,
+    alarms_path: PathBuf,
+    alarm_ramp_secs: u64,
+    /// Raises the volume after an alarm went off
+    alarm_ramp: Option<Ramp>,
+    /// Selected row on the Clock tab, the last one adds an alarm
+    alarm_cursor: i32,
+    /// Alarm being edited and the selected row of its menu
+    alarm_menu: Option<(usize, i32)>,
+    /// Alarm whose sound is being picked on the Files tab
+    picking_sound: Option<usize>,
+    /// Listed in the Radio view
+    pub stations: Vec<Station>,
+    stations_path: PathBuf,
+    station_cursor: i32,
+    station_scroll: i32,
+    /// Attempts to get a dropped stream back, `None` while it plays
+    reconnecting: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlayerStatus {
+    pub is_playing: bool,
+    pub current_file: Option<String>,
+    pub current_path: Option<PathBuf>,
+    pub chapters: Vec<Chapter>,
+    pub chapter: Option<usize>,
+    pub speed: f64,
+    /// What a radio station says is playing
+    pub stream_title: Option<String>,
+}
+
+impl State {
+    pub fn new(
+        display: Display,
+        joystick: Joystick,
+        buttons: Buttons,
+        config: &Config,
+        bt_channel: tokio::sync::mpsc::Sender<BluetoothRequest>,
+        mpv_channel: tokio::sync::mpsc::Sender<MpvRequest>,
+    ) -> Result<Self> {
+        let available_height = display.height() - 10;
+        let font_width = 5;
+        let font_height = 9;
+        let max_files = available_height / font_height;
+        let max_len = display.width() as usize / font_width as usize;
+        let library = Library::default();
+        let alarms_path = config.storage.alarms_path();
+        let alarms = Alarms::load(&alarms_path).unwrap_or_else(|e| {
+            warn!("Could not read {:?}: {}", alarms_path, e);
+            Alarms::default()
+        });
+        let stations_path = config.radio.stations.clone();
+        let stations = radio::load_stations(&stations_path).unwrap_or_else(|e| {
+            warn!(
+                "Could not read the stations from {:?}: {}",
+                stations_path, e
+            );
+            Vec::new()
+        });
+        let mut views = Views::new(max_files as usize);
+        views.open(View::Folders, &library);
+        Ok(Self {
+            display,
+            joystick,
+            buttons,
+            open_tab: Tab::Files,
+            devices: Vec::new(),
+            ip: local_ip().ok(),
+            browser: Browser::new(config.audio.roots.clone(), max_files as usize),
+            views,
+            library,
+            font_width,
+            font_height,
+            running: true,
+            max_files,
+            max_len,
+            bt_scroll: 0,
+            bt_cursor: 0,
+            bt_notice: None,
+            pairing_prompt: None,
+            bt_reconnecting: None,
+            output: None,
+            bt_channel,
+            mpv_channel,
+            player_status: PlayerStatus {
+                is_playing: false,
+                current_file: None,
+                current_path: None,
+                chapters: Vec::new(),
+                chapter: None,
+                speed: 1.0,
+                stream_title: None,
+            },
+            system_volume: 50,
+            track_position: 0,
+            track_duration: 0,
+            filename_scroll_offset: 0,
+            filename_scroll_counter: 0,
+            wifi_enabled: true,
+            queue: Vec::new(),
+            queue_current: None,
+            queue_scroll: 0,
+            queue_cursor: 0,
+            playlists_dir: config.audio.playlists_dir(),
+            saved_queue: None,
+            shuffle: false,
+            repeat: Repeat::Off,
+            player_menu: None,
+            bookmarks: Vec::new(),
+            resume_offer: None,
+            sleep_config: config.sleep.clone(),
+            sleep_timer: None,
+            volume_restore: None,
+            display_asleep: false,
+            now: Local::now().naive_local(),
+            alarms,
+            alarms_path,
+            alarm_ramp_secs: config.alarm.ramp_secs,
+            alarm_ramp: None,
+            alarm_cursor: 0,
+            alarm_menu: None,
+            picking_sound: None,
+            stations,
+            stations_path,
+            station_cursor: 0,
+            station_scroll: 0,
+            reconnecting: None,
+        })
+    }
+
+    pub fn draw(&mut self) {
+        match self.open_tab {
+            _ if self.pairing_prompt.is_some() => self.draw_pairing_prompt(),
+            Tab::Files => self.draw_files_tab(),
+            Tab::Network => self.draw_network_tab(),
+            Tab::Bluetooth => self.draw_bluetooth_tab(),
+            Tab::Player => self.draw_player_tab(),
+            Tab::Queue => self.draw_queue_tab(),
+            Tab::Clock => self.draw_clock_tab(),
+        }
+
+        // The sleep timer's icon and remaining time follow the tab's name
+        let sleep = self
+            .sleep_timer
+            .as_ref()
+            .map(|_| match self.sleep_remaining() {
+                Some(remaining) => format!("  {}", format_time(remaining.as_secs() as u32)),
+                None => "  ".to_string(),
+            });
+        let sleep = sleep.unwrap_or_default();
+        let room = self.max_len - 4 - sleep.chars().count();
+        let label = match self.open_tab {
+            _ if self.pairing_prompt.is_some() => "Pairing".to_string(),
+            Tab::Files if self.in_folders() => match self.browser.breadcrumb() {
+                Some(path) => clip_breadcrumb(&path, room),
+                None => "Files".to_string(),
+            },
+            Tab::Files => match self.views.breadcrumb() {
+                Some(path) => clip_breadcrumb(&path, room),
+                None => Node::Views.name().to_string(),
+            },
+            Tab::Network => "Network".to_string(),
+            Tab::Bluetooth => "Bluetooth".to_string(),
+            Tab::Player => "Player".to_string(),
+            Tab::Queue => "Queue".to_string(),
+            Tab::Clock => "Clock".to_string(),
+        };
+        let name_width = label.chars().count() as i32 * self.font_width;
+        let label = format!("{}{}", label, sleep);
+        // Centered text
+        let label_width = label.chars().count() as i32 * self.font_width;
+        let label_x = (self.display.width() - label_width) / 2;
+        if self.sleep_timer.is_some() {
+            self.draw_icon(label_x + name_width + self.font_width, 1, &SLEEP_ICON);
+        }
+        if self.bt_reconnecting.is_some() {
+            self.draw_icon(self.font_width + 2, 1, &BLUETOOTH_ICON);
+        }
+        let tab_text = Text::new(
+            &label,
+            Point::new(label_x, 0),
+            TextStyle::new(&FONT_5x9, BinaryColor::On),
+        );
+        let left_arrow = Text::new(
+            "<",
+            Point::new(0, 0),
+            TextStyle::new(&FONT_5x9, BinaryColor::On),
+        );
+        let right_arrow = Text::new(
+            ">",
+            Point::new(self.display.width() - 5, 0),
+            TextStyle::new(&FONT_5x9, BinaryColor::On),
+        );
+
+        left_arrow.draw(&mut self.display).unwrap();
+        right_arrow.draw(&mut self.display).unwrap();
+        tab_text.draw(&mut self.display).unwrap();
+    }
+
+    fn in_folders(&self) -> bool {
+        *self.views.node() == Node::View(View::Folders)
+    }
+
+    fn in_radio(&self) -> bool {
+        *self.views.node() == Node::View(View::Radio)
+    }
+
+    /// Draws one row of a list below the header, inverted when selected
+    fn draw_list_row(&mut self, row: i32, label: &str, selected: bool) {
+        let y = 10 + row * self.font_height;
+        if selected {
+            self.display.draw_rect(
+                0,
+                y as u8,
+                self.display.width() as u8,
+                self.font_height as u8,
+                BinaryColor::On,
+            );
+        }
+        let text_color = if selected {
+            BinaryColor::Off
+        } else {
+            BinaryColor::On
+        };
+        let label: String = label.chars().take(self.max_len).collect();
+        let text = Text::new(
+            &label,
+            Point::new(0, y),
+            TextStyle::new(&FONT_5x9, text_color),
+        );
+        text.draw(&mut self.display).unwrap();
+    }
+
+    fn draw_files_tab(&mut self) {
+        if self.in_radio() {
+            self.draw_radio_view();
+            return;
+        }
+        if !self.in_folders() {
+            self.draw_library_view();
+            return;
+        }
+
+        let scroll = self.browser.scroll();
+        let rows: Vec<(String, bool)> = self
+            .browser
+            .entries()
+            .iter()
+            .enumerate()
+            .skip(scroll)
+            .take(self.max_files as usize)
+            .map(|(i, entry)| {
+                // Tracks of a playlist are named by it unless they have tags
+                let label = match entry.kind {
+                    EntryKind::File => self
+                        .library
+                        .get(&entry.path)
+                        .and_then(|info| info.display_name())
+                        .unwrap_or_else(|| entry.label()),
+                    _ => entry.label(),
+                };
+                (label, self.browser.cursor() == i)
+            })
+            .collect();
+        for (row, (label, selected)) in rows.iter().enumerate() {
+            self.draw_list_row(row as i32, label, *selected);
+        }
+
+        // Below the ".." entry that leads back out
+        if self.browser.error().is_some() {
+            let first_row = self.browser.entries().len() as i32;
+            for (i, line) in ["Can't read this", "directory"].iter().enumerate() {
+                let text = Text::new(
+                    line,
+                    Point::new(0, 10 + (first_row + i as i32) * self.font_height),
+                    TextStyle::new(&FONT_5x9, BinaryColor::On),
+                );
+                text.draw(&mut self.display).unwrap();
+            }
+        }
+    }
+
+    fn draw_radio_view(&mut self) {
+        if self.stations.is_empty() {
+            self.draw_list_row(0, "No stations", false);
+            return;
+        }
+        let playing = self.player_status.current_path.clone();
+        let visible = self.station_scroll..self.station_scroll + self.max_files;
+        let rows: Vec<(i32, String)> = self
+            .stations
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| visible.contains(&(*i as i32)))
+            .map(|(i, station)| {
+                let marker = if playing.as_deref() == Some(Path::new(&station.url)) {
+                    '>'
+                } else {
+                    ' '
+                };
+                (i as i32, format!("{}{}", marker, station.name))
+            })
+            .collect();
+        for (i, label) in rows {
+            let selected = i == self.station_cursor;
+            self.draw_list_row(i - self.station_scroll, &label, selected);
+        }
+    }
+
+    fn draw_library_view(&mut self) {
+        let scroll = self.views.scroll();
+        let rows: Vec<(String, bool)> = self
+            .views
+            .entries()
+            .iter()
+            .enumerate()
+            .skip(scroll)
+            .take(self.max_files as usize)
+            .map(|(i, entry)| {
+                let label = match entry {
+                    ViewEntry::Node(node) => format!("{}/", node.name()),
+                    ViewEntry::Track(path) => self.track_label(path),
+                };
+                (label, self.views.cursor() == i)
+            })
+            .collect();
+        for (row, (label, selected)) in rows.iter().enumerate() {
+            self.draw_list_row(row as i32, label, *selected);
+        }
+
+        if self.views.entries().is_empty() {
+            let message = if self.library.is_empty() {
+                "No tracks indexed"
+            } else {
+                "Nothing here"
+            };
+            self.draw_list_row(0, message, false);
+        }
+    }
+
+    fn draw_network_tab(&mut self) {
+        let label = match self.ip {
+            Some(ip) => format!("IP: {}", ip),
+            None => "IP: none".to_string(),
+        };
+        let ip_text = Text::new(
+            &label,
+            Point::new(0, 10),
+            TextStyle::new(&FONT_5x9, BinaryColor::On),
+        );
+        ip_text.draw(&mut self.display).unwrap();
+
+        let wifi_status = if self.wifi_enabled { "ON" } else { "OFF" };
+        let wifi_label = format!("WiFi: {}", wifi_status);
+        let wifi_text = Text::new(
+            &wifi_label,
+            Point::new(0, 20),
+            TextStyle::new(&FONT_5x9, BinaryColor::On),
+        );
+        wifi_text.draw(&mut self.display).unwrap();
+
+        let instruction_text = Text::new(
+            "B1: Toggle WiFi",
+            Point::new(0, 40),
+            TextStyle::new(&FONT_5x9, BinaryColor::On),
+        );
+        instruction_text.draw(&mut self.display).unwrap();
+    }
+
+    fn draw_bluetooth_tab(&mut self) {
+        for (i, device) in self.devices.iter().enumerate() {
+            if (i as i32) >= self.bt_scroll && (i as i32) < self.bt_scroll + self.max_files {
+                let text_color = if self.bt_cursor == i as i32 {
+                    BinaryColor::Off
+                } else {
+                    BinaryColor::On
+                };
+                if self.bt_cursor == i as i32 {
+                    self.display.draw_rect(
+                        0,
+                        (10 + (i as i32 - self.bt_scroll) * self.font_height) as u8,
+                        self.display.width() as u8,
+                        self.font_height as u8,
+                        BinaryColor::On,
+                    );
+                }
+                let mut label: Vec<char> = device.name.chars().take(self.max_len).collect();
+                label.resize(self.max_len, ' ');
+                label[self.max_len - 3] = if device.connected { 'o' } else { 'x' };
+                label[self.max_len - 2] = if device.trusted { 'o' } else { 'x' };
+                label[self.max_len - 1] = if device.paired { 'o' } else { 'x' };
+                let label: String = label.into_iter().collect();
+
+                let text = Text::new(
+                    &label,
+                    Point::new(0, 10 + ((i as i32 - self.bt_scroll) * self.font_height)),
+                    TextStyle::new(&FONT_5x9, text_color),
+                );
+                text.draw(&mut self.display).unwrap();
+            }
+        }
+
+        if let Some((notice, until)) = &self.bt_notice {
+            if Instant::now() < *until {
+                let y = 10 + (self.max_files - 1) * self.font_height;
+                self.display.draw_rect(
+                    0,
+                    y as u8,
+                    self.display.width() as u8,
+                    self.font_height as u8,
+                    BinaryColor::On,
+                );
+                let notice: String = notice.chars().take(self.max_len).collect();
+                Text::new(
+                    &notice,
+                    Point::new(0, y),
+                    TextStyle::new(&FONT_5x9, BinaryColor::Off),
+                )
+                .draw(&mut self.display)
+                .unwrap();
+            }
+        }
+    }
+
+    fn draw_pairing_prompt(&mut self) {
+        let Some((addr, pairing, reply)) = &self.pairing_prompt else {
+            return;
+        };
+        let question = match pairing {
+            Pairing::Confirm(passkey) => format!("Passkey {}?", passkey),
+            Pairing::Display(passkey) => format!("Type {}", passkey),
+            Pairing::PinCode(pin) => format!("Use PIN {}?", pin),
+            Pairing::Authorize => "Allow pairing?".to_string(),
+        };
+        let answer = if reply.is_some() {
+            "B1: yes  B2: no"
+        } else {
+            "B1/B2: hide"
+        };
+        let rows = [
+            self.device_name(*addr).chars().take(self.max_len).collect(),
+            question,
+            answer.to_string(),
+        ];
+        for (i, label) in rows.iter().enumerate() {
+            self.draw_list_row(i as i32, label, false);
+        }
+    }
+
+    fn draw_player_tab(&mut self) {
+        if let Some((file, position)) = self.resume_offer.clone() {
+            let title = self.track_label(&file);
+            let rows = [
+                "Resume?".to_string(),
+                title.chars().take(self.max_len).collect(),
+                format!("at {}", format_time(position)),
+                "B1: yes  B2: no".to_string(),
+            ];
+            for (i, label) in rows.iter().enumerate() {
+                self.draw_list_row(i as i32, label, false);
+            }
+            return;
+        }
+
+        if let Some(selected) = self.player_menu {
+            self.draw_player_menu(selected);
+            return;
+        }
+
+        let right = self.display.width();
+        if self.shuffle {
+            self.draw_icon(right - 25, 11, &SHUFFLE_ICON);
+        }
+        if self.repeat != Repeat::Off {
+            self.draw_icon(right - 15, 11, &REPEAT_ICON);
+        }
+        if self.repeat == Repeat::One {
+            let one = Text::new(
+                "1",
+                Point::new(right - 5, 10),
+                TextStyle::new(&FONT_5x9, BinaryColor::On),
+            );
+            one.draw(&mut self.display).unwrap();
+        }
+
+        let mut status = if self.reconnecting.is_some() {
+            "Reconnecting".to_string()
+        } else if self.player_status.is_playing {
+            "Playing".to_string()
+        } else {
+            "Paused".to_string()
+        };
+        if self.player_status.speed != 1.0 {
+            status = format!("{} {}x", status, self.player_status.speed);
+        }
+        let status_text = Text::new(
+            &status,
+            Point::new(0, 10),
+            TextStyle::new(&FONT_5x9, BinaryColor::On),
+        );
+        status_text.draw(&mut self.display).unwrap();
+
+        let mut volume_label = format!("Vol: {}%", self.system_volume);
+        if let Some(output) = &self.output {
+            volume_label = format!("{}  {}", volume_label, output);
+        }
+        let volume_label: String = volume_label.chars().take(self.max_len).collect();
+        let volume_text = Text::new(
+            &volume_label,
+            Point::new(0, 20),
+            TextStyle::new(&FONT_5x9, BinaryColor::On),
+        );
+        volume_text.draw(&mut self.display).unwrap();
+
+        if self.track_duration > 0 {
+            let progress_label = format!(
+                "{} / {}",
+                format_time(self.track_position),
+                format_time(self.track_duration)
+            );
+            let progress_text = Text::new(
+                &progress_label,
+                Point::new(0, 30),
+                TextStyle::new(&FONT_5x9, BinaryColor::On),
+            );
+            progress_text.draw(&mut self.display).unwrap();
+        }
+
+        if let Some(title) = self.player_title() {
+            let display_name = self.get_scrolling_text(&title);
+            let file_text = Text::new(
+                &display_name,
+                Point::new(0, 40),
+                TextStyle::new(&FONT_5x9, BinaryColor::On),
+            );
+            file_text.draw(&mut self.display).unwrap();
+        }
+
+        if let Some(chapter) = self.player_status.chapter {
+            let count = self.player_status.chapters.len();
+            let label = match self.player_status.chapters.get(chapter) {
+                Some(Chapter { title, .. }) if !title.is_empty() => {
+                    format!("{}/{} {}", chapter + 1, count, title)
+                }
+                _ => format!("Chapter {}/{}", chapter + 1, count),
+            };
+            let label: String = label.chars().take(self.max_len).collect();
+            let chapter_text = Text::new(
+                &label,
+                Point::new(0, 50),
+                TextStyle::new(&FONT_5x9, BinaryColor::On),
+            );
+            chapter_text.draw(&mut self.display).unwrap();
+        } else if let Some(title) = self.player_status.stream_title.clone() {
+            let label: String = title.chars().take(self.max_len).collect();
+            let stream_text = Text::new(
+                &label,
+                Point::new(0, 50),
+                TextStyle::new(&FONT_5x9, BinaryColor::On),
+            );
+            stream_text.draw(&mut self.display).unwrap();
+        }
+    }
+
+    fn draw_queue_tab(&mut self) {
+        if self.queue.is_empty() {
+            let text = Text::new(
+                "Queue is empty",
+                Point::new(0, 10),
+                TextStyle::new(&FONT_5x9, BinaryColor::On),
+            );
+            text.draw(&mut self.display).unwrap();
+            return;
+        }
+
+        let visible = self.queue_scroll..self.queue_scroll + self.max_files;
+        for (i, file) in self.queue.iter().enumerate() {
+            if !visible.contains(&(i as i32)) {
+                continue;
+            }
+            let y = 10 + (i as i32 - self.queue_scroll) * self.font_height;
+            let selected = self.queue_cursor == i as i32;
+            if selected {
+                self.display.draw_rect(
+                    0,
+                    y as u8,
+                    self.display.width() as u8,
+                    self.font_height as u8,
+                    BinaryColor::On,
+                );
+            }
+            let marker = if self.queue_current == Some(i) {
+                '>'
+            } else {
+                ' '
+            };
+            let label: String = std::iter::once(marker)
+                .chain(self.track_label(file).chars())
+                .take(self.max_len)
+                .collect();
+            let text_color = if selected {
+                BinaryColor::Off
+            } else {
+                BinaryColor::On
+            };
+            let text = Text::new(
+                &label,
+                Point::new(0, y),
+                TextStyle::new(&FONT_5x9, text_color),
+            );
+            text.draw(&mut self.display).unwrap();
+        }
+
+        let save_row = self.queue.len() as i32;
+        if visible.contains(&save_row) {
+            let label = match &self.saved_queue {
+                Some(name) => format!("Saved {}", name),
+                None => "Save as playlist".to_string(),
+            };
+            let selected = self.queue_cursor == save_row;
+            self.draw_list_row(save_row - self.queue_scroll, &label, selected);
+        }
+        let clear_row = save_row + 1;
+        if visible.contains(&clear_row) {
+            let selected = self.queue_cursor == clear_row;
+            self.draw_list_row(clear_row - self.queue_scroll, "Clear queue", selected);
+        }
+    }
+
+    fn draw_clock_tab(&mut self) {
+        if let Some((index, selected)) = self.alarm_menu {
+            self.draw_alarm_menu(index, selected);
+            return;
+        }
+
+        let now = self.now.format("%a %-d %b %H:%M").to_string();
+        self.draw_list_row(0, &now, false);
+        let mut rows: Vec<String> = self.alarms.list().iter().map(Alarm::label).collect();
+        rows.push("New alarm".to_string());
+        // The first row shows the time
+        let visible = self.max_files - 1;
+        let scroll = (self.alarm_cursor - visible + 1).max(0);
+        for (i, label) in rows.iter().enumerate().skip(scroll as usize) {
+            let row = i as i32 - scroll;
+            if row >= visible {
+                break;
+            }
+            self.draw_list_row(row + 1, label, self.alarm_cursor == i as i32);
+        }
+    }
+
+    fn draw_alarm_menu(&mut self, index: usize, selected: i32) {
+        let Some(alarm) = self.alarms.list().get(index).cloned() else {
+            return;
+        };
+        let sound = match &alarm.sound {
+            Some(path) => self.track_label(path),
+            None => "last played".to_string(),
+        };
+        let rows = [
+            format!("Hour: {:02}", alarm.hour),
+            format!("Minute: {:02}", alarm.minute),
+            format!("Days: {}", alarm.days.label()),
+            format!("Sound: {}", sound),
+            format!("Volume: {}%", alarm.volume),
+            "Delete".to_string(),
+        ];
+        for (i, label) in rows.iter().enumerate() {
+            self.draw_list_row(i as i32, label, selected == i as i32);
+        }
+    }
+
+    fn draw_player_menu(&mut self, selected: i32) {
+        let repeat = match self.repeat {
+            Repeat::Off => "off",
+            Repeat::One => "one",
+            Repeat::All => "all",
+        };
+        let mut rows = vec![
+            format!("Shuffle: {}", if self.shuffle { "on" } else { "off" }),
+            format!("Repeat: {}", repeat),
+            format!("Speed: {}x", self.player_status.speed),
+            format!(
+                "Sleep: {}",
+                self.sleep_timer
+                    .as_ref()
+                    .map_or("off".to_string(), |timer| timer.mode.label())
+            ),
+            "Add bookmark".to_string(),
+        ];
+        rows.extend(
+            self.bookmarks
+                .iter()
+                .map(|&position| format!("Go to {}", format_time(position))),
+        );
+        // Scrolls once the bookmarks don't fit anymore
+        let scroll = (selected - self.max_files + 1).max(0);
+        let visible = rows.iter().enumerate().skip(scroll as usize);
+        for (i, label) in visible.take(self.max_files as usize) {
+            self.draw_list_row(i as i32 - scroll, label, selected == i as i32);
+        }
+    }
+
+    fn draw_icon(&mut self, x: i32, y: i32, icon: &[u8]) {
+        for (row, bits) in icon.iter().enumerate() {
+            for col in 0..8 {
+                if bits & (0x80 >> col) != 0 {
+                    self.display
+                        .draw_pixel((x + col) as u8, (y + row as i32) as u8, true);
+                }
+            }
+        }
+    }
+
+    pub async fn update(&mut self) -> Result<()> {
+        self.buttons.update().unwrap();
+        self.joystick.update().unwrap();
+        if self.buttons.is_button_pressed(Button::B3) {
+            self.running = false;
+            return Ok(());
+        }
+
+        if let Ok(volume) = self.get_system_volume().await {
+            self.system_volume = volume;
+        }
+
+        if let Ok(wifi_status) = self.get_wifi_status().await {
+            self.wifi_enabled = wifi_status;
+        }
+
+        self.update_alarms().await;
+        self.update_sleep_timer().await;
+        // Waking the display up is all the first input does
+        if self.display_asleep {
+            let pressed = [Button::B1, Button::B2]
+                .into_iter()
+                .any(|button| self.buttons.is_button_pressed(button));
+            let pushed = [
+                joystick::State::Up,
+                joystick::State::Down,
+                joystick::State::Left,
+                joystick::State::Right,
+                joystick::State::Click,
+            ]
+            .into_iter()
+            .any(|state| self.joystick.just_switched_to(state));
+            if pressed || pushed {
+                self.wake_display();
+            }
+            return Ok(());
+        }
+
+        if let Some((_, _, reply)) = &self.pairing_prompt {
+            let accept = self.buttons.is_button_pressed(Button::B1);
+            if accept || self.buttons.is_button_pressed(Button::B2) {
+                if let Some(reply) = reply {
+                    reply.send(accept);
+                }
+                self.pairing_prompt = None;
+            }
+            return Ok(());
+        }
+
+        self.update_filename_scroll();
+        if self.open_tab != Tab::Files {
+            self.picking_sound = None;
+        }
+        match self.open_tab {
+            Tab::Files if self.in_radio() => {
+                if self.joystick.just_switched_to(joystick::State::Left) {
+                    self.views.leave(&self.library);
+                }
+                if self.joystick.just_switched_to(joystick::State::Right) {
+                    self.open_tab = Tab::Network;
+                }
+                if self.joystick.just_switched_to(joystick::State::Up) {
+                    self.move_station_cursor(-1);
+                }
+                if self.joystick.just_switched_to(joystick::State::Down) {
+                    self.move_station_cursor(1);
+                }
+                if self.buttons.is_button_pressed(Button::B1)
+                    || self.joystick.just_switched_to(joystick::State::Click)
+                {
+                    if let Some(station) = self.stations.get(self.station_cursor as usize) {
+                        info!("Tuning in to {}", station.name);
+                        let url = station.url.clone();
+                        self.stop_scan();
+                        self.request_mpv(MpvRequest::Play(MediaSource::Stream(url)));
+                    }
+                }
+            }
+            Tab::Files if !self.in_folders() => {
+                if self.joystick.just_switched_to(joystick::State::Left)
+                    && !self.views.leave(&self.library)
+                {
+                    self.open_tab = Tab::Clock;
+                }
+                if self.joystick.just_switched_to(joystick::State::Right)
+                    && !self.views.enter(&self.library)
+                {
+                    self.open_tab = Tab::Network;
+                }
+
+                if self.joystick.just_switched_to(joystick::State::Up) {
+                    self.views.move_cursor(-1);
+                }
+                if self.joystick.just_switched_to(joystick::State::Down) {
+                    self.views.move_cursor(1);
+                }
+
+                match self.views.selected().cloned() {
+                    Some(ViewEntry::Track(file)) => self.file_actions(file),
+                    Some(ViewEntry::Node(node)) => {
+                        // Play all, Folders has nothing to play and is entered
+                        let tracks = node.tracks(&self.library);
+                        if self.buttons.is_button_pressed(Button::B1) {
+                            if tracks.is_empty() {
+                                self.views.enter(&self.library);
+                            } else {
+                                self.stop_scan();
+                                self.request_mpv(MpvRequest::PlayAll(tracks.clone()));
+                            }
+                        }
+                        if self.buttons.is_button_pressed(Button::B2) && !tracks.is_empty() {
+                            self.request_mpv(MpvRequest::EnqueueAll(tracks));
+                        }
+                        if self.joystick.just_switched_to(joystick::State::Click) {
+                            self.views.enter(&self.library);
+                        }
+                    }
+                    None => {}
+                }
+                // Picks up changes to the stations file
+                if self.in_radio() {
+                    self.load_stations();
+                }
+            }
+            Tab::Files => {
+                // Left and right only switch views or tabs when there is no
+                // directory to leave or enter
+                if self.joystick.just_switched_to(joystick::State::Left) && !self.browser.leave() {
+                    self.views.leave(&self.library);
+                }
+                if self.joystick.just_switched_to(joystick::State::Right) && !self.browser.enter() {
+                    self.open_tab = Tab::Network;
+                }
+
+                if self.joystick.just_switched_to(joystick::State::Up) {
+                    self.browser.move_cursor(-1);
+                }
+                if self.joystick.just_switched_to(joystick::State::Down) {
+                    self.browser.move_cursor(1);
+                }
+
+                let selected = self.browser.selected().cloned();
+                match selected {
+                    Some(entry) if entry.kind == EntryKind::File => self.file_actions(entry.path),
+                    Some(entry) if entry.kind == EntryKind::Playlist => {
+                        self.playlist_actions(entry.path)
+                    }
+                    Some(_)
+                        if self.buttons.is_button_pressed(Button::B1)
+                            || self.joystick.just_switched_to(joystick::State::Click) =>
+                    {
+                        self.browser.enter();
+                    }
+                    _ => {}
+                }
+            }
+            Tab::Network => {
+                if self.joystick.just_switched_to(joystick::State::Left) {
+                    self.open_tab = Tab::Files;
+                }
+                if self.joystick.just_switched_to(joystick::State::Right) {
+                    self.open_tab = Tab::Bluetooth;
+                }
+                if self.buttons.is_button_pressed(Button::B1) {
+                    if let Err(e) = self.toggle_wifi().await {
+                        error!("Failed to toggle WiFi: {}", e);
+                    }
+                }
+            }
+            Tab::Bluetooth => {
+                if self.joystick.just_switched_to(joystick::State::Left) {
+                    self.open_tab = Tab::Network;
+                }
+                if self.joystick.just_switched_to(joystick::State::Right) {
+                    self.open_tab = Tab::Player;
+                }
+                if self.joystick.just_switched_to(joystick::State::Up) {
+                    self.move_bt_cursor(-1);
+                }
+                if self.joystick.just_switched_to(joystick::State::Down) {
+                    self.move_bt_cursor(1);
+                }
+                if let (true, Some(device)) = (
+                    self.buttons.is_button_pressed(Button::B1),
+                    self.devices.get(self.bt_cursor as usize),
+                ) {
+                    info!("Connecting to {}", device.name);
+                    self.bt_channel
+                        .send(BluetoothRequest::Connect(device.clone()))
+                        .await?;
+                }
+                if self.buttons.is_button_pressed(Button::B2)
+                    && !self.devices.is_empty()
+                    && (self.bt_cursor as usize) < self.devices.len()
+                {
+                    let device = &self.devices[self.bt_cursor as usize];
+                    if device.paired {
+                        info!("Unpairing {}", device.name);
+                        self.bt_channel
+                            .send(BluetoothRequest::Unpair(device.clone()))
+                            .await?;
+                    }
+                }
+            }
+            Tab::Player if self.player_menu.is_some() => {
+                let mut selected = self.player_menu.unwrap_or(0);
+                if self.joystick.just_switched_to(joystick::State::Up) {
+                    selected = (selected - 1).max(0);
+                }
+                let rows = PLAYER_MENU_ROWS + self.bookmarks.len() as i32;
+                if self.joystick.just_switched_to(joystick::State::Down) {
+                    selected = (selected + 1).min(rows - 1);
+                }
+                self.player_menu = Some(selected);
+                let bookmark = (selected - PLAYER_MENU_ROWS) as usize;
+                if self.buttons.is_button_pressed(Button::B1)
+                    || self.joystick.just_switched_to(joystick::State::Click)
+                {
+                    match selected {
+                        0 => self.request_mpv(MpvRequest::ToggleShuffle),
+                        1 => self.request_mpv(MpvRequest::CycleRepeat),
+                        2 => self.request_mpv(MpvRequest::SetSpeed(speed::next(
+                            self.player_status.speed,
+                        ))),
+                        3 => {
+                            let mode = self.sleep_timer.as_ref().map(|timer| timer.mode);
+                            let has_chapters = !self.player_status.chapters.is_empty();
+                            self.set_sleep_timer(SleepMode::next(mode, has_chapters));
+                        }
+                        4 => self.request_mpv(MpvRequest::AddBookmark),
+                        _ => {
+                            if let Some(&position) = self.bookmarks.get(bookmark) {
+                                self.request_mpv(MpvRequest::SeekAbsolute(position as f64));
+                                self.player_menu = None;
+                            }
+                        }
+                    }
+                }
+                // B2 resets the speed, turns the sleep timer off and removes
+                // bookmarks
+                if self.buttons.is_button_pressed(Button::B2) {
+                    if selected == 2 {
+                        self.request_mpv(MpvRequest::SetSpeed(1.0));
+                    } else if selected == 3 {
+                        self.set_sleep_timer(None);
+                    } else if selected >= PLAYER_MENU_ROWS {
+                        self.request_mpv(MpvRequest::RemoveBookmark(bookmark));
+                    }
+                }
+                if self.joystick.just_switched_to(joystick::State::Left) {
+                    self.player_menu = None;
+                }
+            }
+            Tab::Player if self.resume_offer.is_some() => {
+                if self.buttons.is_button_pressed(Button::B1) {
+                    self.stop_scan();
+                    self.request_mpv(MpvRequest::Resume);
+                    self.resume_offer = None;
+                }
+                if self.buttons.is_button_pressed(Button::B2) {
+                    self.resume_offer = None;
+                }
+                if self.joystick.just_switched_to(joystick::State::Left) {
+                    self.open_tab = Tab::Bluetooth;
+                }
+                if self.joystick.just_switched_to(joystick::State::Right) {
+                    self.open_tab = Tab::Queue;
+                }
+            }
+            // Holding B2 turns the joystick into transport controls, left and
+            // right skip chapters instead of files in files that have them
+            Tab::Player if self.buttons.is_button_held(Button::B2) => {
+                let (previous, next) = if self.player_status.chapters.is_empty() {
+                    (MpvRequest::Previous, MpvRequest::Next)
+                } else {
+                    (MpvRequest::PreviousChapter, MpvRequest::NextChapter)
+                };
+                let chords = [
+                    (joystick::State::Left, previous),
+                    (joystick::State::Right, next),
+                    (
+                        joystick::State::Up,
+                        MpvRequest::SeekRelative(SEEK_STEP_SECS),
+                    ),
+                    (
+                        joystick::State::Down,
+                        MpvRequest::SeekRelative(-SEEK_STEP_SECS),
+                    ),
+                    (joystick::State::Click, MpvRequest::Stop),
+                ];
+                for (state, request) in chords {
+                    if self.joystick.just_switched_to(state) {
+                        self.request_mpv(request);
+                    }
+                }
+            }
+            Tab::Player => {
+                if self.joystick.just_switched_to(joystick::State::Left) {
+                    self.open_tab = Tab::Bluetooth;
+                }
+                if self.joystick.just_switched_to(joystick::State::Right) {
+                    self.open_tab = Tab::Queue;
+                }
+                if self.joystick.just_switched_to(joystick::State::Click) {
+                    self.player_menu = Some(0);
+                }
+                if self.joystick.just_switched_to(joystick::State::Up) {
+                    self.alarm_ramp = None;
+                    if let Err(e) = self.volume_up().await {
+                        error!("Failed to increase volume: {}", e);
+                    }
+                }
+                if self.joystick.just_switched_to(joystick::State::Down) {
+                    self.alarm_ramp = None;
+                    if let Err(e) = self.volume_down().await {
+                        error!("Failed to decrease volume: {}", e);
+                    }
+                }
+                if self.buttons.is_button_pressed(Button::B1) {
+                    if let Err(e) = self.mpv_channel.try_send(MpvRequest::TogglePause) {
+                        error!("Failed to send TogglePause request: {}", e);
+                    }
+                }
+            }
+            Tab::Queue => {
+                if self.joystick.just_switched_to(joystick::State::Left) {
+                    self.open_tab = Tab::Player;
+                }
+                if self.joystick.just_switched_to(joystick::State::Right) {
+                    self.open_tab = Tab::Clock;
+                }
+                // Holding B2 drags the selected entry along with the cursor
+                for (state, direction) in [(joystick::State::Up, -1), (joystick::State::Down, 1)] {
+                    if !self.joystick.just_switched_to(state) {
+                        continue;
+                    }
+                    let from = self.queue_cursor;
+                    self.move_queue_cursor(direction);
+                    let on_entry = (self.queue_cursor as usize) < self.queue.len();
+                    if self.buttons.is_button_held(Button::B2)
+                        && from != self.queue_cursor
+                        && on_entry
+                    {
+                        self.request_mpv(MpvRequest::MoveInQueue {
+                            from: from as usize,
+                            to: self.queue_cursor as usize,
+                        });
+                    }
+                }
+                // The rows after the queue save it as a playlist and clear it
+                let clicked = self.joystick.just_switched_to(joystick::State::Click);
+                let pressed = self.buttons.is_button_pressed(Button::B1);
+                let cursor = self.queue_cursor as usize;
+                if cursor < self.queue.len() {
+                    if pressed {
+                        self.request_mpv(MpvRequest::RemoveFromQueue(cursor));
+                    }
+                    if clicked {
+                        self.request_mpv(MpvRequest::PlayFromQueue(cursor));
+                    }
+                } else if !self.queue.is_empty() && (pressed || clicked) {
+                    if cursor == self.queue.len() {
+                        self.save_queue();
+                    } else {
+                        self.request_mpv(MpvRequest::ClearQueue);
+                    }
+                }
+            }
+            Tab::Clock if self.alarm_menu.is_some() => self.alarm_menu_actions(),
+            Tab::Clock => {
+                if self.joystick.just_switched_to(joystick::State::Left) {
+                    self.open_tab = Tab::Queue;
+                }
+                if self.joystick.just_switched_to(joystick::State::Right) {
+                    self.open_tab = Tab::Files;
+                }
+                let last = self.alarms.list().len() as i32;
+                if self.joystick.just_switched_to(joystick::State::Up) {
+                    self.alarm_cursor = (self.alarm_cursor - 1).max(0);
+                }
+                if self.joystick.just_switched_to(joystick::State::Down) {
+                    self.alarm_cursor = (self.alarm_cursor + 1).min(last);
+                }
+                let index = self.alarm_cursor as usize;
+                // B1 turns the selected alarm on or off, a click edits it
+                if self.alarm_cursor == last {
+                    if self.buttons.is_button_pressed(Button::B1)
+                        || self.joystick.just_switched_to(joystick::State::Click)
+                    {
+                        let index = self.alarms.add(Alarm::default());
+                        self.save_alarms();
+                        self.alarm_menu = Some((index, 0));
+                    }
+                } else {
+                    if self.buttons.is_button_pressed(Button::B1) {
+                        if let Some(alarm) = self.alarms.get_mut(index) {
+                            alarm.enabled = !alarm.enabled;
+                        }
+                        self.save_alarms();
+                    }
+                    if self.joystick.just_switched_to(joystick::State::Click) {
+                        self.alarm_menu = Some((index, 0));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// B1 plays the selected file, B2 enqueues it and a click plays it next.
+    /// While picking an alarm's sound B1 picks it instead.
+    fn file_actions(&mut self, file: PathBuf) {
+        if let Some(index) = self.picking_sound {
+            if self.buttons.is_button_pressed(Button::B1) {
+                if let Some(alarm) = self.alarms.get_mut(index) {
+                    alarm.sound = Some(file);
+                }
+                self.save_alarms();
+                self.picking_sound = None;
+                self.open_tab = Tab::Clock;
+            }
+            return;
+        }
+        if self.buttons.is_button_pressed(Button::B1) {
+            info!("B1 pressed - loading file: {:?}", file);
+            self.stop_scan();
+            // Playlists can list streams next to files
+            let url = file.to_string_lossy();
+            let source = if radio::is_stream(&url) {
+                MediaSource::Stream(url.into_owned())
+            } else {
+                MediaSource::File(file.clone())
+            };
+            self.request_mpv(MpvRequest::Play(source));
+        }
+        if self.buttons.is_button_pressed(Button::B2) {
+            self.request_mpv(MpvRequest::Enqueue(file.clone()));
+        }
+        if self.joystick.just_switched_to(joystick::State::Click) {
+            self.request_mpv(MpvRequest::PlayNext(file));
+        }
+    }
+
+    /// B1 plays the playlist, B2 appends its tracks to the queue and a click
+    /// opens it. While picking an alarm's sound B1 picks it instead.
+    fn playlist_actions(&mut self, path: PathBuf) {
+        if self.joystick.just_switched_to(joystick::State::Click) {
+            self.browser.enter();
+            return;
+        }
+        if self.picking_sound.is_some() {
+            self.file_actions(path);
+            return;
+        }
+        let play = self.buttons.is_button_pressed(Button::B1);
+        let enqueue = self.buttons.is_button_pressed(Button::B2);
+        if !play && !enqueue {
+            return;
+        }
+        let tracks = match playlist::track_paths(&path) {
+            Ok(tracks) if !tracks.is_empty() => tracks,
+            Ok(_) => {
+                warn!("Nothing to play in {:?}", path);
+                return;
+            }
+            Err(e) => {
+                error!("Failed to read playlist: {}", e);
+                return;
+            }
+        };
+        if play {
+            info!("Playing playlist {:?}", path);
+            self.stop_scan();
+            self.request_mpv(MpvRequest::PlayAll(tracks));
+        } else {
+            self.request_mpv(MpvRequest::EnqueueAll(tracks));
+        }
+    }
+
+    /// Scanning for Bluetooth devices gets in the way of streaming audio
+    fn stop_scan(&self) {
+        if let Err(e) = self.bt_channel.try_send(BluetoothRequest::StopScan) {
+            error!("Failed to send StopScan request: {}", e);
+        }
+    }
+
+    /// B1 or a click raise the selected setting, B2 lowers it
+    fn alarm_menu_actions(&mut self) {
+        let Some((index, mut selected)) = self.alarm_menu else {
+            return;
+        };
+        if self.joystick.just_switched_to(joystick::State::Left) {
+            self.alarm_menu = None;
+            return;
+        }
+        if self.joystick.just_switched_to(joystick::State::Up) {
+            selected = (selected - 1).max(0);
+        }
+        if self.joystick.just_switched_to(joystick::State::Down) {
+            selected = (selected + 1).min(ALARM_MENU_ROWS - 1);
+        }
+        self.alarm_menu = Some((index, selected));
+
+        let direction = if self.buttons.is_button_pressed(Button::B1)
+            || self.joystick.just_switched_to(joystick::State::Click)
+        {
+            1
+        } else if self.buttons.is_button_pressed(Button::B2) {
+            -1
+        } else {
+            return;
+        };
+        let Some(alarm) = self.alarms.get_mut(index) else {
+            self.alarm_menu = None;
+            return;
+        };
+        match selected {
+            0 => alarm.shift(direction, 0),
+            1 => alarm.shift(0, direction * 5),
+            2 => alarm.days = alarm.days.step(direction),
+            // Picked on the Files tab, B2 goes back to what played last
+            3 if direction > 0 => {
+                self.picking_sound = Some(index);
+                self.open_tab = Tab::Files;
+            }
+            3 => alarm.sound = None,
+            4 => alarm.volume = (alarm.volume as i32 + direction * 5).clamp(5, 100) as u8,
+            _ if direction > 0 => {
+                self.alarms.remove(index);
+                self.alarm_menu = None;
+                self.alarm_cursor = self.alarm_cursor.min(self.alarms.list().len() as i32);
+            }
+            _ => {}
+        }
+        self.save_alarms();
+    }
+
+    fn save_alarms(&self) {
+        if let Err(e) = self.alarms.save(&self.alarms_path) {
+            error!("Failed to save alarms: {}", e);
+        }
+    }
+
+    /// Starts playback when an alarm is due and ramps the volume up
+    async fn update_alarms(&mut self) {
+        let now = Local::now().naive_local();
+        let due = self.alarms.due(self.now, now).cloned();
+        self.now = now;
+        if let Some(alarm) = due {
+            info!("Alarm for {:02}:{:02} went off", alarm.hour, alarm.minute);
+            // Audio goes to the speaker used last, it may have been turned
+            // off overnight
+            if let Err(e) = self.bt_channel.try_send(BluetoothRequest::ReconnectLast) {
+                error!("Failed to send ReconnectLast request: {}", e);
+            }
+            match alarm.sound {
+                Some(sound) if playlist::is_playlist(&sound) => {
+                    match playlist::track_paths(&sound) {
+                        Ok(tracks) => self.request_mpv(MpvRequest::PlayAll(tracks)),
+                        Err(e) => error!("Failed to read the alarm's playlist: {}", e),
+                    }
+                }
+                Some(sound) => self.request_mpv(MpvRequest::Play(MediaSource::File(sound))),
+                None => self.request_mpv(MpvRequest::ResumeLast),
+            }
+            let ramp = Duration::from_secs(self.alarm_ramp_secs);
+            self.alarm_ramp = Some(Ramp::new(Instant::now(), alarm.volume, ramp));
+            self.resume_offer = None;
+            self.open_tab = Tab::Player;
+            self.wake_display();
+        }
+
+        if let Some(ramp) = &self.alarm_ramp {
+            let now = Instant::now();
+            let volume = ramp.volume(now);
+            if ramp.done(now) {
+                self.alarm_ramp = None;
+            }
+            if volume != self.system_volume {
+                if let Err(e) = self.set_volume(volume).await {
+                    error!("Failed to ramp up the volume: {}", e);
+                }
+            }
+        }
+    }
+
+    fn load_stations(&mut self) {
+        match radio::load_stations(&self.stations_path) {
+            Ok(stations) => self.stations = stations,
+            Err(e) => error!("Failed to read the stations: {}", e),
+        }
+        self.move_station_cursor(0);
+    }
+
+    fn move_station_cursor(&mut self, direction: i32) {
+        let last = (self.stations.len() as i32 - 1).max(0);
+        self.station_cursor = (self.station_cursor + direction).clamp(0, last);
+        if self.station_cursor < self.station_scroll {
+            self.station_scroll = self.station_cursor;
+        } else if self.station_cursor >= self.station_scroll + self.max_files {
+            self.station_scroll = self.station_cursor - self.max_files + 1;
+        }
+    }
+
+    /// Writes the queue to an M3U file in the playlists directory
+    fn save_queue(&mut self) {
+        let name = format!("Queue {}.m3u", self.now.format("%Y-%m-%d %H.%M"));
+        let path = self.playlists_dir.join(&name);
+        let entries: Vec<playlist::Entry> = self
+            .queue
+            .iter()
+            .map(|track| playlist::Entry {
+                location: track.to_string_lossy().into_owned(),
+                title: Some(self.track_label(track)),
+            })
+            .collect();
+        match playlist::save_m3u(&path, &entries) {
+            Ok(()) => {
+                info!("Saved the queue to {:?}", path);
+                self.saved_queue = Some(name);
+                self.browser.reload();
+            }
+            Err(e) => error!("Failed to save the queue: {}", e),
+        }
+    }
+
+    /// The last two rows save and clear the queue, they are only there when
+    /// it isn't empty
+    fn move_queue_cursor(&mut self, direction: i32) {
+        let last = match self.queue.len() {
+            0 => 0,
+            len => len as i32 + 1,
+        };
+        self.queue_cursor = (self.queue_cursor + direction).clamp(0, last);
+        if self.queue_cursor < self.queue_scroll {
+            self.queue_scroll = self.queue_cursor;
+        } else if self.queue_cursor >= self.queue_scroll + self.max_files {
+            self.queue_scroll = self.queue_cursor - self.max_files + 1;
+        }
+    }
+
+    fn request_mpv(&self, request: MpvRequest) {
+        if let Err(e) = self.mpv_channel.try_send(request) {
+            error!("Failed to send MPV request: {}", e);
+        }
+    }
+
+    fn move_bt_cursor(&mut self, direction: i32) {
+        self.bt_cursor += direction;
+        if self.bt_cursor < 0 {
+            self.bt_cursor = 0;
+        }
+        if self.bt_cursor >= self.devices.len() as i32 {
+            self.bt_cursor = self.devices.len() as i32 - 1;
+        }
+        if self.bt_cursor + self.bt_scroll >= self.max_files {
+            self.bt_scroll += 1;
+            if self.bt_scroll >= self.devices.len() as i32 - self.max_files {
+                self.bt_scroll = self.devices.len() as i32 - self.max_files;
+            }
+        } else if self.bt_cursor <= self.bt_scroll {
+            self.bt_scroll -= 1;
+            if self.bt_scroll < 0 {
+                self.bt_scroll = 0;
+            }
+        }
+    }
+
+    fn wake_display(&mut self) {
+        if self.display_asleep {
+            self.display_asleep = false;
+            if let Err(e) = self.display.set_power(true) {
+                error!("Failed to turn the display on: {}", e);
+            }
+        }
+    }
+
+    /// The device's name, or its address if it has none
+    fn device_name(&self, addr: MacAddr6) -> String {
+        self.devices
+            .iter()
+            .find(|device| device.addr == addr)
+            .map(|device| device.name.clone())
+            .unwrap_or_else(|| addr.to_string())
+    }
+
+    /// Closes the pairing prompt of `addr`, if there is one
+    fn close_pairing_prompt(&mut self, addr: MacAddr6) {
+        if matches!(&self.pairing_prompt, Some((prompt, _, _)) if *prompt == addr) {
+            self.pairing_prompt = None;
+        }
+    }
+
+    /// Updates the devices in place, so the cursor stays on the same device
+    pub fn handle_bluetooth_event(&mut self, event: BluetoothEvent) {
+        match event {
+            BluetoothEvent::DeviceFound(device) => self.set_device(device),
+            BluetoothEvent::DeviceUpdated {
+                addr,
+                name,
+                connected,
+                paired,
+                trusted,
+                rssi,
+            } => self.set_device(Device {
+                addr,
+                name,
+                paired,
+                trusted,
+                connected,
+                rssi,
+            }),
+            BluetoothEvent::DeviceLost(addr) => self.remove_device(addr),
+            BluetoothEvent::ConnectFailed { addr, reason } => {
+                warn!("Could not connect to {}: {}", addr, reason);
+                self.close_pairing_prompt(addr);
+                self.bt_notice = Some((
+                    format!("Failed: {}", self.device_name(addr)),
+                    Instant::now() + BT_NOTICE_DURATION,
+                ));
+            }
+            BluetoothEvent::PairingPrompt {
+                addr,
+                pairing,
+                reply,
+            } => {
+                self.pairing_prompt = Some((addr, pairing, reply));
+                self.wake_display();
+            }
+            BluetoothEvent::PairingCancelled(addr) => self.close_pairing_prompt(addr),
+            BluetoothEvent::Reconnecting { attempt } => self.bt_reconnecting = attempt,
+        }
+    }
+
+    /// Replaces the device with the same address or adds it at the end,
+    /// devices without a name are left out until they have one
+    fn set_device(&mut self, device: Device) {
+        // A passkey shown for typing on the device is done with then
+        if device.paired {
+            self.close_pairing_prompt(device.addr);
+        }
+        let known = self.devices.iter().position(|d| d.addr == device.addr);
+        match known {
+            Some(i) if !device.name.is_empty() => self.devices[i] = device,
+            Some(_) => self.remove_device(device.addr),
+            None if !device.name.is_empty() => self.devices.push(device),
+            None => {}
+        }
+    }
+
+    fn remove_device(&mut self, addr: MacAddr6) {
+        let Some(i) = self.devices.iter().position(|d| d.addr == addr) else {
+            return;
+        };
+        self.devices.remove(i);
+        if (i as i32) < self.bt_cursor {
+            self.bt_cursor -= 1;
+        }
+        self.bt_cursor = self
+            .bt_cursor
+            .clamp(0, (self.devices.len() as i32 - 1).max(0));
+        self.bt_scroll = self
+            .bt_scroll
+            .min((self.devices.len() as i32 - self.max_files).max(0));
+    }
+
+    pub fn handle_audio_event(&mut self, event: AudioEvent) {
+        match event {
+            AudioEvent::Output(output) => self.output = Some(output),
+        }
+    }
+
+    pub fn handle_library_event(&mut self, event: LibraryEvent) {
+        match event {
+            LibraryEvent::FilesChanged => self.browser.reload(),
+            LibraryEvent::Indexed(library) => {
+                info!("Library ready with {} tracks", library.len());
+                self.library = library;
+                self.views.reload(&self.library);
+            }
+        }
+    }
+
+    pub fn handle_mpv_event(&mut self, event: MpvEvent) {
+        debug!("Handling MPV event: {:?}", event);
+        match event {
+            MpvEvent::Error(err) => {
+                error!("MPV Error: {}", err);
+            }
+            MpvEvent::StatusUpdate {
+                is_playing,
+                position,
+                duration,
+                filename,
+                path,
+                chapters,
+                chapter,
+                speed,
+                stream_title,
+            } => {
+                if self.player_status.current_file != filename {
+                    self.filename_scroll_offset = 0;
+                    self.filename_scroll_counter = 0;
+                }
+                if chapter != self.player_status.chapter {
+                    if let Some(timer) = &mut self.sleep_timer {
+                        timer.chapter_changed(&Playback {
+                            position: self.track_position,
+                            duration: self.track_duration,
+                            chapters: &self.player_status.chapters,
+                            chapter: self.player_status.chapter,
+                            speed: self.player_status.speed,
+                        });
+                    }
+                }
+                self.player_status.is_playing = is_playing;
+                self.player_status.current_file = filename;
+                // Playing something else replaces what could be resumed
+                if path.is_some() {
+                    self.resume_offer = None;
+                }
+                self.player_status.current_path = path;
+                self.player_status.chapters = chapters;
+                self.player_status.chapter = chapter;
+                self.player_status.speed = speed;
+                self.player_status.stream_title = stream_title;
+                self.track_position = position;
+                self.track_duration = duration;
+            }
+            MpvEvent::FileLoaded => {
+                self.filename_scroll_offset = 0;
+                self.filename_scroll_counter = 0;
+            }
+            MpvEvent::TrackEnded { reason } => {
+                debug!("Track ended: {}", reason);
+                self.track_position = 0;
+                if reason == "eof" {
+                    if let Some(timer) = &mut self.sleep_timer {
+                        timer.track_ended();
+                    }
+                }
+            }
+            MpvEvent::ModesUpdate { shuffle, repeat } => {
+                self.shuffle = shuffle;
+                self.repeat = repeat;
+            }
+            MpvEvent::QueueUpdate { files, current } => {
+                if files != self.queue {
+                    self.saved_queue = None;
+                }
+                self.queue = files;
+                self.queue_current = current;
+                self.queue_scroll = self.queue_scroll.min(self.queue.len() as i32);
+                self.move_queue_cursor(0);
+            }
+            MpvEvent::ResumeAvailable { file, position } => {
+                self.resume_offer = Some((file, position));
+                self.open_tab = Tab::Player;
+            }
+            MpvEvent::Reconnecting { attempt } => {
+                self.reconnecting = attempt;
+            }
+            MpvEvent::BookmarksUpdate { positions } => {
+                self.bookmarks = positions;
+                if let Some(selected) = self.player_menu {
+                    let last = PLAYER_MENU_ROWS + self.bookmarks.len() as i32 - 1;
+                    self.player_menu = Some(selected.min(last));
+                }
+            }
+        }
+    }
+
+    /// Starts the sleep timer over with `mode`, `None` turns it off
+    pub fn set_sleep_timer(&mut self, mode: Option<SleepMode>) {
+        // Changing it while fading out brings the volume back
+        if let Some(volume) = self.sleep_timer.take().and_then(|timer| timer.fade_from()) {
+            self.volume_restore = Some((Instant::now(), volume));
+        }
+        let fade = Duration::from_secs(self.sleep_config.fade_secs);
+        self.sleep_timer = mode.map(|mode| SleepTimer::new(mode, Instant::now(), fade));
+    }
+
+    fn sleep_remaining(&self) -> Option<Duration> {
+        let timer = self.sleep_timer.as_ref()?;
+        timer.remaining(
+            Instant::now(),
+            &Playback {
+                position: self.track_position,
+                duration: self.track_duration,
+                chapters: &self.player_status.chapters,
+                chapter: self.player_status.chapter,
+                speed: self.player_status.speed,
+            },
+        )
+    }
+
+    /// Fades out and pauses playback once the sleep timer runs out
+    async fn update_sleep_timer(&mut self) {
+        let now = Instant::now();
+        if let Some((at, volume)) = self.volume_restore {
+            if now >= at {
+                self.volume_restore = None;
+                if let Err(e) = self.set_volume(volume).await {
+                    error!("Failed to restore volume: {}", e);
+                }
+            }
+        }
+
+        let Some(timer) = &mut self.sleep_timer else {
+            return;
+        };
+        let playback = Playback {
+            position: self.track_position,
+            duration: self.track_duration,
+            chapters: &self.player_status.chapters,
+            chapter: self.player_status.chapter,
+            speed: self.player_status.speed,
+        };
+        match timer.tick(now, self.system_volume, &playback) {
+            SleepAction::Wait => {}
+            SleepAction::Volume(volume) => {
+                if volume != self.system_volume {
+                    if let Err(e) = self.set_volume(volume).await {
+                        error!("Failed to fade out: {}", e);
+                    }
+                }
+            }
+            SleepAction::Sleep => {
+                info!("Sleep timer ran out, pausing playback");
+                let fade_from = timer.fade_from();
+                self.sleep_timer = None;
+                self.request_mpv(MpvRequest::Pause);
+                if let Some(volume) = fade_from {
+                    self.volume_restore = Some((now + VOLUME_RESTORE_DELAY, volume));
+                }
+                if self.sleep_config.display_off {
+                    match self.display.set_power(false) {
+                        Ok(()) => self.display_asleep = true,
+                        Err(e) => error!("Failed to turn the display off: {}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn get_system_volume(&mut self) -> Result<u8> {
+        let output = Command::new("pactl")
+            .arg("get-sink-volume")
+            .arg("@DEFAULT_SINK@")
+            .output()
+            .await?;
+
+        let output_str = String::from_utf8_lossy(&output.stdout);
+        if let Some(volume_line) = output_str.lines().next() {
+            if let Some(percent_pos) = volume_line.find('%') {
+                let start = volume_line[..percent_pos].rfind(' ').unwrap_or(0) + 1;
+                if let Ok(volume) = volume_line[start..percent_pos].parse::<u8>() {
+                    return Ok(volume);
+                }
+            }
+        }
+        Ok(50)
+    }
+
+    /// What the Player tab shows for the playing file
+    fn player_title(&self) -> Option<String> {
+        let tagged = self
+            .player_status
+            .current_path
+            .as_deref()
+            .and_then(|path| self.library.get(path))
+            .and_then(|info| info.display_name());
+        let station = || {
+            let path = self.player_status.current_path.as_deref()?;
+            self.station_name(path)
+        };
+        tagged
+            .or_else(station)
+            .or_else(|| self.player_status.current_file.clone())
+    }
+
+    fn station_name(&self, url: &Path) -> Option<String> {
+        self.stations
+            .iter()
+            .find(|station| Path::new(&station.url) == url)
+            .map(|station| station.name.clone())
+    }
+
+    /// "Artist - Title" from the library, the file name if it has no tags.
+    /// Streams go by their station's name.
+    fn track_label(&self, path: &Path) -> String {
+        self.library
+            .get(path)
+            .and_then(|info| info.display_name())
+            .or_else(|| self.station_name(path))
+            .unwrap_or_else(|| {
+                path.file_name()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+                    .into_owned()
+            })
+    }
+
+    fn get_scrolling_text(&self, text: &str) -> String {
+        if text.chars().count() <= self.max_len {
+            return text.to_string();
+        }
+
+        let extended_text: Vec<char> = format!("{} --- ", text).chars().collect();
+        let offset = self.filename_scroll_offset % extended_text.len();
+        extended_text
+            .iter()
+            .cycle()
+            .skip(offset)
+            .take(self.max_len)
+            .collect()
+    }
+
+    fn update_filename_scroll(&mut self) {
+        if let Some(title) = self.player_title() {
+            let title_len = title.chars().count();
+            if title_len > self.max_len {
+                self.filename_scroll_counter += 1;
+                if self.filename_scroll_counter >= 15 {
+                    self.filename_scroll_counter = 0;
+                    self.filename_scroll_offset += 1;
+                    let extended_len = title_len + 5;
+                    if self.filename_scroll_offset >= extended_len {
+                        self.filename_scroll_offset = 0;
+                    }
+                }
+            } else {
+                self.filename_scroll_offset = 0;
+                self.filename_scroll_counter = 0;
+            }
+        }
+    }
+
+    async fn volume_up(&mut self) -> Result<()> {
+        Command::new("pactl")
+            .arg("set-sink-volume")
+            .arg("@DEFAULT_SINK@")
+            .arg("+5%")
+            .spawn()?
+            .wait()
+            .await?;
+        Ok(())
+    }
+
+    async fn volume_down(&mut self) -> Result<()> {
+        Command::new("pactl")
+            .arg("set-sink-volume")
+            .arg("@DEFAULT_SINK@")
+            .arg("-5%")
+            .spawn()?
+            .wait()
+            .await?;
+        Ok(())
+    }
+
+    async fn set_volume(&mut self, percent: u8) -> Result<()> {
+        Command::new("pactl")
+            .arg("set-sink-volume")
+            .arg("@DEFAULT_SINK@")
+            .arg(format!("{}%", percent))
+            .spawn()?
+            .wait()
+            .await?;
+        self.system_volume = percent;
+        Ok(())
+    }
+
+    async fn get_wifi_status(&self) -> Result<bool> {
+        let output = Command::new("rfkill")
+            .arg("list")
+            .arg("wifi")
+            .output()
+            .await?;
+
+        let output_str = String::from_utf8_lossy(&output.stdout);
+        for line in output_str.lines() {
+            if line.contains("Soft blocked:") {
+                return Ok(!line.contains("yes"));
+            }
+        }
+        Ok(true)
+    }
+
+    async fn toggle_wifi(&mut self) -> Result<()> {
+        let command = if self.wifi_enabled {
+            "block"
+        } else {
+            "unblock"
+        };
+        Command::new("rfkill")
+            .arg(command)
+            .arg("wifi")
+            .spawn()?
+            .wait()
+            .await?;
+
+        self.wifi_enabled = !self.wifi_enabled;
+        Ok(())
+    }
+}
+
+/// Keeps the end of a breadcrumb that does not fit into `room` characters
+fn clip_breadcrumb(path: &str, room: usize) -> String {
+    let chars: Vec<char> = path.chars().collect();
+    if chars.len() <= room {
+        return path.to_string();
+    }
+    let tail: String = chars[chars.len() - (room - 2)..].iter().collect();
+    format!("..{}", tail)
+}
+
+/// Minutes and seconds, like 83:05
+fn format_time(seconds: u32) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
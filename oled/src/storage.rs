@@ -0,0 +1,70 @@
+//! Small files that have to survive restarts and power cuts, like the
+//! playback modes in the state directory or saved playlists.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Reads `path`, a missing file gives the default
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// Writes `value` like [`save_text`]
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    save_text(path, &serde_json::to_string_pretty(value)?)
+}
+
+/// Writes `contents` to a file next to `path` and renames it over `path`, so
+/// losing power while writing leaves either the old or the new file
+pub fn save_text(path: &Path, contents: &str) -> Result<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir)?;
+    }
+    let temp_path = temp_path(path);
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    // The rename itself is only safe once the directory is written
+    File::open(dir.unwrap_or(Path::new(".")))?.sync_all()?;
+    Ok(())
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("numbers.json");
+        assert_eq!(load_json::<Vec<u32>>(&path).unwrap(), Vec::<u32>::new());
+
+        save_json(&path, &vec![1, 2, 3]).unwrap();
+        assert_eq!(load_json::<Vec<u32>>(&path).unwrap(), [1, 2, 3]);
+        assert!(!temp_path(&path).exists());
+
+        // A write cut short only ever hits the temporary file
+        fs::write(temp_path(&path), "[4, 5").unwrap();
+        assert_eq!(load_json::<Vec<u32>>(&path).unwrap(), [1, 2, 3]);
+        save_json(&path, &vec![4, 5]).unwrap();
+        assert_eq!(load_json::<Vec<u32>>(&path).unwrap(), [4, 5]);
+    }
+}
@@ -0,0 +1,426 @@
+//! Library views of the Files tab. Besides browsing the folders the tracks
+//! can be found by artist and album, by genre or among the files that were
+//! added last. Every view is a tree of [`Node`]s with tracks as leaves. The
+//! internet radio stations are listed as a view too.
+
+use std::path::{Path, PathBuf};
+
+use crate::library::{Library, TrackInfo};
+
+const UNKNOWN_ARTIST: &str = "Unknown artist";
+const UNKNOWN_ALBUM: &str = "Unknown album";
+const UNKNOWN_GENRE: &str = "Unknown genre";
+
+/// How many tracks the Recently added view lists
+const RECENT_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    /// The directory browser
+    Folders,
+    Artists,
+    Genres,
+    RecentlyAdded,
+    /// The internet radio stations
+    Radio,
+}
+
+const VIEWS: [View; 5] = [
+    View::Folders,
+    View::Artists,
+    View::Genres,
+    View::RecentlyAdded,
+    View::Radio,
+];
+
+impl View {
+    pub fn name(self) -> &'static str {
+        match self {
+            View::Folders => "Folders",
+            View::Artists => "Artists",
+            View::Genres => "Genres",
+            View::RecentlyAdded => "Recently added",
+            View::Radio => "Radio",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// The list of views
+    Views,
+    View(View),
+    Artist(String),
+    Album {
+        artist: String,
+        album: String,
+    },
+    Genre(String),
+}
+
+impl Node {
+    pub fn name(&self) -> &str {
+        match self {
+            Node::Views => "Library",
+            Node::View(view) => view.name(),
+            Node::Artist(artist) => artist,
+            Node::Album { album, .. } => album,
+            Node::Genre(genre) => genre,
+        }
+    }
+
+    /// Every track below this node in the order they are played, artist by
+    /// artist and album by album
+    pub fn tracks(&self, library: &Library) -> Vec<PathBuf> {
+        if *self == Node::View(View::RecentlyAdded) {
+            return library.recently_added(RECENT_LIMIT);
+        }
+        let mut tracks: Vec<(&Path, &TrackInfo)> = library
+            .tracks()
+            .filter(|(_, info)| self.contains(info))
+            .collect();
+        tracks.sort_by_cached_key(|(path, info)| {
+            (
+                artist(info).to_lowercase(),
+                album(info).to_lowercase(),
+                info.track_number,
+                path.to_path_buf(),
+            )
+        });
+        tracks
+            .into_iter()
+            .map(|(path, _)| path.to_path_buf())
+            .collect()
+    }
+
+    fn contains(&self, info: &TrackInfo) -> bool {
+        match self {
+            Node::Views | Node::View(View::Folders | View::Radio) => false,
+            Node::View(_) => true,
+            Node::Artist(name) => artist(info) == name,
+            Node::Album {
+                artist: artist_name,
+                album: album_name,
+            } => artist(info) == artist_name && album(info) == album_name,
+            Node::Genre(name) => genre(info) == name,
+        }
+    }
+
+    fn children(&self, library: &Library) -> Vec<ViewEntry> {
+        match self {
+            Node::Views => VIEWS
+                .into_iter()
+                .map(|view| ViewEntry::Node(Node::View(view)))
+                .collect(),
+            // Listed by the directory browser and from the stations file
+            Node::View(View::Folders | View::Radio) => Vec::new(),
+            Node::View(View::Artists) => groups(library, self, artist)
+                .into_iter()
+                .map(|name| ViewEntry::Node(Node::Artist(name)))
+                .collect(),
+            Node::Artist(artist_name) => groups(library, self, album)
+                .into_iter()
+                .map(|album_name| {
+                    ViewEntry::Node(Node::Album {
+                        artist: artist_name.clone(),
+                        album: album_name,
+                    })
+                })
+                .collect(),
+            Node::View(View::Genres) => groups(library, self, genre)
+                .into_iter()
+                .map(|name| ViewEntry::Node(Node::Genre(name)))
+                .collect(),
+            Node::View(View::RecentlyAdded) | Node::Album { .. } | Node::Genre(_) => self
+                .tracks(library)
+                .into_iter()
+                .map(ViewEntry::Track)
+                .collect(),
+        }
+    }
+}
+
+fn artist(info: &TrackInfo) -> &str {
+    tag_or(&info.artist, UNKNOWN_ARTIST)
+}
+
+fn album(info: &TrackInfo) -> &str {
+    tag_or(&info.album, UNKNOWN_ALBUM)
+}
+
+fn genre(info: &TrackInfo) -> &str {
+    tag_or(&info.genre, UNKNOWN_GENRE)
+}
+
+fn tag_or<'a>(tag: &'a Option<String>, fallback: &'a str) -> &'a str {
+    tag.as_deref().filter(|s| !s.is_empty()).unwrap_or(fallback)
+}
+
+/// The distinct values of `key` among the tracks below `node`, sorted by name
+fn groups(library: &Library, node: &Node, key: fn(&TrackInfo) -> &str) -> Vec<String> {
+    let mut names: Vec<&str> = library
+        .tracks()
+        .filter(|(_, info)| node.contains(info))
+        .map(|(_, info)| key(info))
+        .collect();
+    names.sort_by_cached_key(|name| (name.to_lowercase(), name.to_string()));
+    names.dedup();
+    names.into_iter().map(str::to_string).collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViewEntry {
+    Node(Node),
+    Track(PathBuf),
+}
+
+#[derive(Debug)]
+pub struct Views {
+    /// Nodes above the current one and the cursor to restore when going
+    /// back, outermost first
+    stack: Vec<(Node, usize)>,
+    node: Node,
+    entries: Vec<ViewEntry>,
+    cursor: usize,
+    scroll: usize,
+    /// How many entries fit on the screen
+    rows: usize,
+}
+
+impl Views {
+    /// Starts out in the list of views
+    pub fn new(rows: usize) -> Self {
+        Self {
+            stack: Vec::new(),
+            node: Node::Views,
+            entries: Node::Views.children(&Library::default()),
+            cursor: 0,
+            scroll: 0,
+            rows: rows.max(1),
+        }
+    }
+
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    pub fn entries(&self) -> &[ViewEntry] {
+        &self.entries
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    pub fn selected(&self) -> Option<&ViewEntry> {
+        self.entries.get(self.cursor)
+    }
+
+    /// The nodes entered from the list of views, `None` in that list
+    pub fn breadcrumb(&self) -> Option<String> {
+        if self.node == Node::Views {
+            return None;
+        }
+        let names: Vec<&str> = self
+            .stack
+            .iter()
+            .skip(1)
+            .map(|(node, _)| node.name())
+            .chain([self.node.name()])
+            .collect();
+        Some(names.join("/"))
+    }
+
+    /// Moves the cursor and scrolls to keep it on screen
+    pub fn move_cursor(&mut self, delta: i32) {
+        let last = self.entries.len().saturating_sub(1) as i32;
+        self.cursor = (self.cursor as i32 + delta).clamp(0, last) as usize;
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + self.rows {
+            self.scroll = self.cursor + 1 - self.rows;
+        }
+    }
+
+    /// Goes straight to one of the views
+    pub fn open(&mut self, view: View, library: &Library) {
+        let index = VIEWS.iter().position(|&v| v == view).unwrap_or(0);
+        self.stack = vec![(Node::Views, index)];
+        self.node = Node::View(view);
+        self.cursor = 0;
+        self.scroll = 0;
+        self.list(library);
+    }
+
+    /// Opens the selected node. Returns false if the selection is a track.
+    pub fn enter(&mut self, library: &Library) -> bool {
+        let Some(ViewEntry::Node(node)) = self.selected().cloned() else {
+            return false;
+        };
+        let parent = std::mem::replace(&mut self.node, node);
+        self.stack.push((parent, self.cursor));
+        self.cursor = 0;
+        self.scroll = 0;
+        self.list(library);
+        true
+    }
+
+    /// Goes back to the node we came from. Returns false in the list of
+    /// views.
+    pub fn leave(&mut self, library: &Library) -> bool {
+        let Some((parent, cursor)) = self.stack.pop() else {
+            return false;
+        };
+        self.node = parent;
+        self.cursor = cursor;
+        self.scroll = 0;
+        self.list(library);
+        self.move_cursor(0);
+        true
+    }
+
+    /// Lists the current node again, for when the library changed. The
+    /// selected entry stays selected if it's still there.
+    pub fn reload(&mut self, library: &Library) {
+        let selected = self.selected().cloned();
+        self.list(library);
+        if let Some(index) = selected.and_then(|s| self.entries.iter().position(|e| *e == s)) {
+            self.cursor = index;
+            self.move_cursor(0);
+        }
+    }
+
+    /// Lists the current node, keeping the cursor in range
+    fn list(&mut self, library: &Library) {
+        self.entries = self.node.children(library);
+        self.cursor = self.cursor.min(self.entries.len().saturating_sub(1));
+        self.scroll = self.scroll.min(self.cursor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> Library {
+        let track = |path: &str, artist: Option<&str>, album: &str, number, genre: &str| {
+            let info = TrackInfo {
+                title: Some(path.to_string()),
+                artist: artist.map(str::to_string),
+                album: Some(album.to_string()),
+                track_number: Some(number),
+                genre: Some(genre.to_string()),
+                duration: None,
+            };
+            (PathBuf::from(path), info)
+        };
+        Library::from_tracks([
+            track("b2.mp3", Some("beta"), "Second", 2, "Rock"),
+            track("b1.mp3", Some("beta"), "Second", 1, "Rock"),
+            track("a1.mp3", Some("Alpha"), "First", 1, "Jazz"),
+            track("b0.mp3", Some("beta"), "Another", 1, "Rock"),
+            track("x.mp3", None, "", 1, ""),
+        ])
+    }
+
+    fn names(views: &Views) -> Vec<String> {
+        views
+            .entries()
+            .iter()
+            .map(|entry| match entry {
+                ViewEntry::Node(node) => node.name().to_string(),
+                ViewEntry::Track(path) => path.to_string_lossy().into_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_artists_and_albums() {
+        let library = library();
+        let mut views = Views::new(4);
+        assert_eq!(
+            names(&views),
+            ["Folders", "Artists", "Genres", "Recently added", "Radio"]
+        );
+        assert!(!views.leave(&library));
+
+        views.move_cursor(1);
+        assert!(views.enter(&library));
+        assert_eq!(names(&views), ["Alpha", "beta", "Unknown artist"]);
+        views.move_cursor(1);
+        assert!(views.enter(&library));
+        assert_eq!(names(&views), ["Another", "Second"]);
+        views.move_cursor(1);
+        assert!(views.enter(&library));
+        assert_eq!(views.breadcrumb().as_deref(), Some("Artists/beta/Second"));
+        assert_eq!(names(&views), ["b1.mp3", "b2.mp3"]);
+        assert!(!views.enter(&library));
+
+        assert!(views.leave(&library));
+        let album = Node::Album {
+            artist: "beta".to_string(),
+            album: "Second".to_string(),
+        };
+        assert_eq!(views.selected(), Some(&ViewEntry::Node(album)));
+        assert!(views.leave(&library));
+        assert_eq!(views.cursor(), 1);
+    }
+
+    #[test]
+    fn test_play_all_order() {
+        let library = library();
+        let tracks = |node: Node| -> Vec<String> {
+            node.tracks(&library)
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(
+            tracks(Node::View(View::Artists)),
+            ["a1.mp3", "b0.mp3", "b1.mp3", "b2.mp3", "x.mp3"]
+        );
+        assert_eq!(
+            tracks(Node::Genre("Rock".to_string())),
+            ["b0.mp3", "b1.mp3", "b2.mp3"]
+        );
+        assert_eq!(tracks(Node::Genre(UNKNOWN_GENRE.to_string())), ["x.mp3"]);
+        assert!(tracks(Node::View(View::Folders)).is_empty());
+    }
+
+    #[test]
+    fn test_open_and_reload() {
+        let mut views = Views::new(4);
+        views.open(View::Genres, &Library::default());
+        assert!(views.entries().is_empty());
+        assert_eq!(views.breadcrumb().as_deref(), Some("Genres"));
+
+        let library = library();
+        views.reload(&library);
+        assert_eq!(names(&views), ["Jazz", "Rock", "Unknown genre"]);
+
+        // A new genre before the selected one keeps the selection
+        views.move_cursor(1);
+        let mut tracks: Vec<_> = library
+            .tracks()
+            .map(|(path, info)| (path.to_path_buf(), info.clone()))
+            .collect();
+        let blues = TrackInfo {
+            genre: Some("Blues".to_string()),
+            ..Default::default()
+        };
+        tracks.push((PathBuf::from("blues.mp3"), blues));
+        let library = Library::from_tracks(tracks);
+        views.reload(&library);
+        assert_eq!(names(&views), ["Blues", "Jazz", "Rock", "Unknown genre"]);
+        assert_eq!(views.cursor(), 2);
+        assert!(views.leave(&library));
+        assert_eq!(
+            views.selected(),
+            Some(&ViewEntry::Node(Node::View(View::Genres)))
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Watches the audio roots with inotify so music copied onto the card shows
+//! up without a restart.
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Result;
+use notify::{
+    event::{EventKind, ModifyKind},
+    RecommendedWatcher, RecursiveMode, Watcher,
+};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// Watches `roots` recursively and sends on `changes` whenever something
+/// below them is created, written, renamed or removed. Dropping the returned
+/// watcher stops watching.
+pub fn watch(roots: &[PathBuf], changes: mpsc::UnboundedSender<()>) -> Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            // Reading tags opens every file, which must not count as a change
+            Ok(event) if is_change(&event.kind) => {
+                debug!("{:?} {:?}", event.kind, event.paths);
+                let _ = changes.send(());
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Watching the audio roots failed: {}", e),
+        }
+    })?;
+    for root in roots {
+        if let Err(e) = watcher.watch(root, RecursiveMode::Recursive) {
+            warn!("Could not watch {:?}: {}", root, e);
+        }
+    }
+    Ok(watcher)
+}
+
+fn is_change(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+        EventKind::Any | EventKind::Other => true,
+    }
+}
+
+/// Waits for a change and then until there were none for `quiet`. Returns
+/// false once the watcher is gone.
+pub async fn settled(changes: &mut mpsc::UnboundedReceiver<()>, quiet: Duration) -> bool {
+    if changes.recv().await.is_none() {
+        return false;
+    }
+    loop {
+        match tokio::time::timeout(quiet, changes.recv()).await {
+            Ok(Some(())) => continue,
+            Ok(None) => return false,
+            Err(_) => return true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_settled_waits_for_quiet() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let quiet = Duration::from_secs(2);
+        let sender = tokio::spawn(async move {
+            for _ in 0..5 {
+                tx.send(()).unwrap();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            tx
+        });
+
+        let start = tokio::time::Instant::now();
+        assert!(settled(&mut rx, quiet).await);
+        // The last change came after 4 seconds
+        assert_eq!(start.elapsed(), Duration::from_secs(6));
+
+        drop(sender.await.unwrap());
+        assert!(!settled(&mut rx, quiet).await);
+    }
+
+    #[tokio::test]
+    async fn test_watch_reports_new_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let _watcher = watch(&[dir.path().to_path_buf()], tx).unwrap();
+
+        std::fs::create_dir(dir.path().join("Album")).unwrap();
+        std::fs::write(dir.path().join("Album").join("01.mp3"), b"").unwrap();
+        let change = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+        assert_eq!(change.unwrap(), Some(()));
+    }
+}
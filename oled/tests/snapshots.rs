@@ -0,0 +1,737 @@
+//! Renders every tab into an in-memory panel and compares the result with the
+//! golden images in `tests/golden`. After an intentional layout change the
+//! images can be regenerated with
+//!
+//! ```sh
+//! UPDATE_GOLDEN=1 cargo test -p oled --test snapshots
+//! ```
+
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use embedded_graphics::pixelcolor::BinaryColor;
+use macaddr::MacAddr6;
+use oled::{
+    alarm::{Alarm, Days},
+    audio::AudioEvent,
+    bluetooth::{BluetoothEvent, BluetoothRequest, Device, Pairing, PairingReply},
+    buttons::{Button, ButtonInput, Buttons},
+    config::{AudioConfig, Config, DisplayConfig, StorageConfig},
+    display::{Display, Framebuffer, MemoryPanel},
+    joystick::{self, Joystick, JoystickInput},
+    library::{Library, LibraryEvent, TrackInfo},
+    mpv::{modes::Repeat, Chapter, MediaSource, MpvEvent, MpvRequest},
+    radio::Station,
+    sleep::SleepMode,
+    state::{State, Tab},
+    views::View,
+};
+use tempfile::TempDir;
+use tokio::sync::mpsc;
+
+/// The buttons held down and the way the joystick is pushed, shared by the
+/// fixture and the inputs it hands to the state
+#[derive(Debug, Clone)]
+struct ScriptedInput(Arc<Mutex<(Vec<Button>, joystick::State)>>);
+
+impl ScriptedInput {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new((Vec::new(), joystick::State::Neutral))))
+    }
+
+    fn set(&self, buttons: &[Button], joystick: joystick::State) {
+        *self.0.lock().unwrap() = (buttons.to_vec(), joystick);
+    }
+}
+
+impl JoystickInput for ScriptedInput {
+    fn read(&mut self) -> Result<joystick::State> {
+        Ok(self.0.lock().unwrap().1)
+    }
+}
+
+impl ButtonInput for ScriptedInput {
+    fn is_down(&mut self, button: Button) -> Result<bool> {
+        Ok(self.0.lock().unwrap().0.contains(&button))
+    }
+}
+
+struct Fixture {
+    state: State,
+    panel: MemoryPanel,
+    input: ScriptedInput,
+    mpv_rx: mpsc::Receiver<MpvRequest>,
+    _bt_rx: mpsc::Receiver<BluetoothRequest>,
+    _audio_dir: TempDir,
+    _state_dir: TempDir,
+}
+
+impl Fixture {
+    fn new(files: &[&str]) -> Self {
+        Self::sized(files, 128, 64).unwrap()
+    }
+
+    /// A display of this size, fails if the config doesn't accept it
+    fn sized(files: &[&str], width: i32, height: i32) -> Result<Self> {
+        let audio_dir = tempfile::tempdir().unwrap();
+        for file in files {
+            let path = audio_dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            audio: AudioConfig {
+                roots: vec![audio_dir.path().to_path_buf()],
+                ..AudioConfig::default()
+            },
+            storage: StorageConfig {
+                state_dir: state_dir.path().to_path_buf(),
+            },
+            display: DisplayConfig {
+                width,
+                height,
+                ..DisplayConfig::default()
+            },
+            ..Config::default()
+        };
+        config.validate()?;
+
+        let panel = MemoryPanel::new(width, height);
+        let display = Display::new(width, height, Box::new(panel.clone())).unwrap();
+        let (bt_tx, bt_rx) = mpsc::channel(10);
+        let (mpv_tx, mpv_rx) = mpsc::channel(10);
+        let input = ScriptedInput::new();
+        let mut state = State::new(
+            display,
+            Joystick::new(Box::new(input.clone())),
+            Buttons::new(Box::new(input.clone())),
+            &config,
+            bt_tx,
+            mpv_tx,
+        )
+        .unwrap();
+        state.ip = Some(IpAddr::V4(Ipv4Addr::new(192, 168, 2, 60)));
+
+        Ok(Self {
+            state,
+            panel,
+            input,
+            mpv_rx,
+            _bt_rx: bt_rx,
+            _audio_dir: audio_dir,
+            _state_dir: state_dir,
+        })
+    }
+
+    fn render(&mut self, tab: Tab) -> Framebuffer {
+        self.state.open_tab = tab;
+        self.state.display.fill(BinaryColor::Off);
+        self.state.draw();
+        self.state.display.render().unwrap();
+        self.panel.snapshot()
+    }
+
+    /// Runs an update with these buttons down and the joystick pushed this
+    /// way, then one with everything let go so the next press counts again
+    async fn press(&mut self, buttons: &[Button], joystick: joystick::State) {
+        self.input.set(buttons, joystick);
+        self.state.update().await.unwrap();
+        self.input.set(&[], joystick::State::Neutral);
+        self.state.update().await.unwrap();
+    }
+
+    /// The requests sent to mpv since the last call
+    fn mpv_requests(&mut self) -> Vec<MpvRequest> {
+        std::iter::from_fn(|| self.mpv_rx.try_recv().ok()).collect()
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.pbm", name))
+}
+
+fn ascii_art(frame: &Framebuffer) -> String {
+    (0..frame.height)
+        .map(|y| {
+            (0..frame.width)
+                .map(|x| if frame.pixel(x, y) { '#' } else { '.' })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn assert_snapshot(name: &str, frame: &Framebuffer) {
+    let path = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, frame.to_pbm()).unwrap();
+        return;
+    }
+
+    let golden = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "Missing golden image {:?}, run with UPDATE_GOLDEN=1 to create it",
+            path
+        )
+    });
+    let golden = Framebuffer::from_pbm(&golden).unwrap();
+    if golden.pages != frame.pages {
+        let actual = path.with_extension("actual.pbm");
+        fs::write(&actual, frame.to_pbm()).unwrap();
+        panic!(
+            "{} does not match its golden image, the actual frame was written to {:?}\n\nexpected:\n{}\n\nactual:\n{}",
+            name,
+            actual,
+            ascii_art(&golden),
+            ascii_art(frame)
+        );
+    }
+}
+
+fn device(addr: [u8; 6], name: &str, connected: bool, trusted: bool, paired: bool) -> Device {
+    Device {
+        addr: MacAddr6::from(addr),
+        name: name.to_string(),
+        paired,
+        trusted,
+        connected,
+        rssi: None,
+    }
+}
+
+#[test]
+fn files_tab() {
+    let mut fixture = Fixture::new(&[
+        "01 Intro.mp3",
+        "02 A very long track name that gets clipped.flac",
+    ]);
+    let frame = fixture.render(Tab::Files);
+    assert_snapshot("files_tab", &frame);
+}
+
+#[test]
+fn files_tab_subdir() {
+    let mut fixture = Fixture::new(&[
+        "Loose track.mp3",
+        "Artists/Some Band/Their First Album/01 Opening.mp3",
+        "Artists/Some Band/Their First Album/02 Closing.mp3",
+    ]);
+    for _ in 0..3 {
+        assert!(fixture.state.browser.enter());
+    }
+    let frame = fixture.render(Tab::Files);
+    assert_snapshot("files_tab_subdir", &frame);
+}
+
+#[test]
+fn files_tab_playlist() {
+    let mut fixture = Fixture::new(&["Album/01 Opening.mp3", "Album/02 Closing.mp3"]);
+    fs::write(
+        fixture._audio_dir.path().join("Road trip.m3u"),
+        "#EXTM3U\n#EXTINF:-1,Some Band - Closing\nAlbum/02 Closing.mp3\nAlbum/01 Opening.mp3\n",
+    )
+    .unwrap();
+    fixture.state.browser.reload();
+    fixture.state.browser.move_cursor(1);
+    assert!(fixture.state.browser.enter());
+    let frame = fixture.render(Tab::Files);
+    assert_snapshot("files_tab_playlist", &frame);
+}
+
+#[test]
+fn files_tab_unreadable() {
+    let mut fixture = Fixture::new(&["Album/01.mp3"]);
+    // A directory that is gone is left for its parent, the root can't be
+    fs::remove_dir_all(fixture._audio_dir.path()).unwrap();
+    fixture.state.browser.reload();
+    let frame = fixture.render(Tab::Files);
+    assert_snapshot("files_tab_unreadable", &frame);
+}
+
+#[test]
+fn files_tab_empty() {
+    let mut fixture = Fixture::new(&[]);
+    let frame = fixture.render(Tab::Files);
+    assert_snapshot("files_tab_empty", &frame);
+}
+
+fn tagged_library() -> Library {
+    let track = |path: &str, artist: &str, title: &str| {
+        let info = TrackInfo {
+            title: Some(title.to_string()),
+            artist: Some(artist.to_string()),
+            ..Default::default()
+        };
+        (PathBuf::from(path), info)
+    };
+    Library::from_tracks([
+        track("/music/a.mp3", "Some Band", "Opening"),
+        track("/music/b.mp3", "Another Artist", "Closing"),
+    ])
+}
+
+#[test]
+fn files_tab_library() {
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.views.leave(&Library::default());
+    let frame = fixture.render(Tab::Files);
+    assert_snapshot("files_tab_library", &frame);
+}
+
+#[test]
+fn files_tab_artists() {
+    let mut fixture = Fixture::new(&[]);
+    fixture
+        .state
+        .handle_library_event(LibraryEvent::Indexed(tagged_library()));
+    fixture.state.views.open(View::Artists, &tagged_library());
+    let frame = fixture.render(Tab::Files);
+    assert_snapshot("files_tab_artists", &frame);
+}
+
+#[test]
+fn network_tab() {
+    let mut fixture = Fixture::new(&[]);
+    let frame = fixture.render(Tab::Network);
+    assert_snapshot("network_tab", &frame);
+}
+
+fn found_devices(fixture: &mut Fixture) {
+    for device in [
+        device([0, 1, 2, 3, 4, 5], "Speaker", true, true, true),
+        device([0, 1, 2, 3, 4, 6], "Headphones", false, true, true),
+        device([0, 1, 2, 3, 4, 7], "", false, false, false),
+        device([0, 1, 2, 3, 4, 8], "Phone", false, false, false),
+    ] {
+        fixture
+            .state
+            .handle_bluetooth_event(BluetoothEvent::DeviceFound(device));
+    }
+}
+
+#[test]
+fn bluetooth_tab() {
+    let mut fixture = Fixture::new(&[]);
+    found_devices(&mut fixture);
+    let frame = fixture.render(Tab::Bluetooth);
+    assert_snapshot("bluetooth_tab", &frame);
+}
+
+#[test]
+fn bluetooth_tab_connect_failed() {
+    let mut fixture = Fixture::new(&[]);
+    found_devices(&mut fixture);
+    let phone = MacAddr6::from([0, 1, 2, 3, 4, 8]);
+    fixture
+        .state
+        .handle_bluetooth_event(BluetoothEvent::DeviceLost(MacAddr6::from([
+            0, 1, 2, 3, 4, 6,
+        ])));
+    fixture
+        .state
+        .handle_bluetooth_event(BluetoothEvent::ConnectFailed {
+            addr: phone,
+            reason: "Page Timeout".to_string(),
+        });
+    let frame = fixture.render(Tab::Bluetooth);
+    assert_snapshot("bluetooth_tab_connect_failed", &frame);
+}
+
+#[test]
+fn bluetooth_reconnecting() {
+    let mut fixture = Fixture::new(&[]);
+    fixture
+        .state
+        .handle_bluetooth_event(BluetoothEvent::Reconnecting { attempt: Some(2) });
+    let frame = fixture.render(Tab::Queue);
+    assert_snapshot("bluetooth_reconnecting", &frame);
+}
+
+#[test]
+fn pairing_prompt() {
+    let mut fixture = Fixture::new(&[]);
+    found_devices(&mut fixture);
+    let (reply, _answers) = tokio::sync::mpsc::channel(1);
+    fixture
+        .state
+        .handle_bluetooth_event(BluetoothEvent::PairingPrompt {
+            addr: MacAddr6::from([0, 1, 2, 3, 4, 8]),
+            pairing: Pairing::Confirm("012345".to_string()),
+            reply: Some(PairingReply::new(reply)),
+        });
+    let frame = fixture.render(Tab::Files);
+    assert_snapshot("pairing_prompt", &frame);
+}
+
+#[tokio::test]
+async fn pairing_prompt_answers() {
+    let mut fixture = Fixture::new(&["01 Intro.mp3"]);
+    found_devices(&mut fixture);
+    let without_prompt = fixture.render(Tab::Files);
+
+    for (button, accept) in [(Button::B1, true), (Button::B2, false)] {
+        let (reply, mut answers) = mpsc::channel(1);
+        fixture
+            .state
+            .handle_bluetooth_event(BluetoothEvent::PairingPrompt {
+                addr: MacAddr6::from([0, 1, 2, 3, 4, 8]),
+                pairing: Pairing::Confirm("012345".to_string()),
+                reply: Some(PairingReply::new(reply)),
+            });
+        assert_ne!(fixture.render(Tab::Files), without_prompt);
+        fixture.press(&[button], joystick::State::Neutral).await;
+        assert_eq!(answers.try_recv().unwrap(), accept);
+        assert_eq!(fixture.render(Tab::Files), without_prompt);
+    }
+
+    // A passkey to type on the device has nothing to answer, it's just hidden
+    fixture
+        .state
+        .handle_bluetooth_event(BluetoothEvent::PairingPrompt {
+            addr: MacAddr6::from([0, 1, 2, 3, 4, 8]),
+            pairing: Pairing::Display("012345".to_string()),
+            reply: None,
+        });
+    assert_ne!(fixture.render(Tab::Files), without_prompt);
+    fixture.press(&[Button::B1], joystick::State::Neutral).await;
+    assert_eq!(fixture.render(Tab::Files), without_prompt);
+    // The press went to the prompt and not to the tab behind it
+    assert!(fixture.mpv_requests().is_empty());
+    fixture.press(&[Button::B1], joystick::State::Neutral).await;
+    assert!(matches!(
+        fixture.mpv_requests()[..],
+        [MpvRequest::Play(MediaSource::File(_))]
+    ));
+}
+
+#[test]
+fn player_tab() {
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.handle_mpv_event(MpvEvent::StatusUpdate {
+        is_playing: true,
+        position: 83,
+        duration: 245,
+        filename: Some("song.mp3".to_string()),
+        path: None,
+        chapters: Vec::new(),
+        chapter: None,
+        speed: 1.0,
+        stream_title: None,
+    });
+    fixture
+        .state
+        .handle_audio_event(AudioEvent::Output("Speaker".to_string()));
+    let frame = fixture.render(Tab::Player);
+    assert_snapshot("player_tab", &frame);
+}
+
+#[test]
+fn player_tab_modes() {
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.handle_mpv_event(MpvEvent::ModesUpdate {
+        shuffle: true,
+        repeat: Repeat::One,
+    });
+    let frame = fixture.render(Tab::Player);
+    assert_snapshot("player_tab_modes", &frame);
+}
+
+#[test]
+fn player_tab_chapters() {
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.handle_mpv_event(MpvEvent::StatusUpdate {
+        is_playing: true,
+        position: 4523,
+        duration: 36000,
+        filename: Some("book.m4a".to_string()),
+        path: None,
+        chapters: vec![
+            Chapter {
+                title: "Opening".to_string(),
+                start: 0,
+            },
+            Chapter {
+                title: "The Long Road Home".to_string(),
+                start: 4200,
+            },
+        ],
+        chapter: Some(1),
+        speed: 1.25,
+        stream_title: None,
+    });
+    let frame = fixture.render(Tab::Player);
+    assert_snapshot("player_tab_chapters", &frame);
+}
+
+#[test]
+fn player_tab_sleep() {
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.handle_mpv_event(MpvEvent::StatusUpdate {
+        is_playing: true,
+        position: 83,
+        duration: 245,
+        filename: Some("song.mp3".to_string()),
+        path: None,
+        chapters: Vec::new(),
+        chapter: None,
+        speed: 1.0,
+        stream_title: None,
+    });
+    fixture.state.set_sleep_timer(Some(SleepMode::EndOfTrack));
+    let frame = fixture.render(Tab::Player);
+    assert_snapshot("player_tab_sleep", &frame);
+}
+
+#[test]
+fn player_tab_resume() {
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.handle_mpv_event(MpvEvent::ResumeAvailable {
+        file: PathBuf::from("/books/Chapter 12.m4a"),
+        position: 4523,
+    });
+    assert_eq!(fixture.state.open_tab, Tab::Player);
+    let frame = fixture.render(Tab::Player);
+    assert_snapshot("player_tab_resume", &frame);
+}
+
+#[test]
+fn player_tab_idle() {
+    let mut fixture = Fixture::new(&[]);
+    let frame = fixture.render(Tab::Player);
+    assert_snapshot("player_tab_idle", &frame);
+}
+
+#[test]
+fn queue_tab() {
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.handle_mpv_event(MpvEvent::QueueUpdate {
+        files: vec![
+            PathBuf::from("/music/01 Intro.mp3"),
+            PathBuf::from("/music/02 Second.mp3"),
+            PathBuf::from("/music/03 A track name that is too long to fit.flac"),
+        ],
+        current: Some(1),
+    });
+    let frame = fixture.render(Tab::Queue);
+    assert_snapshot("queue_tab", &frame);
+}
+
+#[test]
+fn queue_tab_empty() {
+    let mut fixture = Fixture::new(&[]);
+    let frame = fixture.render(Tab::Queue);
+    assert_snapshot("queue_tab_empty", &frame);
+}
+
+#[tokio::test]
+async fn queue_tab_input() {
+    use joystick::State::{Click, Down, Neutral, Up};
+
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.handle_mpv_event(MpvEvent::QueueUpdate {
+        files: vec![
+            PathBuf::from("/music/01 Intro.mp3"),
+            PathBuf::from("/music/02 Second.mp3"),
+        ],
+        current: Some(0),
+    });
+    fixture.state.open_tab = Tab::Queue;
+
+    // A click plays the selected entry, B1 removes it
+    fixture.press(&[], Down).await;
+    fixture.press(&[], Click).await;
+    assert!(matches!(
+        fixture.mpv_requests()[..],
+        [MpvRequest::PlayFromQueue(1)]
+    ));
+    fixture.press(&[Button::B1], Neutral).await;
+    assert!(matches!(
+        fixture.mpv_requests()[..],
+        [MpvRequest::RemoveFromQueue(1)]
+    ));
+    fixture.press(&[Button::B2], Up).await;
+    assert!(matches!(
+        fixture.mpv_requests()[..],
+        [MpvRequest::MoveInQueue { from: 1, to: 0 }]
+    ));
+
+    // The row after the queue saves it
+    fixture.press(&[], Down).await;
+    fixture.press(&[], Down).await;
+    fixture.press(&[], Click).await;
+    assert!(fixture.mpv_requests().is_empty());
+    let saved: Vec<_> = fs::read_dir(fixture._audio_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert!(
+        matches!(&saved[..], [name] if name.starts_with("Queue ") && name.ends_with(".m3u")),
+        "{:?}",
+        saved
+    );
+
+    // The last one clears it, the cursor can't go past it
+    fixture.press(&[], Down).await;
+    fixture.press(&[], Click).await;
+    fixture.press(&[Button::B1], Down).await;
+    let requests = fixture.mpv_requests();
+    assert!(
+        matches!(
+            requests[..],
+            [MpvRequest::ClearQueue, MpvRequest::ClearQueue]
+        ),
+        "{:?}",
+        requests
+    );
+}
+
+#[tokio::test]
+async fn files_tab_play_next() {
+    let mut fixture = Fixture::new(&["01 Intro.mp3"]);
+    fixture.state.open_tab = Tab::Files;
+    fixture.press(&[], joystick::State::Click).await;
+    let requests = fixture.mpv_requests();
+    assert!(
+        matches!(&requests[..], [MpvRequest::PlayNext(path)] if path.ends_with("01 Intro.mp3")),
+        "{:?}",
+        requests
+    );
+}
+
+#[test]
+fn clock_tab() {
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.now = NaiveDate::from_ymd_opt(2026, 10, 17)
+        .unwrap()
+        .and_hms_opt(6, 42, 0)
+        .unwrap();
+    fixture.state.alarms.add(Alarm::default());
+    fixture.state.alarms.add(Alarm {
+        hour: 9,
+        minute: 30,
+        days: Days::WEEKEND,
+        enabled: false,
+        ..Alarm::default()
+    });
+    let frame = fixture.render(Tab::Clock);
+    assert_snapshot("clock_tab", &frame);
+}
+
+fn stations() -> Vec<Station> {
+    ["Jazz FM", "Classic Rock Radio", "News 24"]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| Station {
+            name: name.to_string(),
+            url: format!("http://radio.example/{}", i),
+        })
+        .collect()
+}
+
+#[test]
+fn files_tab_radio() {
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.stations = stations();
+    fixture.state.views.open(View::Radio, &Library::default());
+    fixture.state.handle_mpv_event(MpvEvent::StatusUpdate {
+        is_playing: true,
+        position: 0,
+        duration: 0,
+        filename: Some("1".to_string()),
+        path: Some(PathBuf::from("http://radio.example/1")),
+        chapters: Vec::new(),
+        chapter: None,
+        speed: 1.0,
+        stream_title: None,
+    });
+    let frame = fixture.render(Tab::Files);
+    assert_snapshot("files_tab_radio", &frame);
+}
+
+#[test]
+fn player_tab_radio() {
+    let mut fixture = Fixture::new(&[]);
+    fixture.state.stations = stations();
+    fixture.state.handle_mpv_event(MpvEvent::StatusUpdate {
+        is_playing: true,
+        position: 1234,
+        duration: 0,
+        filename: Some("0".to_string()),
+        path: Some(PathBuf::from("http://radio.example/0")),
+        chapters: Vec::new(),
+        chapter: None,
+        speed: 1.0,
+        stream_title: Some("Miles Davis - So What".to_string()),
+    });
+    let frame = fixture.render(Tab::Player);
+    assert_snapshot("player_tab_radio", &frame);
+}
+
+/// Every tab with something on it fits the smallest display the config
+/// accepts
+#[test]
+fn every_tab_at_minimum_size() {
+    assert!(Fixture::sized(&[], 79, 64).is_err());
+    assert!(Fixture::sized(&[], 80, 56).is_err());
+    let mut fixture = Fixture::sized(&["Some Artist/01 A rather long title.mp3"], 80, 64).unwrap();
+    found_devices(&mut fixture);
+    fixture.state.handle_mpv_event(MpvEvent::StatusUpdate {
+        is_playing: true,
+        position: 4523,
+        duration: 36000,
+        filename: Some("book.m4a".to_string()),
+        path: None,
+        chapters: vec![Chapter {
+            title: "The Long Road Home".to_string(),
+            start: 0,
+        }],
+        chapter: Some(0),
+        speed: 1.25,
+        stream_title: None,
+    });
+    fixture.state.handle_mpv_event(MpvEvent::QueueUpdate {
+        files: vec![PathBuf::from("/music/01 Intro.mp3"); 8],
+        current: Some(1),
+    });
+    fixture
+        .state
+        .handle_audio_event(AudioEvent::Output("Speaker".to_string()));
+    fixture.state.set_sleep_timer(Some(SleepMode::EndOfTrack));
+    fixture.state.alarms.add(Alarm::default());
+    fixture.state.stations = stations();
+
+    let tabs = [
+        Tab::Files,
+        Tab::Network,
+        Tab::Bluetooth,
+        Tab::Player,
+        Tab::Queue,
+        Tab::Clock,
+    ];
+    for tab in tabs {
+        let frame = fixture.render(tab);
+        assert_eq!((frame.width, frame.height), (80, 64));
+        assert!(frame.pages.iter().any(|&b| b != 0), "{:?} is blank", tab);
+    }
+
+    let (reply, _answers) = tokio::sync::mpsc::channel(1);
+    fixture
+        .state
+        .handle_bluetooth_event(BluetoothEvent::PairingPrompt {
+            addr: MacAddr6::from([0, 1, 2, 3, 4, 8]),
+            pairing: Pairing::Confirm("012345".to_string()),
+            reply: Some(PairingReply::new(reply)),
+        });
+    fixture.render(Tab::Bluetooth);
+}
@@ -4,7 +4,7 @@ use std::{
     time::Duration,
 };
 
-use reqwest::{Response, StatusCode};
+use reqwest::Response;
 
 use crate::{
     server::{ExecuteRequest, FileUploadRequest},
@@ -1,11 +1,6 @@
 use std::path::PathBuf;
 
-use axum::{
-    http::StatusCode,
-    routing::{get, post},
-    Json, Router,
-};
-use clap::{Args, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 use client::client_main;
 use server::server_main;
 use strum::EnumString;
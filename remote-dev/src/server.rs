@@ -1,11 +1,8 @@
-use std::{
-    collections::HashMap, fs, io::Write, os::unix::fs::PermissionsExt as _, path::PathBuf,
-    process::Command,
-};
+use std::{fs, io::Write, os::unix::fs::PermissionsExt as _, process::Command};
 
 use axum::{
     extract::{DefaultBodyLimit, Query},
-    response::{IntoResponse, Response, Result},
+    response::{IntoResponse, Result},
     routing::{get, post},
     Json, Router,
 };
@@ -57,12 +54,12 @@ async fn execute_binary(Json(file_req): Json<ExecuteRequest>) -> Result<impl Int
     let child = Command::new(file_req.name)
         .args(file_req.arguments)
         .spawn()
-        .map_err(|e| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     println!("Spawned child process with pid: {}", child.id());
     let output = child
         .wait_with_output()
-        .map_err(|e| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     if output.status.success() {
         println!(